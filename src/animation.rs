@@ -0,0 +1,330 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Cursor};
+use std::ops::{Index, IndexMut};
+use std::path::Path;
+
+use crate::util::{DifCodeError, DifCodeResult, EncodingContainer};
+use crate::difference_encoder::multi_bit::encode_into_container_with_options;
+use crate::difference_encoder::options::EncodingOptions;
+
+//Multi frame containers, every frame of the animation is part of the container.
+//The frames are written back with their original region, timing and disposal, so the encoded file still animates like the original.
+
+/// Whether the buffer starts like a gif (87a or 89a).
+pub fn is_gif(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"GIF87a") || buffer.starts_with(b"GIF89a")
+}
+/// Whether the buffer is a png with an animation control chunk (i.e. an apng).
+pub fn is_animated_png(buffer: &[u8]) -> bool {
+    png::Decoder::new(Cursor::new(buffer)).read_info()
+        .is_ok_and(|reader| reader.info().animation_control.is_some())
+}
+
+
+
+/// A gif (animated or not) as an encoding container of its color palettes: the global palette (if any), followed by the local palette of each frame that has one.
+///    The pixels of a gif are indices into a palette, changing them (or re-quantizing changed rgb values) would be visible or lossy,
+///    changing the colors of the palettes keeps every index and is restored exactly when the gif is loaded again.
+/// The capacity therefore grows with the number of frames with a local palette, not with the number of pixels.
+#[derive(Clone)]
+pub struct DifCodeGif {
+    width: u16,
+    height: u16,
+    repeat: gif::Repeat,
+    has_global_palette: bool,
+    //the global palette first (if any), then the local palettes in frame order
+    palettes: Vec<Vec<u8>>,
+    //the frames without their palette, index into palettes of the local palette of each frame
+    frames: Vec<(gif::Frame<'static>, Option<usize>)>,
+    //the container index at which each palette starts, followed by the total length
+    palette_offsets: Vec<usize>,
+}
+
+impl DifCodeGif {
+    pub fn width(&self) -> u32 { self.width as u32 }
+    pub fn height(&self) -> u32 { self.height as u32 }
+    pub fn num_frames(&self) -> usize { self.frames.len() }
+    /// The delay (in 10 ms units) and disposal method of each frame.
+    pub fn frame_timings(&self) -> Vec<(u16, gif::DisposalMethod)> {
+        self.frames.iter().map(|(frame, _)| (frame.delay, frame.dispose)).collect()
+    }
+
+    pub fn open(path: &str) -> DifCodeResult<DifCodeGif> {
+        DifCodeGif::from_memory(&fs::read(path)?)
+    }
+    pub fn save(&self, path: &str) -> DifCodeResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> DifCodeResult<()> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn from_memory(buffer: &[u8]) -> DifCodeResult<DifCodeGif> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(Cursor::new(buffer)).map_err(|_| DifCodeError::InvalidAnimation("not a decodable gif"))?;
+
+        let mut palettes = Vec::new();
+        let has_global_palette = decoder.global_palette().is_some();
+        if let Some(global_palette) = decoder.global_palette() {
+            palettes.push(global_palette.to_vec());
+        }
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(|_| DifCodeError::InvalidAnimation("not a decodable gif"))? {
+            let mut frame = frame.clone();
+            let palette_index = frame.palette.take().map(|palette| {
+                palettes.push(palette);
+                palettes.len() - 1
+            });
+            frames.push((frame, palette_index));
+        }
+        if frames.is_empty() {
+            return Err(DifCodeError::InvalidAnimation("gif without frames"))
+        }
+
+        let mut palette_offsets = vec![0];
+        for palette in &palettes {
+            palette_offsets.push(palette_offsets.last().unwrap() + palette.len());
+        }
+        Ok(DifCodeGif { width: decoder.width(), height: decoder.height(), repeat: decoder.repeat(), has_global_palette, palettes, frames, palette_offsets })
+    }
+
+    pub fn to_bytes(&self) -> DifCodeResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        {
+            let global_palette = if self.has_global_palette { &self.palettes[0][..] } else { &[] };
+            let mut encoder = gif::Encoder::new(&mut bytes, self.width, self.height, global_palette).map_err(gif_encoding_error)?;
+            encoder.set_repeat(self.repeat).map_err(gif_encoding_error)?;
+            for (frame, palette_index) in &self.frames {
+                let frame = gif::Frame {
+                    palette: palette_index.map(|index| self.palettes[index].clone()),
+                    buffer: Cow::Borrowed(&frame.buffer),
+                    //the decoder de-interlaces, the encoder does not interlace again
+                    interlaced: false,
+                    ..*frame
+                };
+                encoder.write_frame(&frame).map_err(gif_encoding_error)?;
+            }
+        }
+        //the encoder always writes a global color table (of padding, if none is given), which would become part of the reloaded container
+        if !self.has_global_palette {
+            let global_palette_length = 3 * (2 << (bytes[10] & 0b111));
+            bytes[10] = 0;
+            bytes.drain(13..13 + global_palette_length);
+        }
+        Ok(bytes)
+    }
+
+    fn container_index_to_palette_index(&self, index: usize) -> (usize, usize) {
+        let palette = self.palette_offsets.partition_point(|offset| *offset <= index) - 1;
+        (palette, index - self.palette_offsets[palette])
+    }
+}
+
+fn gif_encoding_error(err: gif::EncodingError) -> DifCodeError {
+    DifCodeError::IO(io::Error::other(err))
+}
+
+impl std::fmt::Debug for DifCodeGif {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DifCodeGif({}, {}, {} frames, {} palettes)", self.width, self.height, self.frames.len(), self.palettes.len())
+    }
+}
+impl std::fmt::Display for DifCodeGif {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Gif({}, {}, {} frames)", self.width, self.height, self.frames.len())
+    }
+}
+impl PartialEq<DifCodeGif> for DifCodeGif {
+    fn eq(&self, other: &DifCodeGif) -> bool {
+        self.width == other.width && self.height == other.height && self.palettes == other.palettes &&
+            self.frames.len() == other.frames.len() && self.frames.iter().zip(&other.frames).all(|((a, _), (b, _))| a.buffer == b.buffer)
+    }
+}
+impl IndexMut<usize> for DifCodeGif {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        let (palette, index) = self.container_index_to_palette_index(index);
+        &mut self.palettes[palette][index]
+    }
+}
+impl Index<usize> for DifCodeGif {
+    type Output = u8;
+    fn index(&self, index: usize) -> &u8 {
+        let (palette, index) = self.container_index_to_palette_index(index);
+        &self.palettes[palette][index]
+    }
+}
+impl EncodingContainer for DifCodeGif {
+    fn len(&self) -> usize {
+        *self.palette_offsets.last().unwrap()
+    }
+}
+
+/// The encoded gif (see encode_into_container) only has changed palette colors, frames and timing are unchanged.
+pub fn encode_into_gif_into_path(message_bytes: &[u8], original: &DifCodeGif, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_gif_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_gif_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeGif, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_container_with_options(message_bytes, options, original, allowed_changes_map)?.save(path)
+}
+
+
+
+/// An animated png as an encoding container of the color values of all its frames, frame after frame
+///    (each frame addressed like an image of its region: width x height x color channels, alpha is never part of the container).
+/// Every frame keeps its region, delay, dispose and blend operation. A default image that is not part of the animation is kept as well (and is the first frame of the container).
+/// Only 8 bit per channel apngs are supported (palette and low bit depth apngs are expanded to 8 bit and written as such).
+#[derive(Clone)]
+pub struct DifCodeApng {
+    width: u32,
+    height: u32,
+    color_type: png::ColorType,
+    num_plays: u32,
+    frames: Vec<ApngFrame>,
+    //the container index at which each frame starts, followed by the total length
+    frame_offsets: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct ApngFrame {
+    //None for a default image that is not part of the animation
+    control: Option<png::FrameControl>,
+    data: Vec<u8>,
+}
+
+impl DifCodeApng {
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn num_frames(&self) -> usize { self.frames.len() }
+    /// The frame control (region, delay, dispose and blend operation) of each frame, None for a default image that is not part of the animation.
+    pub fn frame_controls(&self) -> Vec<Option<png::FrameControl>> {
+        self.frames.iter().map(|frame| frame.control).collect()
+    }
+
+    pub fn open(path: &str) -> DifCodeResult<DifCodeApng> {
+        DifCodeApng::from_memory(&fs::read(path)?)
+    }
+    pub fn save(&self, path: &str) -> DifCodeResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> DifCodeResult<()> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn from_memory(buffer: &[u8]) -> DifCodeResult<DifCodeApng> {
+        let mut decoder = png::Decoder::new(Cursor::new(buffer));
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(|_| DifCodeError::InvalidAnimation("not a decodable png"))?;
+        let animation_control = reader.info().animation_control.ok_or(DifCodeError::InvalidAnimation("png is not animated"))?;
+        let (color_type, bit_depth) = reader.output_color_type();
+        if bit_depth != png::BitDepth::Eight {
+            return Err(DifCodeError::InvalidAnimation("only 8 bit apngs are supported"))
+        }
+        //without a frame control before the image data, the default image is not part of the animation
+        let has_separate_default_image = reader.info().frame_control.is_none();
+
+        let num_frames = animation_control.num_frames as usize + has_separate_default_image as usize;
+        let mut frames = Vec::with_capacity(num_frames);
+        let mut buf = vec![0u8; reader.output_buffer_size()];
+        for i in 0..num_frames {
+            let output_info = reader.next_frame(&mut buf).map_err(|_| DifCodeError::InvalidAnimation("not a decodable apng"))?;
+            let control = if i == 0 && has_separate_default_image { None } else { reader.info().frame_control };
+            frames.push(ApngFrame { control, data: buf[..output_info.buffer_size()].to_vec() });
+        }
+
+        let color_channels = color_type.samples() - has_alpha(color_type) as usize;
+        let mut frame_offsets = vec![0];
+        for frame in &frames {
+            frame_offsets.push(frame_offsets.last().unwrap() + frame.data.len() / color_type.samples() * color_channels);
+        }
+        Ok(DifCodeApng { width: reader.info().width, height: reader.info().height, color_type, num_plays: animation_control.num_plays, frames, frame_offsets })
+    }
+
+    pub fn to_bytes(&self) -> DifCodeResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+            encoder.set_color(self.color_type);
+            encoder.set_depth(png::BitDepth::Eight);
+            let num_animation_frames = self.frames.iter().filter(|frame| frame.control.is_some()).count();
+            encoder.set_animated(num_animation_frames as u32, self.num_plays).map_err(png_encoding_error)?;
+            encoder.set_sep_def_img(self.frames[0].control.is_none()).map_err(png_encoding_error)?;
+            let mut writer = encoder.write_header().map_err(png_encoding_error)?;
+            for frame in &self.frames {
+                if let Some(control) = frame.control {
+                    //the new dimension has to fit at the old position, so the position is only set afterwards
+                    writer.reset_frame_position().map_err(png_encoding_error)?;
+                    writer.set_frame_dimension(control.width, control.height).map_err(png_encoding_error)?;
+                    writer.set_frame_position(control.x_offset, control.y_offset).map_err(png_encoding_error)?;
+                    writer.set_frame_delay(control.delay_num, control.delay_den).map_err(png_encoding_error)?;
+                    writer.set_dispose_op(control.dispose_op).map_err(png_encoding_error)?;
+                    writer.set_blend_op(control.blend_op).map_err(png_encoding_error)?;
+                }
+                writer.write_image_data(&frame.data).map_err(png_encoding_error)?;
+            }
+            writer.finish().map_err(png_encoding_error)?;
+        }
+        Ok(bytes)
+    }
+
+    fn container_index_to_frame_index(&self, index: usize) -> (usize, usize) {
+        let frame = self.frame_offsets.partition_point(|offset| *offset <= index) - 1;
+        let index_in_frame = index - self.frame_offsets[frame];
+        let color_channels = self.color_type.samples() - has_alpha(self.color_type) as usize;
+        (frame, index_in_frame / color_channels * self.color_type.samples() + index_in_frame % color_channels)
+    }
+}
+
+fn has_alpha(color_type: png::ColorType) -> bool {
+    matches!(color_type, png::ColorType::GrayscaleAlpha | png::ColorType::Rgba)
+}
+
+fn png_encoding_error(err: png::EncodingError) -> DifCodeError {
+    DifCodeError::IO(io::Error::from(err))
+}
+
+impl std::fmt::Debug for DifCodeApng {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DifCodeApng({}, {}, {:?}, {} frames)", self.width, self.height, self.color_type, self.frames.len())
+    }
+}
+impl std::fmt::Display for DifCodeApng {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Animated Png({}, {}, {} frames)", self.width, self.height, self.frames.len())
+    }
+}
+impl PartialEq<DifCodeApng> for DifCodeApng {
+    fn eq(&self, other: &DifCodeApng) -> bool {
+        self.width == other.width && self.height == other.height && self.color_type == other.color_type &&
+            self.frames.len() == other.frames.len() && self.frames.iter().zip(&other.frames).all(|(a, b)| a.data == b.data)
+    }
+}
+impl IndexMut<usize> for DifCodeApng {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        let (frame, index) = self.container_index_to_frame_index(index);
+        &mut self.frames[frame].data[index]
+    }
+}
+impl Index<usize> for DifCodeApng {
+    type Output = u8;
+    fn index(&self, index: usize) -> &u8 {
+        let (frame, index) = self.container_index_to_frame_index(index);
+        &self.frames[frame].data[index]
+    }
+}
+impl EncodingContainer for DifCodeApng {
+    fn len(&self) -> usize {
+        *self.frame_offsets.last().unwrap()
+    }
+}
+
+/// The encoded apng (see encode_into_container) only has changed color values in its frames, frame regions and timing are unchanged.
+pub fn encode_into_apng_into_path(message_bytes: &[u8], original: &DifCodeApng, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_apng_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_apng_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeApng, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_container_with_options(message_bytes, options, original, allowed_changes_map)?.save(path)
+}
@@ -0,0 +1,298 @@
+use std::fs;
+use std::ops::{Index, IndexMut, Range};
+use std::path::Path;
+
+use crate::util::{DifCodeError, DifCodeResult, EncodingContainer, Sample};
+use crate::difference_encoder::multi_bit::encode_into_container_with_options;
+use crate::difference_encoder::options::EncodingOptions;
+use crate::difference_encoder::max_change_map_creator::{create_allowed_changes_map_for_message, write_minimal_evenly_random_allowed_changes_map};
+use crate::rand::RngCore;
+
+//Riff wave files with integer pcm samples.
+//Signed samples (16 and 24 bit) are stored in offset binary (i.e. with the sign bit flipped), so that silence is at the center of the sample range
+//    and the order of the values is kept, just like the unsigned 8 bit samples of wav.
+
+const PCM_FORMAT: u16 = 1;
+const EXTENSIBLE_FORMAT: u16 = 0xFFFE;
+
+/// Whether the buffer starts like a riff wave file.
+pub fn is_wav(buffer: &[u8]) -> bool {
+    buffer.len() >= 12 && &buffer[0..4] == b"RIFF" && &buffer[8..12] == b"WAVE"
+}
+/// The bits per sample of a pcm wav, i.e. which DifCodeWav it can be opened as (8 -> u8, 16 -> u16, 24 -> u32).
+pub fn get_wav_bits_per_sample(buffer: &[u8]) -> DifCodeResult<u16> {
+    Ok(parse_format(&read_chunks(buffer)?)?.bits_per_sample)
+}
+
+/// The byte range of the samples (the payload of the data chunk) within a riff wave file.
+pub(crate) fn get_wav_data_range(buffer: &[u8]) -> DifCodeResult<Range<usize>> {
+    let (_, payload) = read_chunks(buffer)?.into_iter().find(|(id, _)| id == b"data").ok_or(DifCodeError::InvalidAudio("missing data chunk"))?;
+    //the payload is a sub slice of the buffer
+    let start = payload.as_ptr() as usize - buffer.as_ptr() as usize;
+    Ok(start..start + payload.len())
+}
+
+/// A pcm wav as an encoding container of its samples, interleaved as stored (frame after frame, every channel of a frame).
+/// The sample type has to match the bits per sample of the file: u8 for 8 bit, u16 for 16 bit and u32 for 24 bit.
+/// All other chunks (e.g. metadata) are written back unchanged.
+#[derive(Clone)]
+pub struct DifCodeWav<T: Sample = u16> {
+    chunks: Vec<Chunk>,
+    num_channels: u16,
+    sample_rate: u32,
+    samples: Vec<T>,
+}
+
+#[derive(Clone)]
+enum Chunk {
+    //kept as is (format, metadata, ...)
+    Raw([u8; 4], Vec<u8>),
+    //written from the samples
+    Data,
+}
+
+struct Format {
+    num_channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+}
+
+impl<T: Sample> DifCodeWav<T> {
+    pub fn num_channels(&self) -> usize { self.num_channels as usize }
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+    pub fn num_frames(&self) -> usize { self.samples.len() / self.num_channels() }
+    pub fn bits_per_sample(&self) -> u8 { T::MAX_NUM_BITS }
+
+    pub fn index_to_frame_and_channel(&self, index: usize) -> (usize, usize) {
+        (index / self.num_channels(), index % self.num_channels())
+    }
+    pub fn get_sample(&self, frame: usize, channel: usize) -> T {
+        self.samples[frame * self.num_channels() + channel]
+    }
+
+    pub fn generate_integral_amplitude(&self) -> IntegralAmplitude {
+        IntegralAmplitude::generate(self)
+    }
+
+    pub fn open(path: &str) -> DifCodeResult<DifCodeWav<T>> {
+        DifCodeWav::from_memory(&fs::read(path)?)
+    }
+    pub fn save(&self, path: &str) -> DifCodeResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> DifCodeResult<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn from_memory(buffer: &[u8]) -> DifCodeResult<DifCodeWav<T>> {
+        let raw_chunks = read_chunks(buffer)?;
+        let format = parse_format(&raw_chunks)?;
+        if format.bits_per_sample != T::MAX_NUM_BITS as u16 {
+            return Err(DifCodeError::InvalidAudio("the bits per sample do not match the sample type of the container"))
+        }
+
+        let bytes_per_sample = bytes_per_sample::<T>();
+        let mut chunks = Vec::with_capacity(raw_chunks.len());
+        let mut samples = None;
+        for (id, payload) in raw_chunks {
+            if &id == b"data" && samples.is_none() {
+                if payload.len() % (bytes_per_sample * format.num_channels as usize) != 0 {
+                    return Err(DifCodeError::InvalidAudio("the data chunk does not contain whole frames"))
+                }
+                samples = Some(payload.chunks(bytes_per_sample).map(read_sample::<T>).collect());
+                chunks.push(Chunk::Data);
+            } else {
+                chunks.push(Chunk::Raw(id, payload.to_vec()));
+            }
+        }
+        let samples = samples.ok_or(DifCodeError::InvalidAudio("missing data chunk"))?;
+        Ok(DifCodeWav { chunks, num_channels: format.num_channels, sample_rate: format.sample_rate, samples })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for chunk in &self.chunks {
+            match chunk {
+                Chunk::Raw(id, payload) => write_chunk(&mut body, id, payload),
+                Chunk::Data => {
+                    let mut payload = Vec::with_capacity(self.samples.len() * bytes_per_sample::<T>());
+                    for sample in &self.samples {
+                        write_sample(*sample, &mut payload);
+                    }
+                    write_chunk(&mut body, b"data", &payload);
+                }
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(8 + body.len());
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+}
+
+fn read_chunks(buffer: &[u8]) -> DifCodeResult<Vec<([u8; 4], &[u8])>> {
+    if !is_wav(buffer) {
+        return Err(DifCodeError::InvalidAudio("not a riff wave file"))
+    }
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= buffer.len() {
+        let id = [buffer[pos], buffer[pos + 1], buffer[pos + 2], buffer[pos + 3]];
+        let length = u32::from_le_bytes([buffer[pos + 4], buffer[pos + 5], buffer[pos + 6], buffer[pos + 7]]) as usize;
+        if pos + 8 + length > buffer.len() {
+            return Err(DifCodeError::InvalidAudio("truncated chunk"))
+        }
+        chunks.push((id, &buffer[pos + 8..pos + 8 + length]));
+        //chunks are padded to an even length
+        pos += 8 + length + length % 2;
+    }
+    Ok(chunks)
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], payload: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        bytes.push(0);
+    }
+}
+
+fn parse_format(chunks: &[([u8; 4], &[u8])]) -> DifCodeResult<Format> {
+    let (_, fmt) = chunks.iter().find(|(id, _)| id == b"fmt ").ok_or(DifCodeError::InvalidAudio("missing format chunk"))?;
+    if fmt.len() < 16 {
+        return Err(DifCodeError::InvalidAudio("truncated format chunk"))
+    }
+    let read_u16 = |pos: usize| u16::from_le_bytes([fmt[pos], fmt[pos + 1]]);
+    let format_tag = read_u16(0);
+    //the extensible format names the actual format in the first two bytes of its sub format guid
+    let is_pcm = format_tag == PCM_FORMAT || (format_tag == EXTENSIBLE_FORMAT && fmt.len() >= 40 && read_u16(24) == PCM_FORMAT);
+    if !is_pcm {
+        return Err(DifCodeError::InvalidAudio("only integer pcm wavs are supported"))
+    }
+    let format = Format {
+        num_channels: read_u16(2),
+        sample_rate: u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]),
+        bits_per_sample: read_u16(14),
+    };
+    if ![8, 16, 24].contains(&format.bits_per_sample) {
+        return Err(DifCodeError::InvalidAudio("only 8, 16 and 24 bit pcm wavs are supported"))
+    }
+    if format.num_channels == 0 || read_u16(12) != format.num_channels * format.bits_per_sample / 8 {
+        return Err(DifCodeError::InvalidAudio("invalid number of channels or block align"))
+    }
+    Ok(format)
+}
+
+fn bytes_per_sample<T: Sample>() -> usize {
+    T::MAX_NUM_BITS as usize / 8
+}
+//8 bit samples are unsigned, all larger ones signed
+fn sign_bit<T: Sample>() -> u32 {
+    if T::MAX_NUM_BITS > 8 { 1 << (T::MAX_NUM_BITS - 1) } else { 0 }
+}
+fn read_sample<T: Sample>(bytes: &[u8]) -> T {
+    let raw = bytes.iter().rev().fold(0u32, |acc, byte| acc << 8 | *byte as u32);
+    T::from_u32(raw ^ sign_bit::<T>())
+}
+fn write_sample<T: Sample>(sample: T, bytes: &mut Vec<u8>) {
+    let raw = sample.to_u32() ^ sign_bit::<T>();
+    for i in 0..bytes_per_sample::<T>() {
+        bytes.push((raw >> (8 * i)) as u8);
+    }
+}
+
+impl<T: Sample> std::fmt::Debug for DifCodeWav<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DifCodeWav({} bit, {} channels, {} Hz, {} frames, {} chunks)", T::MAX_NUM_BITS, self.num_channels, self.sample_rate, self.num_frames(), self.chunks.len())
+    }
+}
+impl<T: Sample> std::fmt::Display for DifCodeWav<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Wav({} bit, {} channels, {} Hz, {} frames)", T::MAX_NUM_BITS, self.num_channels, self.sample_rate, self.num_frames())
+    }
+}
+impl<T: Sample> PartialEq<DifCodeWav<T>> for DifCodeWav<T> {
+    fn eq(&self, other: &DifCodeWav<T>) -> bool {
+        self.num_channels == other.num_channels && self.sample_rate == other.sample_rate && self.samples == other.samples
+    }
+}
+impl<T: Sample> IndexMut<usize> for DifCodeWav<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.samples[index]
+    }
+}
+impl<T: Sample> Index<usize> for DifCodeWav<T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &T {
+        &self.samples[index]
+    }
+}
+impl<T: Sample> EncodingContainer<T> for DifCodeWav<T> {
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+/// The encoded wav (see encode_into_container) only has changed samples, all other chunks are unchanged.
+pub fn encode_into_wav_into_path<T: Sample>(message_bytes: &[u8], original: &DifCodeWav<T>, allowed_changes_map: &dyn EncodingContainer<T>, path: &str) -> DifCodeResult<()> {
+    encode_into_wav_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_wav_into_path_with_options<T: Sample>(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeWav<T>, allowed_changes_map: &dyn EncodingContainer<T>, path: &str) -> DifCodeResult<()> {
+    encode_into_container_with_options(message_bytes, options, original, allowed_changes_map)?.save(path)
+}
+
+//each sample may change by a fraction of the average amplitude around it (louder passages mask larger changes), so silence is never changed.
+//    The change is directed towards silence, so it cannot clip.
+const AMPLITUDE_RADIUS_IN_FRAMES: usize = 256;
+const AMPLITUDE_TO_MAX_DIFFERENCE_DIVISOR: u32 = 8;
+pub fn create_minimal_evenly_random_max_amplitude_allowed_changes_map<T: Sample>(message: &[u8], original: &DifCodeWav<T>) -> DifCodeResult<Vec<T>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_amplitude_allowed_changes_map(length, original, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_amplitude_allowed_changes_map<T: Sample>(message_length_in_bits: usize, original: &DifCodeWav<T>, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    let integral_amplitude = original.generate_integral_amplitude();
+    let silence = T::MAX.to_u32().div_ceil(2);
+    let change_constraint_calculator = move |index, ov: T| {
+        let (frame, channel) = original.index_to_frame_and_channel(index);
+        let average_amplitude = integral_amplitude.average_in_radius(frame, channel, AMPLITUDE_RADIUS_IN_FRAMES);
+        (T::from_u32(average_amplitude / AMPLITUDE_TO_MAX_DIFFERENCE_DIVISOR), ov.to_u32() < silence)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+
+
+/// Per channel prefix sums of the amplitude of a wav (the distance of each sample to silence, the center of the sample range).
+pub struct IntegralAmplitude {
+    num_channels: usize,
+    //(num_frames + 1) x num_channels, the sums of all frames before the frame
+    sums: Vec<u64>,
+}
+impl IntegralAmplitude {
+    pub fn generate<T: Sample>(wav: &DifCodeWav<T>) -> IntegralAmplitude {
+        let num_channels = wav.num_channels();
+        let silence = T::MAX.to_u32().div_ceil(2);
+        let mut sums = vec![0u64; (wav.num_frames() + 1) * num_channels];
+        for frame in 0..wav.num_frames() {
+            for channel in 0..num_channels {
+                let amplitude = wav.get_sample(frame, channel).to_u32().abs_diff(silence);
+                sums[(frame + 1) * num_channels + channel] = sums[frame * num_channels + channel] + amplitude as u64;
+            }
+        }
+        IntegralAmplitude { num_channels, sums }
+    }
+    pub fn num_frames(&self) -> usize {
+        self.sums.len() / self.num_channels - 1
+    }
+    /// The average amplitude of the channel in the frames frame-radius..=frame+radius (cut off at the start and end).
+    pub fn average_in_radius(&self, frame: usize, channel: usize, radius: usize) -> u32 {
+        let first = frame.saturating_sub(radius);
+        let last = (frame + radius).min(self.num_frames() - 1);
+        let sum = self.sums[(last + 1) * self.num_channels + channel] - self.sums[first * self.num_channels + channel];
+        (sum / (last - first + 1) as u64) as u32
+    }
+}
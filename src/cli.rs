@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use jokrey_utilities::encoding::tag_based::bytes::remote::authenticated::authentication_helper::{aes_crt_np_128_decrypt_from_decipherable, aes_crt_np_128_encrypt_into_decipherable};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use generic_image_steganography::{DifCodeError, DifCodeKey, PayloadFlags, EncodingOptions, ChannelWeighting, DeltaEFormula, DEFAULT_MAX_DELTA_E, QualityMetrics, SteganalysisScores,
+                                  DEFAULT_AREA_AVERAGE_RADIUS, DEFAULT_LOCAL_VARIANCE_RADIUS};
+
+use crate::{download, encryption_overhead_in_bytes, SelectionAlgorithm};
+use crate::container_image::{ContainerImage, ContainerFormat};
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_USAGE: i32 = 1;
+pub const EXIT_INPUT: i32 = 2;
+pub const EXIT_CAPACITY_REACHED: i32 = 3;
+pub const EXIT_MISMATCHED_CONTAINER_SIZES: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+pub const EXIT_INTERNAL: i32 = 6;
+pub const EXIT_UNDECODABLE_MESSAGE: i32 = 7;
+pub const EXIT_NO_VALID_MESSAGE: i32 = 8;
+
+const USAGE: &str = "\
+Usage:
+    generic_image_steganography                      (interactive menu)
+    generic_image_steganography encode   --original <path|url> --output <path|*.jpg|*.gif|*.wav> (--message <text> | --message-file <path|->)
+                                         [--codec utf8|base64] [--password <aes password>] [--key <secret>] [--algorithm evenly-random|area-average|local-variance|stratified|delta-e]
+                                         [--error-correction <parity bytes per 255 byte block>] [--embed-in-alpha] [--force] [--raw [--protect <ranges>]]
+                                         [--seed <number>|key] [--channel-weighting uniform|perceptual|<r>,<g>,<b>]
+                                         [--max-delta-e <threshold>] [--delta-e-formula cie76|ciede2000] [--radius <pixels>]
+    generic_image_steganography decode   --original <path|url> --encoded <path|url>
+                                         [--codec utf8|base64] [--password <aes password>] [--key <secret>] [--output <path>] [--raw]
+    generic_image_steganography capacity --original <path|url> [--algorithm evenly-random|area-average|local-variance|stratified|delta-e] [--error-correction <parity bytes>] [--embed-in-alpha]
+                                         [--channel-weighting uniform|perceptual|<r>,<g>,<b>] [--max-delta-e <threshold>] [--delta-e-formula cie76|ciede2000]
+                                         [--radius <pixels>] [--password <aes password>] [--raw [--protect <ranges>]]
+    generic_image_steganography inspect  --original <path|url> [--encoded <path|url>] [--raw [--protect <ranges>]]
+    generic_image_steganography analyze  --original <path|url> [--encoded <path|url>]
+
+    --codec       encode: how the message is given (utf8 text or base64 of raw bytes), decode: how the message is printed
+    --password    aes encryption of the message, capacity: the reported capacity accounts for the encryption overhead
+    --message-file  raw message bytes are read from the file (- for stdin), --codec base64 decodes the file content first
+    --key         secret that permutes the difference code tables, decoding requires the same key (in addition to the images)
+    --seed        makes the encoded output reproducible: the same seed (and inputs) always changes the same values in the same way,
+                  'key' derives the seed from --key (the seed is never needed for decoding)
+    --algorithm   evenly-random changes any value, area-average limits each change by the distance to the local average,
+                  local-variance by the local standard deviation (textured areas take the message, flat areas stay untouched),
+                  stratified changes any value like evenly-random, but spreads the changes evenly over the image (no clusters or voids),
+                  delta-e limits the color shift of each pixel (all channels together) to --max-delta-e (default 2.3, about the smallest
+                  noticeable difference) by --delta-e-formula (default ciede2000)
+    --radius      of the square around each pixel that area-average (default 10) and local-variance (default 3) consider
+    --channel-weighting  scales the max change of the r, g and b channel of 8 bit images, perceptual scales each inversely to its share of the
+                  perceived brightness (green ~0.57, red ~1.1, blue ~2.9), so capacity moves from green to blue at about the same visual distortion
+    --error-correction  reed solomon parity bytes per block of 255 bytes (2-254), repairs up to half as many altered bytes per block
+    --embed-in-alpha  images with alpha channel keep it, with this flag it is also changed where the original alpha already varies (soft edges)
+    --raw         any file is coded in its bytes (evenly-random selection only), the headers (and row padding) of bmps and everything
+                  but the samples of wavs are never changed
+    --protect     byte ranges of a --raw original that are never changed in addition (e.g. checksums), as start..end (end exclusive
+                  or omitted for the end of the file), comma separated, e.g. 0..128,4000..
+    --output      encode: path of the encoded image (must not exist, unless --force), decode: write the raw message bytes to the path instead of printing
+                  encoding into a .jpg/.jpeg output changes the dct coefficients of the (baseline jpeg) original instead of its pixels,
+                  such a pair is decoded in the same way (when both --original and --encoded are .jpg/.jpeg)
+                  encoding into a .gif output changes the palettes of all frames of the (gif) original, an animated png original
+                  is encoded into all of its frames if the output is a .png, both keep their frame timing and disposal
+                  (a pair of gifs or a pair of animated pngs is decoded in the same way)
+                  a .wav output is encoded in the samples of a (8, 16 or 24 bit pcm) wav original, with --algorithm area-average
+                  limited by the local amplitude (a pair of wavs is decoded in the same way)
+    --encoded     inspect: prints the changed values and the encoded message length, and for images and jpegs the quality of the encoded pixels
+                  compared to the original (psnr, ssim, mse and max absolute change of any channel, also printed after every encode)
+                  analyze: runs the steganalysis detectors (chi-square attack, rs analysis and sample pair analysis of lsb replacement)
+                  on the pixels of the original and the encoded image, to compare how much an encoding raises their scores
+
+Exit codes:
+    0 success, 1 invalid arguments, 2 input could not be loaded, 3 message exceeds capacity,
+    4 image sizes do not match, 5 io/image error, 6 internal error, 7 decoded message not representable in codec,
+    8 no valid message found (header or checksum mismatch or too many errors, i.e. wrong images, wrong key or altered encoded image)";
+
+enum CliError {
+    Usage(String),
+    Input(String),
+    Undecodable(String),
+    DifCode(DifCodeError),
+}
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Input(_) => EXIT_INPUT,
+            CliError::Undecodable(_) => EXIT_UNDECODABLE_MESSAGE,
+            CliError::DifCode(err) => exit_code_for(err),
+        }
+    }
+}
+impl From<DifCodeError> for CliError {
+    fn from(err: DifCodeError) -> CliError {
+        CliError::DifCode(err)
+    }
+}
+
+pub fn exit_code_for(err: &DifCodeError) -> i32 {
+    match err {
+        DifCodeError::InternalCapacityReached(_) => EXIT_CAPACITY_REACHED,
+        DifCodeError::InternalMismatchedContainerSizes => EXIT_MISMATCHED_CONTAINER_SIZES,
+        DifCodeError::InvalidHeader(_) | DifCodeError::UnsupportedFormatVersion(_) | DifCodeError::ChecksumMismatch { .. } |
+        DifCodeError::UncorrectableErrors { .. } => EXIT_NO_VALID_MESSAGE,
+        DifCodeError::InvalidJpeg(_) | DifCodeError::InvalidAnimation(_) | DifCodeError::InvalidAudio(_) => EXIT_INPUT,
+        DifCodeError::IO(_) | DifCodeError::IMG(_) => EXIT_IO,
+        DifCodeError::Internal(_) => EXIT_INTERNAL,
+    }
+}
+
+
+
+/// Runs the non-interactive command line interface for the given arguments (without program name) and returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "encode" => parse_options(&args[1..]).and_then(|options| encode_command(&options)),
+        "decode" => parse_options(&args[1..]).and_then(|options| decode_command(&options)),
+        "capacity" => parse_options(&args[1..]).and_then(|options| capacity_command(&options)),
+        "inspect" => parse_options(&args[1..]).and_then(|options| inspect_command(&options)),
+        "analyze" => parse_options(&args[1..]).and_then(|options| analyze_command(&options)),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        unknown => Err(CliError::Usage(format!("unknown command: {}", unknown)))
+    };
+
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(err) => {
+            match &err {
+                CliError::Usage(msg) => eprintln!("{}\n\n{}", msg, USAGE),
+                CliError::Input(msg) | CliError::Undecodable(msg) => eprintln!("{}", msg),
+                CliError::DifCode(DifCodeError::InternalCapacityReached(num_bits)) =>
+                    eprintln!("Message too long - the image can only fit {} bits ({} bytes) of it with the selected algorithm", num_bits, num_bits / 8),
+                CliError::DifCode(dif_error) => eprintln!("Failed({:?})", dif_error),
+            }
+            err.exit_code()
+        }
+    }
+}
+
+const FLAGS: [&str; 3] = ["force", "embed-in-alpha", "raw"];
+
+struct Options(HashMap<String, String>);
+impl Options {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|s| s.as_str())
+    }
+    fn require(&self, name: &str) -> Result<&str, CliError> {
+        self.get(name).ok_or_else(|| CliError::Usage(format!("missing required option --{}", name)))
+    }
+    fn has_flag(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, CliError> {
+    let mut options = HashMap::new();
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        let name = arg.strip_prefix("--").ok_or_else(|| CliError::Usage(format!("unexpected argument: {}", arg)))?;
+        if FLAGS.contains(&name) {
+            options.insert(name.to_string(), String::new());
+        } else {
+            let value = args_iter.next().ok_or_else(|| CliError::Usage(format!("missing value for --{}", name)))?;
+            if options.insert(name.to_string(), value.to_string()).is_some() {
+                return Err(CliError::Usage(format!("--{} given more than once", name)))
+            }
+        }
+    }
+    Ok(Options(options))
+}
+
+
+
+fn encode_command(options: &Options) -> Result<(), CliError> {
+    let codec = options.get("codec").unwrap_or("utf8");
+    let message = match (options.get("message"), options.get("message-file")) {
+        (Some(text), None) => decode_message_input(text.as_bytes(), codec)?,
+        (None, Some(path)) => decode_message_input(&read_message_file(path)?, codec)?,
+        (Some(_), Some(_)) => return Err(CliError::Usage("--message and --message-file are mutually exclusive".to_string())),
+        (None, None) => return Err(CliError::Usage("missing message (--message or --message-file)".to_string())),
+    };
+    let (final_message_bytes, flags) = match options.get("password") {
+        Some(password) => (aes_crt_np_128_encrypt_into_decipherable(&message, password), PayloadFlags::ENCRYPTED),
+        None => (message, PayloadFlags::NONE)
+    };
+
+    let mut encoding_options = parse_error_correction(options, EncodingOptions::default().with_flags(flags))?;
+    if let Some(key) = parse_key(options) {
+        encoding_options = encoding_options.with_key(key);
+    }
+    let mut rng = parse_seed(options)?;
+
+    let output_path = options.require("output")?;
+    let original_source = options.require("original")?;
+    let original_bytes = load_bytes(original_source)?;
+    let format = if options.has_flag("raw") { ContainerFormat::Raw } else { ContainerFormat::for_output(&original_bytes, output_path) };
+    let mut original = load_image(original_source, &original_bytes, format)?;
+    apply_protected_ranges(options, &mut original)?;
+    let selection_algorithm = parse_selection_algorithm(options)?;
+    let channel_weighting = parse_channel_weighting(options)?;
+    if Path::new(output_path).exists() && !options.has_flag("force") {
+        return Err(CliError::Usage(format!("output path {} already exists (use --force to overwrite)", output_path)))
+    }
+
+    original.encode_into_path(&final_message_bytes, &encoding_options, &selection_algorithm, options.has_flag("embed-in-alpha"), channel_weighting, &mut rng, output_path)?;
+    eprintln!("Encoded {} bytes into {} and stored at {}", final_message_bytes.len(), original, output_path);
+    if let Some(metrics) = quality_metrics_of(format, original_source, &original_bytes, output_path, &fs::read(output_path).map_err(DifCodeError::from)?)? {
+        eprintln!("Quality: {}", metrics);
+    }
+    Ok(())
+}
+
+fn decode_command(options: &Options) -> Result<(), CliError> {
+    let (original_source, encoded_source) = (options.require("original")?, options.require("encoded")?);
+    let (original_bytes, encoded_bytes) = (load_bytes(original_source)?, load_bytes(encoded_source)?);
+    let format = if options.has_flag("raw") { ContainerFormat::Raw } else { ContainerFormat::for_pair(original_source, &original_bytes, encoded_source, &encoded_bytes) };
+    let original = load_image(original_source, &original_bytes, format)?;
+    let encoded = load_image(encoded_source, &encoded_bytes, format)?;
+
+    let (report, decoded_raw_bytes) = ContainerImage::decode_with_report(parse_key(options).as_ref(), &original, &encoded)?;
+    if let Some(error_correction) = report.error_correction {
+        eprintln!("Repaired {} altered bytes in {} error correction blocks, resynchronized after {} altered values", error_correction.corrected_symbols, error_correction.num_blocks, error_correction.resynchronized_values);
+    }
+    let is_encrypted = report.header.flags.contains(PayloadFlags::ENCRYPTED);
+    let final_message_bytes = match options.get("password") {
+        Some(password) if is_encrypted => aes_crt_np_128_decrypt_from_decipherable(&decoded_raw_bytes, password),
+        None if is_encrypted => return Err(CliError::Usage("the message is encrypted, --password is required".to_string())),
+        Some(_) => {
+            eprintln!("the message is not encrypted, ignoring --password");
+            decoded_raw_bytes
+        }
+        None => decoded_raw_bytes
+    };
+
+    if let Some(output_path) = options.get("output") {
+        fs::write(output_path, &final_message_bytes).map_err(DifCodeError::from)?;
+        return Ok(())
+    }
+    match options.get("codec").unwrap_or("utf8") {
+        "utf8" => match std::str::from_utf8(&final_message_bytes) {
+            Ok(utf8) => println!("{}", utf8),
+            Err(_) => return Err(CliError::Undecodable(format!("decoded message could not be utf8 decoded (raw: {:?})", final_message_bytes)))
+        },
+        "base64" => println!("{}", BASE64.encode(&final_message_bytes)),
+        unknown => return Err(CliError::Usage(format!("unknown codec: {}", unknown)))
+    }
+    Ok(())
+}
+
+fn capacity_command(options: &Options) -> Result<(), CliError> {
+    let original_source = options.require("original")?;
+    let original_bytes = load_bytes(original_source)?;
+    let format = if options.has_flag("raw") { ContainerFormat::Raw } else { ContainerFormat::of(original_source, &original_bytes) };
+    let selection_algorithm = parse_selection_algorithm(options)?;
+    let channel_weighting = parse_channel_weighting(options)?;
+    let encoding_options = parse_error_correction(options, EncodingOptions::default())?;
+    let overhead_in_bytes = if options.get("password").is_some() { encryption_overhead_in_bytes() } else { 0 };
+
+    //audio has no pixels, raw files are only coded in their bytes
+    if format != ContainerFormat::Wav && format != ContainerFormat::Raw {
+        let original = load_image(original_source, &original_bytes, ContainerFormat::Pixels)?;
+        println!("image: {}", original);
+        print_capacity(&original, &selection_algorithm, &encoding_options, options.has_flag("embed-in-alpha"), channel_weighting, overhead_in_bytes)?;
+    }
+    //a jpeg, gif or animated png original can also be encoded into its dct coefficients, palettes or all frames
+    let output_kind = match format {
+        ContainerFormat::Pixels => return Ok(()),
+        ContainerFormat::Jpeg => "into a .jpg output",
+        ContainerFormat::Gif => "into a .gif output",
+        ContainerFormat::Apng => "into a .png output",
+        ContainerFormat::Wav => "into a .wav output",
+        ContainerFormat::Raw => "with --raw",
+    };
+    let mut original = load_image(original_source, &original_bytes, format)?;
+    apply_protected_ranges(options, &mut original)?;
+    println!("{} ({}):", original, output_kind);
+    print_capacity(&original, &selection_algorithm, &encoding_options, false, ChannelWeighting::Uniform, overhead_in_bytes)?;
+    Ok(())
+}
+
+fn print_capacity(original: &ContainerImage, selection_algorithm: &SelectionAlgorithm, encoding_options: &EncodingOptions, embed_in_alpha: bool, channel_weighting: ChannelWeighting, overhead_in_bytes: usize) -> Result<(), CliError> {
+    let report = original.capacity_report(selection_algorithm, embed_in_alpha, channel_weighting, encoding_options)?.without_overhead(overhead_in_bytes);
+    println!("guaranteed capacity: {} bytes", report.guaranteed_bytes);
+    println!("expected capacity: {} bytes (random, e.g. encrypted, messages)", report.expected_bytes);
+    println!("maximum capacity: {} bytes", report.maximum_bytes);
+    Ok(())
+}
+
+fn inspect_command(options: &Options) -> Result<(), CliError> {
+    let original_source = options.require("original")?;
+    let original_bytes = load_bytes(original_source)?;
+    let encoded_bytes = options.get("encoded").map(load_bytes).transpose()?;
+    let format = match (options.get("encoded"), &encoded_bytes) {
+        _ if options.has_flag("raw") => ContainerFormat::Raw,
+        (Some(encoded_source), Some(encoded_bytes)) => ContainerFormat::for_pair(original_source, &original_bytes, encoded_source, encoded_bytes),
+        //audio has no pixels
+        _ if ContainerFormat::of(original_source, &original_bytes) == ContainerFormat::Wav => ContainerFormat::Wav,
+        _ => ContainerFormat::Pixels
+    };
+    let mut original = load_image(original_source, &original_bytes, format)?;
+    apply_protected_ranges(options, &mut original)?;
+    println!("original: {} with {} channel values", original, original.len());
+
+    if let (Some(encoded_source), Some(encoded_bytes)) = (options.get("encoded"), &encoded_bytes) {
+        let encoded = load_image(encoded_source, encoded_bytes, format)?;
+        println!("encoded: {} with {} channel values", encoded, encoded.len());
+        if original.width() != encoded.width() || original.height() != encoded.height() {
+            return Err(CliError::from(DifCodeError::InternalMismatchedContainerSizes))
+        }
+        let (num_changed, encoded_length_in_bits) = ContainerImage::compare(&original, &encoded)?;
+        println!("changed channel values: {}", num_changed);
+        println!("encoded message length: {} bits", encoded_length_in_bits);
+        if let Some(metrics) = quality_metrics_of(format, original_source, &original_bytes, encoded_source, encoded_bytes)? {
+            println!("psnr: {:.2} dB", metrics.psnr);
+            println!("ssim: {:.5}", metrics.ssim);
+            println!("mse: {:.5}", metrics.mse);
+            println!("max absolute change: {}", metrics.max_absolute_change);
+        }
+    }
+    Ok(())
+}
+
+
+
+fn analyze_command(options: &Options) -> Result<(), CliError> {
+    let original_source = options.require("original")?;
+    let original = load_image(original_source, &load_bytes(original_source)?, ContainerFormat::Pixels)?;
+    println!("original: {}", original);
+    let original_scores = original.steganalysis_scores()?;
+    //with an encoded image every score is printed as original -> encoded
+    let encoded_scores = match options.get("encoded") {
+        Some(encoded_source) => {
+            let encoded = load_image(encoded_source, &load_bytes(encoded_source)?, ContainerFormat::Pixels)?;
+            println!("encoded: {}", encoded);
+            Some(ContainerImage::steganalysis_report(&original, &encoded)?.encoded)
+        }
+        None => None
+    };
+    let describe = |score: fn(&SteganalysisScores) -> String| match &encoded_scores {
+        Some(encoded_scores) => format!("{} -> {}", score(&original_scores), score(encoded_scores)),
+        None => score(&original_scores)
+    };
+    println!("chi-square attack: {} (probability of lsb replacement)", describe(|scores| format!("{:.4}", scores.chi_square)));
+    println!("rs analysis: {} (estimated fraction of values with a message in their lsb)", describe(|scores| format!("{:.4}", scores.rs)));
+    println!("sample pair analysis: {} (estimated fraction of values with a message in their lsb)", describe(|scores| format!("{:.4}", scores.spa)));
+    println!("flagged: {}", describe(|scores| (if scores.is_flagged() { "yes" } else { "no" }).to_string()));
+    Ok(())
+}
+
+//of the decoded pixels (so for jpegs the visible change of the coefficients), None for containers without pixels or with several frames
+fn quality_metrics_of(format: ContainerFormat, original_source: &str, original_bytes: &[u8], encoded_source: &str, encoded_bytes: &[u8]) -> Result<Option<QualityMetrics>, CliError> {
+    if format != ContainerFormat::Pixels && format != ContainerFormat::Jpeg {
+        return Ok(None)
+    }
+    let original = load_image(original_source, original_bytes, ContainerFormat::Pixels)?;
+    let encoded = load_image(encoded_source, encoded_bytes, ContainerFormat::Pixels)?;
+    Ok(Some(ContainerImage::quality_metrics(&original, &encoded)?))
+}
+
+fn parse_selection_algorithm(options: &Options) -> Result<SelectionAlgorithm, CliError> {
+    match options.get("algorithm").unwrap_or("evenly-random") {
+        "evenly-random" => Ok(SelectionAlgorithm::EvenlyRandom),
+        "area-average" => Ok(SelectionAlgorithm::AreaAverage { radius: parse_radius(options)?.unwrap_or(DEFAULT_AREA_AVERAGE_RADIUS) }),
+        "local-variance" => Ok(SelectionAlgorithm::LocalVariance { radius: parse_radius(options)?.unwrap_or(DEFAULT_LOCAL_VARIANCE_RADIUS) }),
+        "stratified" => Ok(SelectionAlgorithm::Stratified),
+        "delta-e" => {
+            let formula = match options.get("delta-e-formula").unwrap_or("ciede2000") {
+                "cie76" => DeltaEFormula::Cie76,
+                "ciede2000" => DeltaEFormula::Ciede2000,
+                unknown => return Err(CliError::Usage(format!("unknown color difference formula: {}", unknown)))
+            };
+            let max_delta_e = match options.get("max-delta-e") {
+                None => DEFAULT_MAX_DELTA_E,
+                Some(raw) => match raw.parse::<f64>() {
+                    Ok(max_delta_e) if max_delta_e.is_finite() && max_delta_e >= 0.0 => max_delta_e,
+                    _ => return Err(CliError::Usage(format!("invalid max color difference: {}", raw)))
+                }
+            };
+            Ok(SelectionAlgorithm::DeltaE { formula, max_delta_e })
+        }
+        unknown => Err(CliError::Usage(format!("unknown selection algorithm: {}", unknown)))
+    }
+}
+
+fn parse_radius(options: &Options) -> Result<Option<u32>, CliError> {
+    match options.get("radius") {
+        None => Ok(None),
+        Some(raw) => raw.parse::<u32>().map(Some).map_err(|_| CliError::Usage(format!("invalid radius: {}", raw)))
+    }
+}
+
+fn parse_channel_weighting(options: &Options) -> Result<ChannelWeighting, CliError> {
+    match options.get("channel-weighting").unwrap_or("uniform") {
+        "uniform" => Ok(ChannelWeighting::Uniform),
+        "perceptual" => Ok(ChannelWeighting::Perceptual),
+        raw => {
+            let scales: Vec<f32> = raw.split(',').map(|scale| scale.trim().parse::<f32>()).collect::<Result<_, _>>()
+                .map_err(|_| CliError::Usage(format!("invalid channel weighting: {}", raw)))?;
+            match scales[..] {
+                [r, g, b] if [r, g, b].iter().all(|scale| scale.is_finite() && *scale >= 0.0) => Ok(ChannelWeighting::Custom([r, g, b])),
+                _ => Err(CliError::Usage(format!("invalid channel weighting (uniform, perceptual or three scales r,g,b): {}", raw)))
+            }
+        }
+    }
+}
+
+fn parse_key(options: &Options) -> Option<DifCodeKey> {
+    options.get("key").map(DifCodeKey::from_password)
+}
+
+//without a seed every encoding picks different changes
+fn parse_seed(options: &Options) -> Result<StdRng, CliError> {
+    match options.get("seed") {
+        None => Ok(StdRng::from_entropy()),
+        Some("key") => parse_key(options).map(|key| key.map_rng()).ok_or_else(|| CliError::Usage("--seed key requires --key".to_string())),
+        Some(raw) => raw.parse::<u64>().map(StdRng::seed_from_u64).map_err(|_| CliError::Usage(format!("invalid seed (a number or key): {}", raw)))
+    }
+}
+
+fn parse_error_correction(options: &Options, encoding_options: EncodingOptions) -> Result<EncodingOptions, CliError> {
+    match options.get("error-correction") {
+        None => Ok(encoding_options),
+        Some(raw) => match raw.parse::<u8>() {
+            Ok(parity_symbols) if (2..=254).contains(&parity_symbols) => Ok(encoding_options.with_error_correction(parity_symbols)),
+            _ => Err(CliError::Usage(format!("invalid number of error correction parity bytes: {}", raw)))
+        }
+    }
+}
+
+//only raw files have protected ranges, the file format already protects some of them (see DifCodeRawFile)
+fn apply_protected_ranges(options: &Options, container: &mut ContainerImage) -> Result<(), CliError> {
+    let raw = match options.get("protect") {
+        None => return Ok(()),
+        Some(raw) => raw
+    };
+    let file = match container {
+        ContainerImage::Raw(file) => file,
+        _ => return Err(CliError::Usage("--protect requires --raw".to_string()))
+    };
+    for range in raw.split(',') {
+        file.protect(parse_range(range.trim()).ok_or_else(|| CliError::Usage(format!("invalid protected range: {}", range)))?);
+    }
+    Ok(())
+}
+fn parse_range(raw: &str) -> Option<Range<usize>> {
+    let (start, end) = raw.split_once("..")?;
+    let start = start.parse().ok()?;
+    let end = if end.is_empty() { usize::MAX } else { end.parse().ok()? };
+    if start < end { Some(start..end) } else { None }
+}
+
+fn decode_message_input(raw: &[u8], codec: &str) -> Result<Vec<u8>, CliError> {
+    match codec {
+        "utf8" => Ok(raw.to_vec()),
+        "base64" => BASE64.decode(String::from_utf8_lossy(raw).trim())
+            .map_err(|_| CliError::Input("could not base64 decode message".to_string())),
+        unknown => Err(CliError::Usage(format!("unknown codec: {}", unknown)))
+    }
+}
+
+fn read_message_file(path: &str) -> Result<Vec<u8>, CliError> {
+    let result = if path == "-" {
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer).map(|_| buffer)
+    } else {
+        fs::read(path)
+    };
+    result.map_err(|err| CliError::Input(format!("could not read message from {}: {}", path, err)))
+}
+
+fn load_bytes(source: &str) -> Result<Vec<u8>, CliError> {
+    let is_url = source.starts_with("http://") || source.starts_with("https://");
+    let result = if is_url {
+        download(source)
+    } else {
+        fs::read(source).map_err(|_| "Failed to read image from path")
+    };
+    result.map_err(|err| CliError::Input(format!("{}: {}", err, source)))
+}
+
+//the format decides which part of the file is the container (see ContainerFormat)
+fn load_image(source: &str, bytes: &[u8], format: ContainerFormat) -> Result<ContainerImage, CliError> {
+    ContainerImage::from_memory_as(bytes, format)
+        .map_err(|_| CliError::Input(format!("Failed to load {}: {}", format, source)))
+}
@@ -0,0 +1,284 @@
+//Conversion of 8 and 16 bit srgb colors into CIELAB (D65 white point) and the CIE76 and CIEDE2000 color differences between them.
+
+use std::sync::OnceLock;
+
+use crate::rand::RngCore;
+use crate::util::{DifCodeImage, DifCodeImage16, DifCodeRgbaImage, DifCodeLumaImage, DifCodeLumaImage16, DifCodeResult, EncodingContainer, Sample};
+use crate::difference_encoder::bits_difference_converter::calculate_worst_case_difference_of_sample;
+use crate::difference_encoder::max_change_map_creator::{create_allowed_changes_map_for_message, write_minimal_evenly_random_allowed_changes_map, calculate_alpha_change_constraint};
+use crate::parallel;
+
+//reference white (D65)
+const WHITE_X: f64 = 0.95047;
+const WHITE_Y: f64 = 1.0;
+const WHITE_Z: f64 = 1.08883;
+
+/// The formula of the perceived difference between two CIELAB colors.
+/// A difference of about 2.3 is the smallest one that is just noticeable side by side, below 1 it is not perceptible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeltaEFormula {
+    /// The euclidean distance in CIELAB.
+    Cie76,
+    /// Corrects the non uniformity of CIELAB (e.g. the overestimation of differences in saturated colors).
+    Ciede2000,
+}
+impl DeltaEFormula {
+    pub fn difference(&self, first: [f64; 3], second: [f64; 3]) -> f64 {
+        match self {
+            DeltaEFormula::Cie76 => cie76(first, second),
+            DeltaEFormula::Ciede2000 => ciede2000(first, second),
+        }
+    }
+}
+
+/// Converts 8 and 16 bit srgb colors into CIELAB, the linearization of each of the 256 (or 65536, on the first 16 bit color) values is computed once.
+pub struct LabConverter {
+    linear: [f64; 256],
+    linear16: OnceLock<Vec<f64>>,
+}
+impl LabConverter {
+    pub fn new() -> LabConverter {
+        let mut linear = [0.0; 256];
+        for (value, linear) in linear.iter_mut().enumerate() {
+            *linear = to_linear(value as f64 / 255.0);
+        }
+        LabConverter { linear, linear16: OnceLock::new() }
+    }
+
+    /// L, a and b of the srgb color.
+    pub fn to_lab(&self, [r, g, b]: [u8; 3]) -> [f64; 3] {
+        linear_to_lab(self.linear[r as usize], self.linear[g as usize], self.linear[b as usize])
+    }
+    /// L, a and b of the 16 bit srgb color.
+    pub fn to_lab16(&self, [r, g, b]: [u16; 3]) -> [f64; 3] {
+        let linear = self.linear16.get_or_init(|| (0..=u16::MAX).map(|value| to_linear(value as f64 / u16::MAX as f64)).collect());
+        linear_to_lab(linear[r as usize], linear[g as usize], linear[b as usize])
+    }
+}
+impl Default for LabConverter {
+    fn default() -> Self {
+        LabConverter::new()
+    }
+}
+
+//of an srgb value in 0..=1
+fn to_linear(value: f64) -> f64 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+fn linear_to_lab(r: f64, g: f64, b: f64) -> [f64; 3] {
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / WHITE_X;
+    let y = (0.2126729 * r + 0.7151522 * g + 0.0721750 * b) / WHITE_Y;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / WHITE_Z;
+    let (fx, fy, fz) = (lab_f(x), lab_f(y), lab_f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_f(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn cie76(first: [f64; 3], second: [f64; 3]) -> f64 {
+    first.iter().zip(second.iter()).map(|(a, b)| (a - b) * (a - b)).sum::<f64>().sqrt()
+}
+
+//as in Sharma, Wu, Dalal: The CIEDE2000 Color-Difference Formula (2005), with all parametric factors 1
+fn ciede2000([l1, a1, b1]: [f64; 3], [l2, a2, b2]: [f64; 3]) -> f64 {
+    const POW_25_7: f64 = 6103515625.0;
+    let c_mean = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + POW_25_7)).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |b: f64, a: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else if h2 - h1 < -180.0 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos() + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos() - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + POW_25_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
+
+
+
+//the changes of the r, g and b value of a pixel add up to one color shift, so their max differences are chosen together:
+//    one more bit at a time and channel (round robin), as long as every color the encoder can produce within them stays within max_delta_e
+//    of the original color. The shift is largest at the corners of the box between the pixel and its allowed change (the conversion is almost linear
+//    at that scale), so only the corners are checked. The channels are changed away from the closer end of the value range (as in the evenly random map).
+pub const DEFAULT_MAX_DELTA_E: f64 = 2.3;
+//the value changed by difference away from the closer end of the value range, if that is within the range
+fn change_away_from_closer_end<T: Sample>(value: T, difference: T) -> Option<T> {
+    let (value, difference) = (value.to_u32(), difference.to_u32());
+    if value <= T::MAX.to_u32() / 2 {
+        Some(value + difference).filter(|&changed| changed <= T::MAX.to_u32()).map(T::from_u32)
+    } else {
+        value.checked_sub(difference).map(T::from_u32)
+    }
+}
+fn calculate_delta_e_max_differences<T: Sample>(converter: &LabConverter, to_lab: fn(&LabConverter, [T; 3]) -> [f64; 3], formula: DeltaEFormula, max_delta_e: f64, pixel: [T; 3]) -> [T; 3] {
+    let original = to_lab(converter, pixel);
+    let changed = |channel: usize, difference: T| change_away_from_closer_end(pixel[channel], difference);
+    let mut num_bits = [0u8; 3];
+    let mut max_differences = [T::default(); 3];
+    let mut is_saturated = [false; 3];
+    while is_saturated.contains(&false) {
+        for channel in 0..3 {
+            if is_saturated[channel] {
+                continue
+            }
+            let next_difference = match calculate_worst_case_difference_of_sample::<T>(num_bits[channel] + 1) {
+                Some(next_difference) if changed(channel, next_difference).is_some() => next_difference,
+                _ => {
+                    is_saturated[channel] = true;
+                    continue
+                }
+            };
+            let (first_other, second_other) = ((channel + 1) % 3, (channel + 2) % 3);
+            let is_within = (0..4).all(|corner| {
+                let mut color = pixel;
+                color[channel] = changed(channel, next_difference).unwrap();
+                color[first_other] = changed(first_other, if corner & 1 == 0 { T::default() } else { max_differences[first_other] }).unwrap();
+                color[second_other] = changed(second_other, if corner & 2 == 0 { T::default() } else { max_differences[second_other] }).unwrap();
+                formula.difference(original, to_lab(converter, color)) <= max_delta_e
+            });
+            if is_within {
+                num_bits[channel] += 1;
+                max_differences[channel] = next_difference;
+            } else {
+                is_saturated[channel] = true;
+            }
+        }
+    }
+    max_differences
+}
+//a gray value is changed as a whole, i.e. its color moves along the gray axis
+fn calculate_delta_e_max_difference_of_gray<T: Sample>(converter: &LabConverter, to_lab: fn(&LabConverter, [T; 3]) -> [f64; 3], formula: DeltaEFormula, max_delta_e: f64, value: T) -> T {
+    let original = to_lab(converter, [value; 3]);
+    let mut max_difference = T::default();
+    for num_bits in 1..T::MAX_NUM_BITS {
+        let difference = calculate_worst_case_difference_of_sample::<T>(num_bits).unwrap();
+        match change_away_from_closer_end(value, difference) {
+            Some(changed) if formula.difference(original, to_lab(converter, [changed; 3])) <= max_delta_e => max_difference = difference,
+            _ => break
+        }
+    }
+    max_difference
+}
+//the max differences of all pixels (row by row), computed once before the map is created
+fn generate_delta_e_max_differences<T: Sample, F: Fn(u32, u32) -> [T; 3] + Sync>(width: u32, height: u32, pixel_at: F, to_lab: fn(&LabConverter, [T; 3]) -> [f64; 3], formula: DeltaEFormula, max_delta_e: f64) -> Vec<[T; 3]> {
+    let converter = LabConverter::new();
+    parallel::map_ranges(width as usize * height as usize, |range| {
+        range.map(|pixel_index| {
+            let pixel = pixel_at(pixel_index as u32 % width, pixel_index as u32 / width);
+            calculate_delta_e_max_differences(&converter, to_lab, formula, max_delta_e, pixel)
+        }).collect::<Vec<_>>()
+    }).concat()
+}
+
+pub fn create_minimal_evenly_random_max_delta_e_allowed_changes_map(message: &[u8], original: &DifCodeImage, formula: DeltaEFormula, max_delta_e: f64) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_delta_e_allowed_changes_map(length, original, formula, max_delta_e, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_delta_e_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage, formula: DeltaEFormula, max_delta_e: f64, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let (width, height) = (original.width(), original.height());
+    let max_differences = generate_delta_e_max_differences(width, height, |x, y| original.get_pixel(x, y).0, LabConverter::to_lab, formula, max_delta_e);
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = DifCodeImage::index_to_xyz_with_wh(index, width, height);
+        (max_differences[(y * width + x) as usize][z as usize], ov <= 255 / 2)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_delta_e_luma_allowed_changes_map(message: &[u8], original: &DifCodeLumaImage, formula: DeltaEFormula, max_delta_e: f64) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_delta_e_luma_allowed_changes_map(length, original, formula, max_delta_e, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_delta_e_luma_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeLumaImage, formula: DeltaEFormula, max_delta_e: f64, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    //there are only 256 gray values
+    let converter = LabConverter::new();
+    let max_differences: Vec<u8> = (0..=255u8).map(|value| calculate_delta_e_max_difference_of_gray(&converter, LabConverter::to_lab, formula, max_delta_e, value)).collect();
+    let change_constraint_calculator = move |_index, ov: u8| (max_differences[ov as usize], ov <= 255 / 2);
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_delta_e_image16_allowed_changes_map(message: &[u8], original: &DifCodeImage16, formula: DeltaEFormula, max_delta_e: f64) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_delta_e_image16_allowed_changes_map(length, original, formula, max_delta_e, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_delta_e_image16_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage16, formula: DeltaEFormula, max_delta_e: f64, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    let width = original.width();
+    let max_differences = generate_delta_e_max_differences(width, original.height(), |x, y| original.get_pixel(x, y).0, LabConverter::to_lab16, formula, max_delta_e);
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        (max_differences[(y * width + x) as usize][z as usize], ov <= u16::MAX / 2)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map(message: &[u8], original: &DifCodeLumaImage16, formula: DeltaEFormula, max_delta_e: f64) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map(length, original, formula, max_delta_e, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeLumaImage16, formula: DeltaEFormula, max_delta_e: f64, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    //all 65536 gray values are cheaper than a value per pixel for all but tiny images
+    let converter = LabConverter::new();
+    let max_differences: Vec<u16> = parallel::map_ranges(u16::MAX as usize + 1, |range| {
+        range.map(|value| calculate_delta_e_max_difference_of_gray(&converter, LabConverter::to_lab16, formula, max_delta_e, value as u16)).collect::<Vec<_>>()
+    }).concat();
+    let change_constraint_calculator = move |_index, ov: u16| (max_differences[ov as usize], ov <= u16::MAX / 2);
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map(message: &[u8], original: &DifCodeRgbaImage, formula: DeltaEFormula, max_delta_e: f64, embed_in_alpha: bool) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map(length, original, formula, max_delta_e, embed_in_alpha, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeRgbaImage, formula: DeltaEFormula, max_delta_e: f64, embed_in_alpha: bool, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let width = original.width();
+    let max_differences = generate_delta_e_max_differences(width, original.height(), |x, y| {
+        let [r, g, b, _] = original.get_pixel(x, y).0;
+        [r, g, b]
+    }, LabConverter::to_lab, formula, max_delta_e);
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        if z == DifCodeRgbaImage::ALPHA_CHANNEL {
+            return calculate_alpha_change_constraint(original, x, y, embed_in_alpha)
+        }
+        (max_differences[(y * width + x) as usize][z as usize], ov <= 255 / 2)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
@@ -0,0 +1,425 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use image::{DynamicImage, ImageError};
+use image::error::{ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use rand::{RngCore, thread_rng};
+
+use generic_image_steganography::{DifCodeImage, DifCodeRgbaImage, DifCodeImage16, DifCodeLumaImage, DifCodeLumaImage16, DifCodeJpeg, DifCodeGif, DifCodeApng, DifCodeWav, DifCodeRawFile, DifCodeKey, DifCodeResult, DifCodeError, DecodingReport, EncodingContainer, EncodingOptions, Sample,
+                                  encode_into_image_into_path_with_options, encode_into_rgba_image_into_path_with_options, encode_into_image16_into_path_with_options,
+                                  encode_into_luma_image_into_path_with_options, encode_into_luma_image16_into_path_with_options, encode_into_jpeg_into_path_with_options,
+                                  encode_into_gif_into_path_with_options, encode_into_apng_into_path_with_options, encode_into_wav_into_path_with_options, encode_into_raw_file_into_path_with_options,
+                                  decode_into_vec_with_report, is_gif, is_animated_png, is_wav, get_wav_bits_per_sample,
+                                  get_encoded_message_length_in_bits, get_capacity_report, CapacityReport, ChannelWeighting, get_quality_metrics_of_planes, QualityMetrics,
+                                  get_steganalysis_scores, get_steganalysis_scores_of_planes, SteganalysisScores, SteganalysisReport};
+
+use crate::SelectionAlgorithm;
+
+/// The container the ui and the command line encode into: rgba if the loaded image has an alpha channel (so it is preserved),
+///    grayscale if the loaded image is grayscale (so it stays grayscale), otherwise rgb. Both without alpha in 8 or 16 bit, matching the loaded image.
+/// Note: 16 bit images with alpha are not supported (there is no 16 bit rgba container and converting them to 8 bit would lose the lower byte).
+/// Jpegs, gifs and animated pngs are only coded in their dct coefficients, palettes or all frames if explicitly opened as such (see ContainerFormat),
+///    otherwise they are decoded to (the first frame of) pixels like any other image.
+/// Wavs are containers of their samples in their bit depth, their width is the number of frames, their height the number of channels.
+/// Raw files are containers of all of their bytes (except the protected ones), their width is the number of bytes, their height 1.
+pub enum ContainerImage {
+    Rgb(DifCodeImage),
+    Rgba(DifCodeRgbaImage),
+    Rgb16(DifCodeImage16),
+    Luma(DifCodeLumaImage),
+    Luma16(DifCodeLumaImage16),
+    Jpeg(DifCodeJpeg),
+    Gif(DifCodeGif),
+    Apng(DifCodeApng),
+    Wav8(DifCodeWav<u8>),
+    Wav16(DifCodeWav<u16>),
+    Wav24(DifCodeWav<u32>),
+    Raw(DifCodeRawFile),
+}
+impl ContainerImage {
+    pub fn open(path: &str) -> Result<ContainerImage, ImageError> {
+        ContainerImage::from_dynamic(image::open(path)?)
+    }
+    pub fn from_memory(buffer: &[u8]) -> Result<ContainerImage, ImageError> {
+        ContainerImage::from_dynamic(image::load_from_memory(buffer)?)
+    }
+    pub fn open_as(path: &str, format: ContainerFormat) -> DifCodeResult<ContainerImage> {
+        ContainerImage::from_memory_as(&fs::read(path)?, format)
+    }
+    pub fn from_memory_as(buffer: &[u8], format: ContainerFormat) -> DifCodeResult<ContainerImage> {
+        match format {
+            ContainerFormat::Pixels => Ok(ContainerImage::from_memory(buffer)?),
+            ContainerFormat::Jpeg => Ok(ContainerImage::Jpeg(DifCodeJpeg::from_memory(buffer)?)),
+            ContainerFormat::Gif => Ok(ContainerImage::Gif(DifCodeGif::from_memory(buffer)?)),
+            ContainerFormat::Apng => Ok(ContainerImage::Apng(DifCodeApng::from_memory(buffer)?)),
+            ContainerFormat::Wav => match get_wav_bits_per_sample(buffer)? {
+                8 => Ok(ContainerImage::Wav8(DifCodeWav::from_memory(buffer)?)),
+                16 => Ok(ContainerImage::Wav16(DifCodeWav::from_memory(buffer)?)),
+                _ => Ok(ContainerImage::Wav24(DifCodeWav::from_memory(buffer)?)),
+            }
+            ContainerFormat::Raw => Ok(ContainerImage::Raw(DifCodeRawFile::from_memory(buffer))),
+        }
+    }
+    /// Opens a gif or an animated png with all of its frames.
+    pub fn open_animation(path: &str) -> DifCodeResult<ContainerImage> {
+        let buffer = fs::read(path)?;
+        match ContainerFormat::of(path, &buffer) {
+            format @ (ContainerFormat::Gif | ContainerFormat::Apng) => ContainerImage::from_memory_as(&buffer, format),
+            _ => Err(DifCodeError::InvalidAnimation("neither a gif nor an animated png"))
+        }
+    }
+    pub fn from_dynamic(image: DynamicImage) -> Result<ContainerImage, ImageError> {
+        let color = image.color();
+        let is_16_bit = color.bytes_per_pixel() / color.channel_count() == 2;
+        match (color.has_alpha(), color.has_color(), is_16_bit) {
+            (true, _, true) => Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(ImageFormatHint::Unknown, UnsupportedErrorKind::Color(color.into())))),
+            (true, _, false) => Ok(ContainerImage::Rgba(DifCodeRgbaImage::from(image.into_rgba8()))),
+            (false, true, true) => Ok(ContainerImage::Rgb16(DifCodeImage16::from_dynamic(image)?)),
+            (false, true, false) => Ok(ContainerImage::Rgb(DifCodeImage::from_dynamic(image)?)),
+            (false, false, true) => Ok(ContainerImage::Luma16(DifCodeLumaImage16::from_dynamic(image)?)),
+            (false, false, false) => Ok(ContainerImage::Luma(DifCodeLumaImage::from_dynamic(image)?)),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            ContainerImage::Rgb(image) => image.width(),
+            ContainerImage::Rgba(image) => image.width(),
+            ContainerImage::Rgb16(image) => image.width(),
+            ContainerImage::Luma(image) => image.width(),
+            ContainerImage::Luma16(image) => image.width(),
+            ContainerImage::Jpeg(jpeg) => jpeg.width(),
+            ContainerImage::Gif(gif) => gif.width(),
+            ContainerImage::Apng(apng) => apng.width(),
+            ContainerImage::Wav8(wav) => wav.num_frames() as u32,
+            ContainerImage::Wav16(wav) => wav.num_frames() as u32,
+            ContainerImage::Wav24(wav) => wav.num_frames() as u32,
+            ContainerImage::Raw(file) => file.len() as u32,
+        }
+    }
+    pub fn height(&self) -> u32 {
+        match self {
+            ContainerImage::Rgb(image) => image.height(),
+            ContainerImage::Rgba(image) => image.height(),
+            ContainerImage::Rgb16(image) => image.height(),
+            ContainerImage::Luma(image) => image.height(),
+            ContainerImage::Luma16(image) => image.height(),
+            ContainerImage::Jpeg(jpeg) => jpeg.height(),
+            ContainerImage::Gif(gif) => gif.height(),
+            ContainerImage::Apng(apng) => apng.height(),
+            ContainerImage::Wav8(wav) => wav.num_channels() as u32,
+            ContainerImage::Wav16(wav) => wav.num_channels() as u32,
+            ContainerImage::Wav24(wav) => wav.num_channels() as u32,
+            ContainerImage::Raw(_) => 1,
+        }
+    }
+    pub fn len(&self) -> usize {
+        match (self.as_container24(), self.as_container16()) {
+            (Some(container), _) => container.len(),
+            (_, Some(container)) => container.len(),
+            _ => self.as_container().map_or(0, |container| container.len())
+        }
+    }
+    pub fn num_bits_per_value(&self) -> usize {
+        match (self.as_container24(), self.as_container16()) {
+            (Some(_), _) => u32::MAX_NUM_BITS as usize,
+            (_, Some(_)) => u16::MAX_NUM_BITS as usize,
+            _ => u8::MAX_NUM_BITS as usize,
+        }
+    }
+
+    /// Creates the allowed changes map with the given selection algorithm and encodes into the image stored at path.
+    ///     embed_in_alpha is ignored for images without alpha channel, the rng picks the changes (see SelectionAlgorithm::write_map)
+    ///     channel_weighting is ignored for grayscale images and only supported for 8 bit images
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_into_path(&self, message_bytes: &[u8], options: &EncodingOptions, selection_algorithm: &SelectionAlgorithm, embed_in_alpha: bool, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, path: &str) -> DifCodeResult<()> {
+        self.check_channel_weighting(channel_weighting)?;
+        let embedded_length_in_bits = options.get_embedded_length_in_bits(message_bytes.len());
+        match self {
+            ContainerImage::Wav8(wav) => return encode_wav_into_path(message_bytes, options, selection_algorithm, wav, rng, path),
+            ContainerImage::Wav16(wav) => return encode_wav_into_path(message_bytes, options, selection_algorithm, wav, rng, path),
+            ContainerImage::Wav24(wav) => return encode_wav_into_path(message_bytes, options, selection_algorithm, wav, rng, path),
+            _ => {}
+        }
+        if let ContainerImage::Jpeg(jpeg) = self {
+            let mut allowed_changes_map = vec![0u16; jpeg.len()];
+            selection_algorithm.write_jpeg_map(embedded_length_in_bits, jpeg, rng, &mut allowed_changes_map).map_err(|err| options.payload_capacity_error(err))?;
+            return encode_into_jpeg_into_path_with_options(message_bytes, options, jpeg, &allowed_changes_map, path)
+        }
+        if let Some(original) = self.as_container16() {
+            let mut allowed_changes_map = vec![0u16; original.len()];
+            selection_algorithm.write_map16(embedded_length_in_bits, self, rng, &mut allowed_changes_map).map_err(|err| options.payload_capacity_error(err))?;
+            return match self {
+                ContainerImage::Rgb16(image) => encode_into_image16_into_path_with_options(message_bytes, options, image, &allowed_changes_map, path),
+                ContainerImage::Luma16(image) => encode_into_luma_image16_into_path_with_options(message_bytes, options, image, &allowed_changes_map, path),
+                _ => unreachable!(),
+            }
+        }
+
+        let mut allowed_changes_map = vec![0u8; self.len()];
+        selection_algorithm.write_map(embedded_length_in_bits, self, embed_in_alpha, channel_weighting, rng, &mut allowed_changes_map).map_err(|err| options.payload_capacity_error(err))?;
+        match self {
+            ContainerImage::Rgb(image) => encode_into_image_into_path_with_options(message_bytes, options, image, &allowed_changes_map, path),
+            ContainerImage::Rgba(image) => encode_into_rgba_image_into_path_with_options(message_bytes, options, image, &allowed_changes_map, path),
+            ContainerImage::Luma(image) => encode_into_luma_image_into_path_with_options(message_bytes, options, image, &allowed_changes_map, path),
+            ContainerImage::Gif(gif) => encode_into_gif_into_path_with_options(message_bytes, options, gif, &allowed_changes_map, path),
+            ContainerImage::Apng(apng) => encode_into_apng_into_path_with_options(message_bytes, options, apng, &allowed_changes_map, path),
+            ContainerImage::Raw(file) => encode_into_raw_file_into_path_with_options(message_bytes, options, file, &allowed_changes_map, path),
+            _ => unreachable!(),
+        }
+    }
+
+    /// How long a message can be with the selection algorithm and the encoding options (see get_capacity_report).
+    ///     embed_in_alpha is ignored for images without alpha channel, channel_weighting as in encode_into_path
+    pub fn capacity_report(&self, selection_algorithm: &SelectionAlgorithm, embed_in_alpha: bool, channel_weighting: ChannelWeighting, options: &EncodingOptions) -> DifCodeResult<CapacityReport> {
+        self.check_channel_weighting(channel_weighting)?;
+        //attempt to fit a message as long as the container, the map creator stops once no index can take any more bits
+        //    (so every round changes all changeable indices and the map does not depend on the rng)
+        let attempted_length_in_bits = self.len() * self.num_bits_per_value();
+        match self {
+            ContainerImage::Wav8(wav) => return wav_capacity_report(selection_algorithm, wav, attempted_length_in_bits, options),
+            ContainerImage::Wav16(wav) => return wav_capacity_report(selection_algorithm, wav, attempted_length_in_bits, options),
+            ContainerImage::Wav24(wav) => return wav_capacity_report(selection_algorithm, wav, attempted_length_in_bits, options),
+            _ => {}
+        }
+        if let ContainerImage::Jpeg(jpeg) = self {
+            let mut allowed_changes_map = vec![0u16; jpeg.len()];
+            ignore_capacity_reached(selection_algorithm.write_jpeg_map(attempted_length_in_bits, jpeg, &mut thread_rng(), &mut allowed_changes_map))?;
+            return Ok(get_capacity_report(options, jpeg, &allowed_changes_map))
+        }
+        if let Some(original) = self.as_container16() {
+            let mut allowed_changes_map = vec![0u16; original.len()];
+            ignore_capacity_reached(selection_algorithm.write_map16(attempted_length_in_bits, self, &mut thread_rng(), &mut allowed_changes_map))?;
+            return Ok(get_capacity_report(options, original, &allowed_changes_map))
+        }
+
+        let mut allowed_changes_map = vec![0u8; self.len()];
+        ignore_capacity_reached(selection_algorithm.write_map(attempted_length_in_bits, self, embed_in_alpha, channel_weighting, &mut thread_rng(), &mut allowed_changes_map))?;
+        Ok(get_capacity_report(options, self.as_container()?, &allowed_changes_map))
+    }
+
+    /// Decodes from the pair (order irrelevant), see with_matching.
+    pub fn decode_with_report(key: Option<&DifCodeKey>, first: &ContainerImage, second: &ContainerImage) -> DifCodeResult<(DecodingReport, Vec<u8>)> {
+        ContainerImage::with_matching(first, second,
+                                      |first, second| decode_into_vec_with_report(key, first, second),
+                                      |first, second| decode_into_vec_with_report(key, first, second),
+                                      |first, second| decode_into_vec_with_report(key, first, second))?
+    }
+    /// The (number of changed channel values, encoded message length in bits) between the pair, see with_matching.
+    pub fn compare(first: &ContainerImage, second: &ContainerImage) -> DifCodeResult<(usize, usize)> {
+        ContainerImage::with_matching(first, second, count_changes, count_changes, count_changes)
+    }
+    /// The quality metrics of the encoded image compared to the original, see with_matching.
+    ///     Only for pixel images, a jpeg, gif or animated png has to be opened as pixels to measure its visible change.
+    pub fn quality_metrics(original: &ContainerImage, encoded: &ContainerImage) -> DifCodeResult<QualityMetrics> {
+        if !original.is_pixel_image() || !encoded.is_pixel_image() {
+            return Err(DifCodeError::Internal("quality metrics are only supported for pixel images"))
+        }
+        if original.width() != encoded.width() || original.height() != encoded.height() {
+            return Err(DifCodeError::InternalMismatchedContainerSizes)
+        }
+        let (width, height) = (original.width(), original.height());
+        ContainerImage::with_matching(original, encoded,
+                                      |original, encoded| get_quality_metrics_of_planes(original, encoded, width, height),
+                                      |original, encoded| get_quality_metrics_of_planes(original, encoded, width, height),
+                                      |original, encoded| get_quality_metrics_of_planes(original, encoded, width, height))?
+    }
+    /// The steganalysis scores of the color channels (alpha is left out), only for 8 bit pixel images.
+    pub fn steganalysis_scores(&self) -> DifCodeResult<SteganalysisScores> {
+        match self {
+            ContainerImage::Rgb(image) => get_steganalysis_scores(image),
+            ContainerImage::Rgba(image) => get_steganalysis_scores(&DifCodeImage::from(DynamicImage::ImageRgba8(image.raw().clone()).into_rgb8())),
+            ContainerImage::Luma(image) => get_steganalysis_scores_of_planes(image, image.width(), image.height()),
+            _ => Err(DifCodeError::Internal("steganalysis is only supported for 8 bit pixel images"))
+        }
+    }
+    pub fn steganalysis_report(original: &ContainerImage, encoded: &ContainerImage) -> DifCodeResult<SteganalysisReport> {
+        if original.width() != encoded.width() || original.height() != encoded.height() {
+            return Err(DifCodeError::InternalMismatchedContainerSizes)
+        }
+        Ok(SteganalysisReport { original: original.steganalysis_scores()?, encoded: encoded.steganalysis_scores()? })
+    }
+    pub fn is_pixel_image(&self) -> bool {
+        matches!(self, ContainerImage::Rgb(_) | ContainerImage::Rgba(_) | ContainerImage::Rgb16(_) | ContainerImage::Luma(_) | ContainerImage::Luma16(_))
+    }
+
+    /// Decoding requires both images to be the same container. If one of them has fewer channels (of the same bit depth)
+    ///     it is promoted to the other, i.e. grayscale is treated as rgb with identical channels and rgb as fully opaque rgba.
+    ///     This also decodes images encoded as rgb from a grayscale original.
+    fn with_matching<R, F8, F16, F24>(first: &ContainerImage, second: &ContainerImage, on_8_bit: F8, on_16_bit: F16, on_24_bit: F24) -> DifCodeResult<R>
+        where F8: FnOnce(&dyn EncodingContainer, &dyn EncodingContainer) -> R,
+              F16: FnOnce(&dyn EncodingContainer<u16>, &dyn EncodingContainer<u16>) -> R,
+              F24: FnOnce(&dyn EncodingContainer<u32>, &dyn EncodingContainer<u32>) -> R {
+        let (promoted_first, promoted_second) = (first.promoted_to(second), second.promoted_to(first));
+        let first = promoted_first.as_ref().unwrap_or(first);
+        let second = promoted_second.as_ref().unwrap_or(second);
+
+        if let (Some(first), Some(second)) = (first.as_container24(), second.as_container24()) {
+            return Ok(on_24_bit(first, second))
+        }
+        match (first.as_container16(), second.as_container16()) {
+            (Some(first), Some(second)) => Ok(on_16_bit(first, second)),
+            _ => Ok(on_8_bit(first.as_container()?, second.as_container()?))
+        }
+    }
+    fn promoted_to(&self, other: &ContainerImage) -> Option<ContainerImage> {
+        match (self, other) {
+            (ContainerImage::Luma(image), ContainerImage::Rgb(_)) => Some(ContainerImage::Rgb(DifCodeImage::from(DynamicImage::ImageLuma8(image.raw().clone()).into_rgb8()))),
+            (ContainerImage::Luma(image), ContainerImage::Rgba(_)) => Some(ContainerImage::Rgba(DifCodeRgbaImage::from(DynamicImage::ImageLuma8(image.raw().clone()).into_rgba8()))),
+            (ContainerImage::Rgb(image), ContainerImage::Rgba(_)) => Some(ContainerImage::Rgba(DifCodeRgbaImage::from(DynamicImage::ImageRgb8(image.raw().clone()).into_rgba8()))),
+            (ContainerImage::Luma16(image), ContainerImage::Rgb16(_)) => Some(ContainerImage::Rgb16(DifCodeImage16::from(DynamicImage::ImageLuma16(image.raw().clone()).into_rgb16()))),
+            _ => None
+        }
+    }
+
+    //the 8 bit view, 16 bit images cannot be paired with 8 bit ones
+    fn as_container(&self) -> DifCodeResult<&dyn EncodingContainer> {
+        match self {
+            ContainerImage::Rgb(image) => Ok(image),
+            ContainerImage::Rgba(image) => Ok(image),
+            ContainerImage::Luma(image) => Ok(image),
+            ContainerImage::Gif(gif) => Ok(gif),
+            ContainerImage::Apng(apng) => Ok(apng),
+            ContainerImage::Wav8(wav) => Ok(wav),
+            ContainerImage::Raw(file) => Ok(file),
+            ContainerImage::Rgb16(_) | ContainerImage::Luma16(_) | ContainerImage::Jpeg(_) | ContainerImage::Wav16(_) | ContainerImage::Wav24(_) =>
+                Err(DifCodeError::Internal("16 bit images, jpegs and 16/24 bit wavs can only be paired with their own kind")),
+        }
+    }
+    fn check_channel_weighting(&self, channel_weighting: ChannelWeighting) -> DifCodeResult<()> {
+        match self {
+            ContainerImage::Rgb(_) | ContainerImage::Rgba(_) | ContainerImage::Luma(_) => Ok(()),
+            _ if channel_weighting == ChannelWeighting::Uniform => Ok(()),
+            _ => Err(DifCodeError::Internal("channel weighting is only supported for 8 bit images"))
+        }
+    }
+
+    fn as_container16(&self) -> Option<&dyn EncodingContainer<u16>> {
+        match self {
+            ContainerImage::Rgb16(image) => Some(image),
+            ContainerImage::Luma16(image) => Some(image),
+            ContainerImage::Jpeg(jpeg) => Some(jpeg),
+            ContainerImage::Wav16(wav) => Some(wav),
+            _ => None
+        }
+    }
+    fn as_container24(&self) -> Option<&dyn EncodingContainer<u32>> {
+        match self {
+            ContainerImage::Wav24(wav) => Some(wav),
+            _ => None
+        }
+    }
+}
+
+fn encode_wav_into_path<T: Sample>(message_bytes: &[u8], options: &EncodingOptions, selection_algorithm: &SelectionAlgorithm, wav: &DifCodeWav<T>, rng: &mut dyn RngCore, path: &str) -> DifCodeResult<()> {
+    let mut allowed_changes_map = vec![T::default(); wav.len()];
+    selection_algorithm.write_wav_map(options.get_embedded_length_in_bits(message_bytes.len()), wav, rng, &mut allowed_changes_map).map_err(|err| options.payload_capacity_error(err))?;
+    encode_into_wav_into_path_with_options(message_bytes, options, wav, &allowed_changes_map, path)
+}
+fn wav_capacity_report<T: Sample>(selection_algorithm: &SelectionAlgorithm, wav: &DifCodeWav<T>, attempted_length_in_bits: usize, options: &EncodingOptions) -> DifCodeResult<CapacityReport> {
+    let mut allowed_changes_map = vec![T::default(); wav.len()];
+    ignore_capacity_reached(selection_algorithm.write_wav_map(attempted_length_in_bits, wav, &mut thread_rng(), &mut allowed_changes_map))?;
+    Ok(get_capacity_report(options, wav, &allowed_changes_map))
+}
+
+/// Which part of a file is the container.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContainerFormat {
+    /// the pixels (of the first frame), decoded like any other image
+    Pixels,
+    /// the dct coefficients of a baseline jpeg
+    Jpeg,
+    /// the palettes of a gif (all frames)
+    Gif,
+    /// the pixels of all frames of an animated png
+    Apng,
+    /// the samples of a pcm wav
+    Wav,
+    /// all bytes of any file, except the protected ones (see DifCodeRawFile), never detected, only if explicitly chosen
+    Raw,
+}
+impl ContainerFormat {
+    /// The format an original is encoded in for the given output path: a .jpg/.jpeg output is coded in the dct coefficients, a .gif output in the palettes
+    ///     and an animated png original into a .png output in all frames, a .wav output in the samples. Otherwise the pixels are used.
+    pub fn for_output(original: &[u8], output_path: &str) -> ContainerFormat {
+        if has_extension(output_path, &["jpg", "jpeg"]) {
+            ContainerFormat::Jpeg
+        } else if has_extension(output_path, &["gif"]) {
+            ContainerFormat::Gif
+        } else if has_extension(output_path, &["wav"]) {
+            ContainerFormat::Wav
+        } else if has_extension(output_path, &["png", "apng"]) && is_animated_png(original) {
+            ContainerFormat::Apng
+        } else {
+            ContainerFormat::Pixels
+        }
+    }
+    /// The format a pair is decoded in, only if both are of the same format (e.g. both gifs) it is used, otherwise their pixels are compared.
+    pub fn for_pair(first_path: &str, first: &[u8], second_path: &str, second: &[u8]) -> ContainerFormat {
+        let format = ContainerFormat::of(first_path, first);
+        if format == ContainerFormat::of(second_path, second) { format } else { ContainerFormat::Pixels }
+    }
+    /// Jpegs are recognized by their path (or url), gifs, animated pngs and wavs by their content.
+    pub fn of(path: &str, buffer: &[u8]) -> ContainerFormat {
+        if has_extension(path, &["jpg", "jpeg"]) {
+            ContainerFormat::Jpeg
+        } else if is_wav(buffer) {
+            ContainerFormat::Wav
+        } else if is_gif(buffer) {
+            ContainerFormat::Gif
+        } else if is_animated_png(buffer) {
+            ContainerFormat::Apng
+        } else {
+            ContainerFormat::Pixels
+        }
+    }
+}
+impl fmt::Display for ContainerFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerFormat::Pixels => write!(f, "image"),
+            ContainerFormat::Jpeg => write!(f, "baseline jpeg"),
+            ContainerFormat::Gif => write!(f, "gif"),
+            ContainerFormat::Apng => write!(f, "animated png"),
+            ContainerFormat::Wav => write!(f, "pcm wav"),
+            ContainerFormat::Raw => write!(f, "raw file"),
+        }
+    }
+}
+
+fn has_extension(path: &str, extensions: &[&str]) -> bool {
+    Path::new(path).extension().and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|extension| ext.eq_ignore_ascii_case(extension)))
+}
+
+fn count_changes<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> (usize, usize) {
+    let num_changed = (0..original.len()).filter(|i| original[*i] != encoded[*i]).count();
+    (num_changed, get_encoded_message_length_in_bits(original, encoded))
+}
+
+fn ignore_capacity_reached(result: DifCodeResult<()>) -> DifCodeResult<()> {
+    match result {
+        Ok(()) | Err(DifCodeError::InternalCapacityReached(_)) => Ok(()),
+        Err(err) => Err(err)
+    }
+}
+
+impl fmt::Display for ContainerImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerImage::Rgb(image) => write!(f, "{}", image),
+            ContainerImage::Rgba(image) => write!(f, "{}", image),
+            ContainerImage::Rgb16(image) => write!(f, "{}", image),
+            ContainerImage::Luma(image) => write!(f, "{}", image),
+            ContainerImage::Luma16(image) => write!(f, "{}", image),
+            ContainerImage::Jpeg(jpeg) => write!(f, "{}", jpeg),
+            ContainerImage::Gif(gif) => write!(f, "{}", gif),
+            ContainerImage::Apng(apng) => write!(f, "{}", apng),
+            ContainerImage::Wav8(wav) => write!(f, "{}", wav),
+            ContainerImage::Wav16(wav) => write!(f, "{}", wav),
+            ContainerImage::Wav24(wav) => write!(f, "{}", wav),
+            ContainerImage::Raw(file) => write!(f, "{}", file),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use jokrey_utilities::general::is_odd;
 use crate::rand::Rng;
+use crate::difference_encoder::key::DifCodeKey;
+use crate::util::Sample;
 
 pub fn get_min_num_bits_encodable(difference: u8) -> u8 {
     //LOGIC: a dif=1 cannot necessarily encode a bit, dif=2 always can(regardless of encoding table permutation and actual bit string).
@@ -53,6 +55,72 @@ fn test_min_num_bits_encodable() {
     assert_eq!(7, get_min_num_bits_encodable(255));//max == 8 (though unlikely)
 }
 
+//the same boundaries for any sample type, the range of differences just continues further for wider samples
+pub fn get_min_num_bits_encodable_of_sample<T: Sample>(difference: T) -> u8 {
+    if difference == T::MAX {return T::MAX_NUM_BITS - 1;}//otherwise difference +1 would overflow
+    get_num_bits_decodable_of_sample(T::from_u32(difference.to_u32() + 1)).saturating_sub(1)
+}
+pub fn get_num_bits_decodable_of_sample<T: Sample>(difference: T) -> u8 {
+    get_max_num_bits_encodable_of_sample(difference)
+}
+pub fn get_max_num_bits_encodable_of_sample<T: Sample>(difference: T) -> u8 {
+    //0 -> 0, 1..=2 -> 1, 3..=6 -> 2, 7..=14 -> 3, ... i.e. floor(log2(difference + 1))
+    (31 - (difference.to_u32() + 1).leading_zeros()) as u8
+}
+//the encoder always uses the min number of bits and tries one more. For random message bits the differences of one more bit are uniformly
+//    distributed over the 2^(min+1) consecutive values from 2^(min+1) - 1 upwards, the share of them within the difference is the chance that it fits.
+//    The result is a dyadic fraction, so sums of it are exact (independent of the order of summation) for any realistic container.
+pub fn get_expected_num_bits_encodable_of_sample<T: Sample>(difference: T) -> f64 {
+    let min = get_min_num_bits_encodable_of_sample(difference);
+    let num_values = 1u64 << (min as u64 + 1);
+    let num_fitting = (difference.to_u32() as u64 + 2).saturating_sub(num_values).min(num_values);
+    min as f64 + num_fitting as f64 / num_values as f64
+}
+pub fn calculate_worst_case_difference_of_sample<T: Sample>(num_bits: u8) -> Option<T> {
+    //*2 + 2, i.e. 2^(num_bits+1) - 2
+    if num_bits >= T::MAX_NUM_BITS {
+        None //worst case for the max num bits is also out of bounds...
+    } else {
+        Some(T::from_u32((1u32 << (num_bits as u32 + 1)) - 2))
+    }
+}
+
+#[test]
+fn test_num_bits_of_sample_match_u8() {
+    for difference in 0..=255u8 {
+        assert_eq!(get_min_num_bits_encodable(difference), get_min_num_bits_encodable_of_sample(difference));
+        assert_eq!(get_max_num_bits_encodable(difference), get_max_num_bits_encodable_of_sample(difference));
+    }
+    for num_bits in 0..=8 {
+        assert_eq!(calculate_worst_case_difference_for(num_bits), calculate_worst_case_difference_of_sample::<u8>(num_bits));
+    }
+    assert_eq!(15, get_min_num_bits_encodable_of_sample(u16::MAX));
+    assert_eq!(16, get_max_num_bits_encodable_of_sample(u16::MAX));
+    assert_eq!(15, get_max_num_bits_encodable_of_sample(65534u16));
+    assert_eq!(Some(65534u16), calculate_worst_case_difference_of_sample(15));
+    assert_eq!(None, calculate_worst_case_difference_of_sample::<u16>(16));
+    assert_eq!(23, get_min_num_bits_encodable_of_sample(<u32 as Sample>::MAX));
+    assert_eq!(24, get_max_num_bits_encodable_of_sample(<u32 as Sample>::MAX));
+    assert_eq!(Some((1u32 << 24) - 2), calculate_worst_case_difference_of_sample(23));
+    assert_eq!(None, calculate_worst_case_difference_of_sample::<u32>(24));
+}
+
+#[test]
+fn test_expected_num_bits_encodable() {
+    for difference in 0..=255u8 {
+        let min = get_min_num_bits_encodable(difference) as usize;
+        let num_fitting = (0..1u32 << (min + 1)).filter(|bit_string| {
+            let bits: Vec<bool> = (0..min + 1).map(|i| bit_string >> i & 1 == 1).collect();
+            dynamic_bits_to_difference_if_allowed(0, &bits, difference).is_some()
+        }).count();
+        let expected = min as f64 + num_fitting as f64 / (1u32 << (min + 1)) as f64;
+        assert_eq!(expected, get_expected_num_bits_encodable_of_sample(difference));
+        assert!(expected >= get_min_num_bits_encodable(difference) as f64 && expected <= get_max_num_bits_encodable(difference) as f64);
+    }
+    assert_eq!(0.5, get_expected_num_bits_encodable_of_sample(1u8));
+    assert_eq!(15.0 + 1.0 / 65536.0, get_expected_num_bits_encodable_of_sample(u16::MAX));
+}
+
 pub fn calculate_worst_case_difference_for(num_bits: u8) -> Option<u8> {
     //*2 + 2
     match num_bits {
@@ -131,8 +199,19 @@ pub fn static_difference_to_bits(mut difference: u8, output_bits: &mut [bool]) {
 
 
 
+//the code table at each index is permuted by an rng seeded with the index (and the key, if given)
+fn code_table_rng(key: Option<&DifCodeKey>, index: usize) -> rand::rngs::StdRng {
+    match key {
+        None => rand::SeedableRng::seed_from_u64(index as u64),
+        Some(key) => key.rng_at(index)
+    }
+}
+
 pub fn dynamic_bits_to_difference_if_allowed(index: usize, bits: &[bool], max_allowed_difference: u8) -> Option<u8> {
-    let encoded_difference = dynamic_bits_to_difference(index, bits)?;
+    dynamic_bits_to_difference_if_allowed_with_key(None, index, bits, max_allowed_difference)
+}
+pub fn dynamic_bits_to_difference_if_allowed_with_key<T: Sample>(key: Option<&DifCodeKey>, index: usize, bits: &[bool], max_allowed_difference: T) -> Option<T> {
+    let encoded_difference = dynamic_bits_to_difference_with_key(key, index, bits)?;
     if encoded_difference <= max_allowed_difference {
         Some(encoded_difference)
     } else {
@@ -140,36 +219,42 @@ pub fn dynamic_bits_to_difference_if_allowed(index: usize, bits: &[bool], max_al
     }
 }
 pub fn dynamic_bits_to_difference(index: usize, bits: &[bool]) -> Option<u8> {
-    let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(index as u64);
+    dynamic_bits_to_difference_with_key(None, index, bits)
+}
+pub fn dynamic_bits_to_difference_with_key<T: Sample>(key: Option<&DifCodeKey>, index: usize, bits: &[bool]) -> Option<T> {
+    let mut rng = code_table_rng(key, index);
 
-    let mut acc: u8 = 0;
+    let mut acc: u32 = 0;
     for i in 0..bits.len() {
         let rand = rng.gen_bool(0.5);
-        let single_bit_encoded:u8 = if bits[i] ^ rand { 2 } else {1};
+        let single_bit_encoded:u32 = if bits[i] ^ rand { 2 } else {1};
 
-        // acc += single_bit_encoded * 2_u8.pow(i as u32); //WOULD BE THE SAME AS BELOW, BUT WITHOUT OVERFLOW HANDLING
-        if let Some(influence_multiplier) = 2_u8.checked_pow(i as u32) {
-            if let Some(add_summand) = single_bit_encoded.checked_mul(influence_multiplier) {
-                if let Some(new_acc) = acc.checked_add(add_summand) {
-                    acc = new_acc;
-                    continue;
-                }
-            }
+        //overflow, if the sum (or already the influence multiplier) exceeds the sample type
+        if i >= T::MAX_NUM_BITS as usize {
+            return None;
+        }
+        acc += single_bit_encoded << i;
+        if acc > T::MAX.to_u32() {
+            return None;
         }
-        return None; //only arrives here if any of the previous overflow checks FAIL
     }
-    return Some(acc);
+    return Some(T::from_u32(acc));
 }
 
 /// Note: output bits must have length of get_num_bits_encodable(difference) , otherwise the code will raise a panic
-pub fn dynamic_difference_to_bits(index: usize, mut difference: u8, output_bits: &mut [bool]) {
-    let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(index as u64);
+pub fn dynamic_difference_to_bits(index: usize, difference: u8, output_bits: &mut [bool]) {
+    dynamic_difference_to_bits_with_key(None, index, difference, output_bits)
+}
+/// Note: has to be given the same key as the encoding, otherwise the bits are garbage
+pub fn dynamic_difference_to_bits_with_key<T: Sample>(key: Option<&DifCodeKey>, index: usize, difference: T, output_bits: &mut [bool]) {
+    let mut rng = code_table_rng(key, index);
 
+    let mut difference = difference.to_u32();
     for i in 0..output_bits.len() {
         let rand = rng.gen_bool(0.5);
-        let is_odd = is_odd(difference);
-        output_bits[i] = !is_odd ^ rand;
-        if is_odd {
+        let difference_is_odd = difference % 2 == 1;
+        output_bits[i] = !difference_is_odd ^ rand;
+        if difference_is_odd {
             difference = difference / 2;
         } else {
             difference = difference / 2 - 1;
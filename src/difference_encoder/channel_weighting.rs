@@ -0,0 +1,37 @@
+use crate::util::Sample;
+
+//rec. 601 luma coefficients, i.e. how much a change of each channel contributes to the perceived brightness change
+const LUMA_WEIGHTS: [f32; 3] = [0.299, 0.587, 0.114];
+
+/// Scales the maximum change of the color channels (r, g, b) of an image, any further channel (alpha) is never scaled.
+/// A channel with a scale below 1 takes fewer or smaller changes, a channel with a scale above 1 takes more of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChannelWeighting {
+    /// All channels are changed alike.
+    #[default]
+    Uniform,
+    /// Each channel is scaled inversely to its luma weight, so that every channel contributes the same share of the perceived change
+    ///    (green is scaled to ~0.57, red to ~1.1 and blue to ~2.9). Capacity moves from green to blue, at about the same visual distortion.
+    Perceptual,
+    /// The scales of r, g and b.
+    Custom([f32; 3]),
+}
+impl ChannelWeighting {
+    pub fn scale_of(&self, channel: u32) -> f32 {
+        match (self, channel) {
+            (_, channel) if channel >= 3 => 1.0,
+            (ChannelWeighting::Uniform, _) => 1.0,
+            (ChannelWeighting::Perceptual, channel) => 1.0 / (3.0 * LUMA_WEIGHTS[channel as usize]),
+            (ChannelWeighting::Custom(scales), channel) => scales[channel as usize].max(0.0),
+        }
+    }
+
+    /// The max difference of a value of the channel, rounded and capped at T::MAX.
+    pub fn weigh<T: Sample>(&self, channel: u32, max_difference: T) -> T {
+        let scale = self.scale_of(channel);
+        if scale == 1.0 {
+            return max_difference
+        }
+        T::from_u32((max_difference.to_u32() as f64 * scale as f64).round().min(T::MAX.to_u32() as f64) as u32)
+    }
+}
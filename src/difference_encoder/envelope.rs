@@ -0,0 +1,151 @@
+use crate::util::{DifCodeError, DifCodeResult};
+
+//The envelope is what is actually encoded into the container:
+//    magic(2) | version(1) | flags(1) | payload length(4, big endian) | crc32(4, big endian) | payload
+//The crc covers version, flags, length and the payload.
+pub const MAGIC: [u8; 2] = [0xD1, 0xFC];
+pub const FORMAT_VERSION: u8 = 1;
+pub const HEADER_LENGTH_IN_BYTES: usize = 12;
+pub const HEADER_LENGTH_IN_BITS: usize = HEADER_LENGTH_IN_BYTES * 8;
+
+/// Describes how the payload was transformed before it was enveloped, so the decoder knows how to undo it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PayloadFlags(u8);
+impl PayloadFlags {
+    pub const NONE: PayloadFlags = PayloadFlags(0);
+    pub const ENCRYPTED: PayloadFlags = PayloadFlags(0b0000_0001);
+    pub const COMPRESSED: PayloadFlags = PayloadFlags(0b0000_0010);
+
+    pub fn from_bits(bits: u8) -> PayloadFlags { PayloadFlags(bits) }
+    pub fn bits(&self) -> u8 { self.0 }
+    pub fn contains(&self, other: PayloadFlags) -> bool { self.0 & other.0 == other.0 }
+    pub fn with(&self, other: PayloadFlags) -> PayloadFlags { PayloadFlags(self.0 | other.0) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadHeader {
+    pub version: u8,
+    pub flags: PayloadFlags,
+    pub payload_length: u32,
+    pub crc: u32,
+}
+
+
+
+pub fn wrap(payload: &[u8], flags: PayloadFlags) -> DifCodeResult<Vec<u8>> {
+    if payload.len() > u32::MAX as usize {
+        return Err(DifCodeError::Internal("payload too long for envelope"))
+    }
+    let payload_length = payload.len() as u32;
+
+    let mut enveloped = Vec::with_capacity(HEADER_LENGTH_IN_BYTES + payload.len());
+    enveloped.extend_from_slice(&create_header(flags, payload_length, calculate_crc(FORMAT_VERSION, flags, payload_length, payload)));
+    enveloped.extend_from_slice(payload);
+    Ok(enveloped)
+}
+pub(crate) fn create_header(flags: PayloadFlags, payload_length: u32, crc: u32) -> [u8; HEADER_LENGTH_IN_BYTES] {
+    let mut header = [0u8; HEADER_LENGTH_IN_BYTES];
+    header[0..2].copy_from_slice(&MAGIC);
+    header[2] = FORMAT_VERSION;
+    header[3] = flags.bits();
+    header[4..8].copy_from_slice(&payload_length.to_be_bytes());
+    header[8..12].copy_from_slice(&crc.to_be_bytes());
+    header
+}
+
+pub fn read_header(enveloped: &[u8]) -> DifCodeResult<PayloadHeader> {
+    if enveloped.len() < HEADER_LENGTH_IN_BYTES {
+        return Err(DifCodeError::InvalidHeader("no header found (too few bits decoded)"))
+    }
+    if enveloped[0..2] != MAGIC {
+        return Err(DifCodeError::InvalidHeader("no header found (magic number mismatch)"))
+    }
+    let version = enveloped[2];
+    if version != FORMAT_VERSION {
+        return Err(DifCodeError::UnsupportedFormatVersion(version))
+    }
+    Ok(PayloadHeader {
+        version,
+        flags: PayloadFlags::from_bits(enveloped[3]),
+        payload_length: u32::from_be_bytes([enveloped[4], enveloped[5], enveloped[6], enveloped[7]]),
+        crc: u32::from_be_bytes([enveloped[8], enveloped[9], enveloped[10], enveloped[11]]),
+    })
+}
+
+/// Validates the envelope and returns its header and payload.
+/// Bytes decoded after the announced payload are ignored.
+/// Decoding validates the envelope while it is streamed instead (see streaming).
+#[cfg(test)]
+pub fn unwrap(enveloped: &[u8]) -> DifCodeResult<(PayloadHeader, &[u8])> {
+    let header = read_header(enveloped)?;
+    let payload_end = HEADER_LENGTH_IN_BYTES + header.payload_length as usize;
+    if enveloped.len() < payload_end {
+        return Err(DifCodeError::InvalidHeader("payload shorter than announced in header"))
+    }
+    let payload = &enveloped[HEADER_LENGTH_IN_BYTES..payload_end];
+
+    let actual = calculate_crc(header.version, header.flags, header.payload_length, payload);
+    if actual != header.crc {
+        return Err(DifCodeError::ChecksumMismatch { expected: header.crc, actual })
+    }
+    Ok((header, payload))
+}
+
+
+
+fn calculate_crc(version: u8, flags: PayloadFlags, payload_length: u32, payload: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    crc = crc32_update(crc, &[version, flags.bits()]);
+    crc = crc32_update(crc, &payload_length.to_be_bytes());
+    crc = crc32_update(crc, payload);
+    !crc
+}
+
+/// The crc of an envelope (see calculate_crc), updated while the payload is streamed.
+/// The payload length, which is covered before the payload, is only required at the end.
+pub(crate) struct StreamingCrc {
+    //without the initial inversion, i.e. as if the register started at zero
+    payload_crc: u32,
+    payload_length: usize,
+}
+impl StreamingCrc {
+    pub(crate) fn new() -> StreamingCrc {
+        StreamingCrc { payload_crc: 0, payload_length: 0 }
+    }
+    pub(crate) fn update(&mut self, payload: &[u8]) {
+        self.payload_crc = crc32_update(self.payload_crc, payload);
+        self.payload_length += payload.len();
+    }
+    pub(crate) fn payload_length(&self) -> usize {
+        self.payload_length
+    }
+    pub(crate) fn finish(&self, version: u8, flags: PayloadFlags) -> u32 {
+        //the register is linear in its start value and the input, so crc(prefix | payload) = crc(prefix | zeros) ^ crc_from_zero(payload)
+        let mut crc = !0u32;
+        crc = crc32_update(crc, &[version, flags.bits()]);
+        crc = crc32_update(crc, &(self.payload_length as u32).to_be_bytes());
+        let zeros = [0u8; 1024];
+        let mut remaining = self.payload_length;
+        while remaining > 0 {
+            let num_zeros = remaining.min(zeros.len());
+            crc = crc32_update(crc, &zeros[..num_zeros]);
+            remaining -= num_zeros;
+        }
+        !(crc ^ self.payload_crc)
+    }
+}
+
+/// Standard (ieee, reflected) crc32 over the given bytes, to check crc32_update against the well known check value.
+#[cfg(test)]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !crc32_update(!0u32, bytes)
+}
+fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
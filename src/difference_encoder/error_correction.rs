@@ -0,0 +1,332 @@
+use crate::util::{DifCodeError, DifCodeResult};
+
+//Reed-Solomon forward error correction over GF(2^8), applied to the enveloped message before it is encoded.
+//The protected stream is:
+//    preamble(6 bytes: marker, parity symbols, data length(4, big endian)), repeated 3 times | blocks
+//Each block holds up to 255 - parity_symbols data bytes, followed by parity_symbols parity bytes,
+//    and can repair up to parity_symbols / 2 altered bytes.
+//The preamble is read by a bitwise majority vote, so it survives the same kind of damage.
+//
+//Changes that keep the number of bits decodable at a value (i.e. the difference stays within its bit range) are byte substitutions,
+//    changes across the ranges insert or drop bits, the decoder resynchronizes the blocks after them (see resynchronization).
+const MARKER: u8 = 0xEC;
+pub(crate) const PREAMBLE_LENGTH_IN_BYTES: usize = 6;
+const PREAMBLE_REPETITIONS: usize = 3;
+pub const PROTECTED_PREAMBLE_LENGTH_IN_BYTES: usize = PREAMBLE_LENGTH_IN_BYTES * PREAMBLE_REPETITIONS;
+pub(crate) const MAX_BLOCK_LENGTH: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCorrectionReport {
+    pub parity_symbols: u8,
+    pub num_blocks: usize,
+    /// Number of bytes(symbols) that were altered and have been repaired
+    pub corrected_symbols: usize,
+    /// Number of altered values that decoded to a different number of bits, after which the blocks have been resynchronized
+    pub resynchronized_values: usize,
+}
+
+pub fn validate_parity_symbols(parity_symbols: u8) -> DifCodeResult<()> {
+    if parity_symbols < 2 || parity_symbols as usize >= MAX_BLOCK_LENGTH {
+        Err(DifCodeError::Internal("number of error correction parity symbols must be within [2, 254]"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn get_protected_length_in_bytes(data_length: usize, parity_symbols: u8) -> usize {
+    let data_per_block = MAX_BLOCK_LENGTH - parity_symbols as usize;
+    let num_blocks = data_length.div_ceil(data_per_block);
+    PROTECTED_PREAMBLE_LENGTH_IN_BYTES + data_length + num_blocks * parity_symbols as usize
+}
+/// Inverse of get_protected_length_in_bytes, i.e. the maximum number of data bytes that can be protected within the given length.
+pub fn get_max_data_length_in_bytes(protected_length: usize, parity_symbols: u8) -> usize {
+    let blocks_length = protected_length.saturating_sub(PROTECTED_PREAMBLE_LENGTH_IN_BYTES);
+    let full_blocks = blocks_length / MAX_BLOCK_LENGTH;
+    let remaining = blocks_length % MAX_BLOCK_LENGTH;
+    full_blocks * (MAX_BLOCK_LENGTH - parity_symbols as usize) + remaining.saturating_sub(parity_symbols as usize)
+}
+
+/// Whether the given stream starts with an error correction preamble (as opposed to directly with an envelope).
+pub fn is_protected(stream: &[u8]) -> bool {
+    stream.len() >= PROTECTED_PREAMBLE_LENGTH_IN_BYTES && read_preamble(stream)[0] == MARKER
+}
+
+
+
+pub fn protect(data: &[u8], parity_symbols: u8) -> DifCodeResult<Vec<u8>> {
+    let coder = BlockCoder::new(parity_symbols)?;
+    if data.len() > u32::MAX as usize {
+        return Err(DifCodeError::Internal("data too long for error correction"))
+    }
+
+    let mut protected = create_preamble(data.len() as u32, parity_symbols);
+    protected.reserve(get_protected_length_in_bytes(data.len(), parity_symbols));
+    for block in data.chunks(coder.data_per_block()) {
+        coder.protect_block(block, &mut protected);
+    }
+    Ok(protected)
+}
+
+/// Repairs and returns the data of a stream created by protect.
+/// Bytes after the last block are ignored.
+/// Decoding repairs the blocks while they are decoded instead (see resynchronization).
+#[cfg(test)]
+pub fn repair(protected: &[u8]) -> DifCodeResult<(Vec<u8>, ErrorCorrectionReport)> {
+    let (parity_symbols, data_length) = read_protected_preamble(protected)?;
+    if protected.len() < get_protected_length_in_bytes(data_length, parity_symbols) {
+        return Err(DifCodeError::InvalidHeader("error corrected stream shorter than announced in preamble"))
+    }
+
+    let coder = BlockCoder::new(parity_symbols)?;
+    let mut report = ErrorCorrectionReport { parity_symbols, num_blocks: 0, corrected_symbols: 0, resynchronized_values: 0 };
+    let mut data = Vec::with_capacity(data_length);
+    let mut block_start = PROTECTED_PREAMBLE_LENGTH_IN_BYTES;
+    while data.len() < data_length {
+        let block_data_length = coder.data_per_block().min(data_length - data.len());
+        let block_end = block_start + block_data_length + parity_symbols as usize;
+        let mut block = protected[block_start..block_end].to_vec();
+        report.corrected_symbols += coder.repair_block(&mut block)
+            .ok_or(DifCodeError::UncorrectableErrors { block: report.num_blocks })?;
+        data.extend_from_slice(&block[..block_data_length]);
+        report.num_blocks += 1;
+        block_start = block_end;
+    }
+    Ok((data, report))
+}
+
+pub(crate) fn create_preamble(data_length: u32, parity_symbols: u8) -> Vec<u8> {
+    let mut preamble = Vec::with_capacity(PROTECTED_PREAMBLE_LENGTH_IN_BYTES);
+    for _ in 0..PREAMBLE_REPETITIONS {
+        preamble.push(MARKER);
+        preamble.push(parity_symbols);
+        preamble.extend_from_slice(&data_length.to_be_bytes());
+    }
+    preamble
+}
+/// The (parity symbols, data length) announced in the preamble of a protected stream.
+pub(crate) fn read_protected_preamble(protected: &[u8]) -> DifCodeResult<(u8, usize)> {
+    if !is_protected(protected) {
+        return Err(DifCodeError::InvalidHeader("no error correction preamble found"))
+    }
+    let preamble = read_preamble(protected);
+    let parity_symbols = preamble[1];
+    validate_parity_symbols(parity_symbols).map_err(|_| DifCodeError::InvalidHeader("invalid error correction preamble"))?;
+    Ok((parity_symbols, u32::from_be_bytes([preamble[2], preamble[3], preamble[4], preamble[5]]) as usize))
+}
+
+/// Protects and repairs single blocks, for streams that are not available at once (see protect and repair).
+pub(crate) struct BlockCoder {
+    gf: GaloisField,
+    generator: Vec<u8>,
+    parity_symbols: u8,
+}
+impl BlockCoder {
+    pub(crate) fn new(parity_symbols: u8) -> DifCodeResult<BlockCoder> {
+        validate_parity_symbols(parity_symbols)?;
+        let gf = GaloisField::new();
+        let generator = gf.generator_polynomial(parity_symbols as usize);
+        Ok(BlockCoder { gf, generator, parity_symbols })
+    }
+    pub(crate) fn parity_symbols(&self) -> usize {
+        self.parity_symbols as usize
+    }
+    pub(crate) fn data_per_block(&self) -> usize {
+        MAX_BLOCK_LENGTH - self.parity_symbols as usize
+    }
+    /// Appends the data (at most data_per_block bytes) followed by its parity.
+    pub(crate) fn protect_block(&self, data: &[u8], output: &mut Vec<u8>) {
+        output.extend_from_slice(data);
+        output.extend_from_slice(&self.gf.calculate_parity(data, &self.generator));
+    }
+    /// Repairs the block (data followed by parity) in place, see GaloisField::correct.
+    pub(crate) fn repair_block(&self, block: &mut [u8]) -> Option<usize> {
+        self.gf.correct(block, self.parity_symbols as usize)
+    }
+}
+
+fn read_preamble(protected: &[u8]) -> [u8; PREAMBLE_LENGTH_IN_BYTES] {
+    let mut preamble = [0u8; PREAMBLE_LENGTH_IN_BYTES];
+    for i in 0..PREAMBLE_LENGTH_IN_BYTES {
+        let (a, b, c) = (protected[i], protected[i + PREAMBLE_LENGTH_IN_BYTES], protected[i + 2 * PREAMBLE_LENGTH_IN_BYTES]);
+        preamble[i] = (a & b) | (a & c) | (b & c); //bitwise majority
+    }
+    preamble
+}
+
+
+
+//polynomials are stored highest degree first (i.e. in the order of the bytes in a block)
+struct GaloisField {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+impl GaloisField {
+    fn new() -> GaloisField {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x: u16 = 1;
+        for (i, exp_i) in exp.iter_mut().take(255).enumerate() {
+            *exp_i = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d; //primitive polynomial x^8 + x^4 + x^3 + x^2 + 1
+            }
+        }
+        for i in 255..512 {
+            exp[i] = exp[i - 255];
+        }
+        GaloisField { exp, log }
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+        }
+    }
+    fn div(&self, a: u8, b: u8) -> u8 {
+        if a == 0 {
+            0
+        } else {
+            self.exp[(self.log[a as usize] as usize + 255 - self.log[b as usize] as usize) % 255]
+        }
+    }
+    fn pow_alpha(&self, power: usize) -> u8 {
+        self.exp[power % 255]
+    }
+
+    fn eval(&self, poly: &[u8], x: u8) -> u8 {
+        let mut y = 0;
+        for coefficient in poly {
+            y = self.mul(y, x) ^ coefficient;
+        }
+        y
+    }
+
+    fn generator_polynomial(&self, parity_symbols: usize) -> Vec<u8> {
+        let mut generator = vec![1u8];
+        for i in 0..parity_symbols {
+            //multiply with (x - alpha^i)
+            let root = self.pow_alpha(i);
+            let mut next = vec![0u8; generator.len() + 1];
+            for (j, coefficient) in generator.iter().enumerate() {
+                next[j] ^= coefficient;
+                next[j + 1] ^= self.mul(*coefficient, root);
+            }
+            generator = next;
+        }
+        generator
+    }
+
+    fn calculate_parity(&self, data: &[u8], generator: &[u8]) -> Vec<u8> {
+        //remainder of data * x^parity_symbols divided by the (monic) generator
+        let parity_symbols = generator.len() - 1;
+        let mut remainder = vec![0u8; parity_symbols];
+        for byte in data {
+            let factor = byte ^ remainder[0];
+            remainder.rotate_left(1);
+            remainder[parity_symbols - 1] = 0;
+            if factor != 0 {
+                for j in 0..parity_symbols {
+                    remainder[j] ^= self.mul(generator[j + 1], factor);
+                }
+            }
+        }
+        remainder
+    }
+
+    /// Corrects the block (data followed by parity) in place and returns the number of corrected bytes,
+    ///    or None if there are more errors than can be corrected.
+    fn correct(&self, block: &mut [u8], parity_symbols: usize) -> Option<usize> {
+        let syndromes: Vec<u8> = (0..parity_symbols).map(|i| self.eval(block, self.pow_alpha(i))).collect();
+        if syndromes.iter().all(|s| *s == 0) {
+            return Some(0)
+        }
+
+        //berlekamp-massey, the locator is stored lowest degree first: locator(x) = prod(1 - X_k x)
+        let mut locator = vec![1u8];
+        let mut previous = vec![1u8];
+        let mut num_errors = 0;
+        let mut shift = 1;
+        let mut previous_discrepancy = 1u8;
+        for n in 0..parity_symbols {
+            let mut discrepancy = syndromes[n];
+            for i in 1..=num_errors.min(locator.len() - 1) {
+                discrepancy ^= self.mul(locator[i], syndromes[n - i]);
+            }
+            if discrepancy == 0 {
+                shift += 1;
+                continue;
+            }
+            let scale = self.div(discrepancy, previous_discrepancy);
+            let mut next = locator.clone();
+            if next.len() < previous.len() + shift {
+                next.resize(previous.len() + shift, 0);
+            }
+            for (i, coefficient) in previous.iter().enumerate() {
+                next[i + shift] ^= self.mul(scale, *coefficient);
+            }
+            if 2 * num_errors <= n {
+                num_errors = n + 1 - num_errors;
+                previous = locator;
+                previous_discrepancy = discrepancy;
+                shift = 1;
+            } else {
+                shift += 1;
+            }
+            locator = next;
+        }
+        while locator.len() > 1 && *locator.last().unwrap() == 0 {
+            locator.pop();
+        }
+        if num_errors != locator.len() - 1 || 2 * num_errors > parity_symbols {
+            return None
+        }
+
+        //chien search - the byte at position p has degree n-1-p, its locator is X = alpha^(n-1-p)
+        let n = block.len();
+        let locator_high_first: Vec<u8> = locator.iter().rev().cloned().collect();
+        let mut error_positions = Vec::with_capacity(num_errors);
+        for p in 0..n {
+            let x_inverse = self.pow_alpha(255 - (n - 1 - p) % 255);
+            if self.eval(&locator_high_first, x_inverse) == 0 {
+                error_positions.push(p);
+            }
+        }
+        if error_positions.len() != num_errors {
+            return None
+        }
+
+        //forney - evaluator(x) = syndromes(x) * locator(x) mod x^parity_symbols (lowest degree first)
+        let mut evaluator = vec![0u8; parity_symbols];
+        for (i, s) in syndromes.iter().enumerate() {
+            for (j, l) in locator.iter().enumerate() {
+                if i + j < parity_symbols {
+                    evaluator[i + j] ^= self.mul(*s, *l);
+                }
+            }
+        }
+        let evaluator_high_first: Vec<u8> = evaluator.iter().rev().cloned().collect();
+        //formal derivative, in characteristic 2 only the odd terms remain
+        let derivative_high_first: Vec<u8> = (1..locator.len()).rev()
+            .map(|i| if i % 2 == 1 { locator[i] } else { 0 })
+            .collect();
+        for p in error_positions {
+            let x = self.pow_alpha(n - 1 - p);
+            let x_inverse = self.div(1, x);
+            let denominator = self.eval(&derivative_high_first, x_inverse);
+            if denominator == 0 {
+                return None
+            }
+            let magnitude = self.mul(x, self.div(self.eval(&evaluator_high_first, x_inverse), denominator));
+            block[p] ^= magnitude;
+        }
+
+        if (0..parity_symbols).any(|i| self.eval(block, self.pow_alpha(i)) != 0) {
+            return None
+        }
+        Some(num_errors)
+    }
+}
@@ -0,0 +1,69 @@
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use sha2::{Digest, Sha256};
+
+//stretches the password, so that guessing keys against an image pair is expensive
+const KEY_DERIVATION_ROUNDS: usize = 1 << 14;
+const KEY_DERIVATION_SALT: &[u8] = b"generic_image_steganography/difference-code-key";
+const CODE_TABLE_PURPOSE: u64 = 0;
+const TRAVERSAL_PURPOSE: u64 = 1;
+const MAP_PURPOSE: u64 = 2;
+
+/// Secret that permutes the difference code tables and the order in which the container is traversed.
+/// Without it the bits cannot be recovered, even with access to both the original and the encoded container.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DifCodeKey([u8; 32]);
+impl DifCodeKey {
+    pub fn from_bytes(seed: [u8; 32]) -> DifCodeKey {
+        DifCodeKey(seed)
+    }
+    /// Derives the key by iterated, salted sha256 of the password.
+    pub fn from_password(password: &str) -> DifCodeKey {
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_DERIVATION_SALT);
+        hasher.update(password.as_bytes());
+        let mut digest = hasher.finalize();
+        for _ in 1..KEY_DERIVATION_ROUNDS {
+            let mut hasher = Sha256::new();
+            hasher.update(digest);
+            hasher.update(password.as_bytes());
+            digest = hasher.finalize();
+        }
+
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&digest);
+        DifCodeKey(seed)
+    }
+
+    /// The rng that determines the code table at the given index.
+    pub fn rng_at(&self, index: usize) -> StdRng {
+        self.rng_for(CODE_TABLE_PURPOSE, index as u64)
+    }
+    /// The rng that determines the order in which the container indices are traversed.
+    pub fn traversal_rng(&self) -> StdRng {
+        self.rng_for(TRAVERSAL_PURPOSE, 0)
+    }
+    /// An rng for the randomized map creation (and legacy encoding), so that the same key also results in the same encoded container.
+    ///     It is independent of the code tables and the traversal order, the decoder never needs it.
+    pub fn map_rng(&self) -> StdRng {
+        self.rng_for(MAP_PURPOSE, 0)
+    }
+
+    //independent streams for each purpose and index (chacha with differing seeds)
+    fn rng_for(&self, purpose: u64, index: u64) -> StdRng {
+        let mut seed = self.0;
+        for (seed_byte, index_byte) in seed[0..8].iter_mut().zip(index.to_le_bytes().iter()) {
+            *seed_byte ^= *index_byte;
+        }
+        for (seed_byte, purpose_byte) in seed[8..16].iter_mut().zip(purpose.to_le_bytes().iter()) {
+            *seed_byte ^= *purpose_byte;
+        }
+        StdRng::from_seed(seed)
+    }
+}
+//never print the secret
+impl std::fmt::Debug for DifCodeKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DifCodeKey(..)")
+    }
+}
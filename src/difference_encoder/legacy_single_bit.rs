@@ -1,26 +1,28 @@
-use rand::{Rng, thread_rng};
+use rand::{Rng, RngCore, thread_rng};
+use rand::seq::index;
 
-use jokrey_utilities::general::{get_bit_at, sample_unique, set_bit_at};
+use jokrey_utilities::general::{get_bit_at, set_bit_at};
 
 use crate::util::{DifCodeError, DifCodeImage, DifCodeResult, EncodingContainer, get_length_in_bits};
 
 pub fn randomly_select_indices_within(message_bytes: &[u8], original: &dyn EncodingContainer<Output=u8>) -> Vec<usize> {
-    randomly_select_indices(get_length_in_bits(message_bytes), original.len())
+    randomly_select_indices(get_length_in_bits(message_bytes), original.len(), &mut thread_rng())
 }
-pub fn randomly_select_indices(message_length_in_bits: usize, max_index: usize) -> Vec<usize> {
-    let mut selected_indices = sample_unique(0..max_index, message_length_in_bits);
+///Note: panics if message_length_in_bits > max_index
+pub fn randomly_select_indices(message_length_in_bits: usize, max_index: usize, rng: &mut dyn RngCore) -> Vec<usize> {
+    let mut selected_indices = index::sample(rng, max_index, message_length_in_bits).into_vec();
     selected_indices.sort();
     selected_indices
 }
 
 pub fn encode_into_vec_at_indices(message_bytes: &[u8], original: &dyn EncodingContainer<Output=u8>, selected_indices: &[usize]) -> DifCodeResult<Vec<u8>> {
     let mut encoded= vec![0; original.len()];
-    encode_at_indices(message_bytes, original, selected_indices, &mut encoded)?;
+    encode_at_indices(message_bytes, original, selected_indices, &mut thread_rng(), &mut encoded)?;
     Ok(encoded)
 }
 pub fn encode_into_image_at_indices(message_bytes: &[u8], original: &DifCodeImage, selected_indices: &[usize]) -> DifCodeResult<DifCodeImage> {
     let mut encoded_image = DifCodeImage::with_capacity(original.width(), original.height());
-    encode_at_indices(&message_bytes, original, selected_indices, &mut encoded_image)?;
+    encode_at_indices(&message_bytes, original, selected_indices, &mut thread_rng(), &mut encoded_image)?;
     Ok(encoded_image)
 }
 pub fn encode_into_image_into_path_at_indices(message_bytes: &[u8], original: &DifCodeImage, selected_indices: &[usize], path: &str) -> DifCodeResult<()> {
@@ -30,14 +32,13 @@ pub fn encode_into_image_into_path_at_indices(message_bytes: &[u8], original: &D
 }
 
 ///Note: - selected_indices must be sorted in ascending order and each entry must represent an index within the original container
-pub fn encode_at_indices(message_bytes: &[u8], original: &dyn EncodingContainer<Output=u8>, selected_indices: &[usize], encoded: &mut dyn EncodingContainer<Output=u8>) -> DifCodeResult<()> {
+///      - the rng chooses the direction of each change (the same rng state always results in the same encoded container)
+pub fn encode_at_indices(message_bytes: &[u8], original: &dyn EncodingContainer<Output=u8>, selected_indices: &[usize], rng: &mut dyn RngCore, encoded: &mut dyn EncodingContainer<Output=u8>) -> DifCodeResult<()> {
     if get_length_in_bits(message_bytes) < selected_indices.len() {
         Err(DifCodeError::from("message length in bits > selected indices length (too many selected indices, ambiguity must be resolved by caller)"))
     } else if get_length_in_bits(message_bytes) > selected_indices.len() { //here the longer code tables come into play
         Err(DifCodeError::from("message length in bits > selected indices length (too few selected indices)"))
     } else {
-        let mut rng = thread_rng();
-
         let mut index_in_original = 0;
         let mut selected_indices_iterator = selected_indices.iter();
         for message_byte in message_bytes {
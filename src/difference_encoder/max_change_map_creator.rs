@@ -1,121 +1,254 @@
-use crate::util::{get_length_in_bits, EncodingContainer, DifCodeImage, DifCodeResult, DifCodeError};
-use crate::difference_encoder::bits_difference_converter::{calculate_worst_case_difference_for, get_max_num_bits_encodable};
+use crate::util::{EncodingContainer, DifCodeImage, DifCodeImage16, DifCodeRgbaImage, DifCodeLumaImage, DifCodeLumaImage16, DifCodeResult, DifCodeError, Sample};
+use crate::integral_image::IntegralVarianceImage;
+use crate::difference_encoder::bits_difference_converter::{calculate_worst_case_difference_of_sample, get_max_num_bits_encodable_of_sample};
 use jokrey_utilities::general::distance;
-use crate::rand::prelude::SliceRandom;
+use crate::rand::{Rng, RngCore, thread_rng};
+use crate::difference_encoder::options::EncodingOptions;
+use crate::difference_encoder::channel_weighting::ChannelWeighting;
+use crate::parallel;
+use std::ops::Range;
 
-//having a max_difference different to the num bits boundaries can cause issues, because the algorithm is conservative. I.e. it ensures that the message can also be encoded in the worst case, not just in the best case.
-pub fn create_minimal_evenly_random_allowed_changes_map_for(message: &[u8], original: &dyn EncodingContainer, max_difference: u8) -> DifCodeResult<Vec<u8>> {
-    let mut output_map = vec![0; original.len()];
-    write_minimal_evenly_random_allowed_changes_map_with(get_length_in_bits(message), original, max_difference, &mut output_map)?;
+//the message based creators reserve room for the envelope header written by encode (with default EncodingOptions) and pick the changes with thread_rng,
+//    the length based ones take the raw number of bits and the rng (the same rng state, e.g. a seeded StdRng, always results in the same map).
+pub(crate) fn create_allowed_changes_map_for_message<T: Sample, F>(message: &[u8], original: &dyn EncodingContainer<T>, write_map: F) -> DifCodeResult<Vec<T>>
+where F: FnOnce(usize, &mut dyn RngCore, &mut dyn EncodingContainer<T>) -> DifCodeResult<()>
+{
+    let mut output_map = vec![T::default(); original.len()];
+    write_allowed_changes_map_for_message(message, &mut output_map, write_map)?;
     Ok(output_map)
 }
+pub(crate) fn write_allowed_changes_map_for_message<T: Sample, F>(message: &[u8], output_map: &mut dyn EncodingContainer<T>, write_map: F) -> DifCodeResult<()>
+where F: FnOnce(usize, &mut dyn RngCore, &mut dyn EncodingContainer<T>) -> DifCodeResult<()>
+{
+    let options = EncodingOptions::default();
+    write_map(options.get_embedded_length_in_bits(message.len()), &mut thread_rng(), output_map).map_err(|err| options.payload_capacity_error(err))
+}
+
+//having a max_difference different to the num bits boundaries can cause issues, because the algorithm is conservative. I.e. it ensures that the message can also be encoded in the worst case, not just in the best case.
+pub fn create_minimal_evenly_random_allowed_changes_map_for<T: Sample>(message: &[u8], original: &dyn EncodingContainer<T>, max_difference: T) -> DifCodeResult<Vec<T>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_allowed_changes_map_with(length, original, max_difference, rng, output_map))
+}
 pub fn create_minimal_evenly_random_allowed_changes_map_for_image(message: &[u8], original: &DifCodeImage) -> DifCodeResult<Vec<u8>> {
     let mut output_map = vec![0; original.len()];
     write_minimal_evenly_random_allowed_changes_map_for(message, original, &mut output_map)?;
     Ok(output_map)
 }
-pub fn create_minimal_evenly_random_allowed_changes_map(message: &[u8], original: &dyn EncodingContainer) -> DifCodeResult<Vec<u8>> {
-    let mut output_map = vec![0; original.len()];
+pub fn create_minimal_evenly_random_allowed_changes_map<T: Sample>(message: &[u8], original: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<T>> {
+    let mut output_map = vec![T::default(); original.len()];
     write_minimal_evenly_random_allowed_changes_map_for(message, original, &mut output_map)?;
     Ok(output_map)
 }
-pub fn write_minimal_evenly_random_allowed_changes_map_for(message: &[u8], original: &dyn EncodingContainer, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
-    write_minimal_evenly_random_allowed_changes_map_with(get_length_in_bits(message), original, 255, output_map)
+pub fn write_minimal_evenly_random_allowed_changes_map_for<T: Sample>(message: &[u8], original: &dyn EncodingContainer<T>, output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    write_allowed_changes_map_for_message(message, output_map, |length, rng, output_map| write_minimal_evenly_random_allowed_changes_map_with(length, original, T::MAX, rng, output_map))
 }
 
 //having a max_difference different to the num bits boundaries can cause issues, because the algorithm is conservative. I.e. it ensures that the message can also be encoded in the worst case, not just in the best case.
-pub fn write_minimal_evenly_random_allowed_changes_map_with(message_length_in_bits: usize, original: &dyn EncodingContainer, max_difference: u8, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+pub fn write_minimal_evenly_random_allowed_changes_map_with<T: Sample>(message_length_in_bits: usize, original: &dyn EncodingContainer<T>, max_difference: T, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
     write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original,
-                                move |_index, ov| (max_difference, ov <= 255 / 2), //maximize possibility
-                                                   output_map)
+                                move |_index, ov: T| (max_difference, ov.to_u32() <= T::MAX.to_u32() / 2), //maximize possibility
+                                                   rng, output_map)
 }
-pub fn write_minimal_evenly_random_allowed_changes_map<F>(message_length_in_bits: usize,
-                                                          original: &dyn EncodingContainer,
+pub fn write_minimal_evenly_random_allowed_changes_map<T: Sample, F>(message_length_in_bits: usize,
+                                                          original: &dyn EncodingContainer<T>,
                                                           change_constraint_calculator: F,
-                                                          output_map: &mut dyn EncodingContainer) -> DifCodeResult<()>
-where F: Fn(usize, u8) -> (u8, bool)
+                                                          rng: &mut dyn RngCore,
+                                                          output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()>
+where F: Fn(usize, T) -> (T, bool) + Sync
 {
-    //todo - this entire algorithm is very inefficient in terms of memory usage...
-
-
+    write_minimal_allowed_changes_map(message_length_in_bits, original, &change_constraint_calculator, None, rng, output_map)
+}
+/// Like write_minimal_evenly_random_allowed_changes_map, but the changes of the last round are spread evenly over the container,
+///     instead of uniformly at random (which leaves clusters and voids). The container is seen as rows of row_length values (e.g. the width of an image).
+pub fn write_minimal_stratified_allowed_changes_map<T: Sample, F>(message_length_in_bits: usize,
+                                                                 original: &dyn EncodingContainer<T>,
+                                                                 row_length: usize,
+                                                                 change_constraint_calculator: F,
+                                                                 rng: &mut dyn RngCore,
+                                                                 output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()>
+where F: Fn(usize, T) -> (T, bool) + Sync
+{
+    write_minimal_allowed_changes_map(message_length_in_bits, original, &change_constraint_calculator, Some(row_length.max(1)), rng, output_map)
+}
+pub fn write_minimal_stratified_allowed_changes_map_with<T: Sample>(message_length_in_bits: usize, original: &dyn EncodingContainer<T>, row_length: usize, max_difference: T, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    write_minimal_stratified_allowed_changes_map(message_length_in_bits, original, row_length,
+                                                 move |_index, ov: T| (max_difference, ov.to_u32() <= T::MAX.to_u32() / 2),
+                                                 rng, output_map)
+}
+/// Like write_minimal_evenly_random_allowed_changes_map_with, with the max difference of each channel scaled by the channel weighting.
+pub fn write_minimal_evenly_random_image_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage, max_difference: u8, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let (width, height) = (original.width(), original.height());
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original,
+                                                    move |index, ov: u8| (channel_weighting.weigh(DifCodeImage::index_to_xyz_with_wh(index, width, height).2, max_difference), ov <= 255 / 2),
+                                                    rng, output_map)
+}
+/// Like write_minimal_stratified_allowed_changes_map_with, with the max difference of each channel scaled by the channel weighting.
+pub fn write_minimal_stratified_image_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage, max_difference: u8, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let (width, height) = (original.width(), original.height());
+    write_minimal_stratified_allowed_changes_map(message_length_in_bits, original, width as usize,
+                                                 move |index, ov: u8| (channel_weighting.weigh(DifCodeImage::index_to_xyz_with_wh(index, width, height).2, max_difference), ov <= 255 / 2),
+                                                 rng, output_map)
+}
+pub fn create_minimal_stratified_allowed_changes_map_for_image(message: &[u8], original: &DifCodeImage) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_stratified_allowed_changes_map_with(length, original, original.width() as usize, 255, rng, output_map))
+}
+fn write_minimal_allowed_changes_map<T: Sample>(message_length_in_bits: usize,
+                                                original: &dyn EncodingContainer<T>,
+                                                change_constraint_calculator: &(dyn Fn(usize, T) -> (T, bool) + Sync),
+                                                stratified_row_length: Option<usize>,
+                                                rng: &mut dyn RngCore,
+                                                output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    //In each round every index that can be changed by one more bit (within its constraint) is changed once, until the message fits.
+    //    Changes at different indices are independent of each other, so a round that does not fit the rest of the message completely
+    //    changes all of these indices (in index order). The last round changes a uniformly random subset of exactly the missing size (selection sampling).
+    //    That is the same as visiting all indices in a random order in each round and stopping once the message fits, without holding the order in memory.
     let mut remaining_bits_in_message = message_length_in_bits;
 
     for i in 0..original.len() {
         output_map[i] = original[i];
     }
 
-    let mut all_indices: Vec<usize> = (0..original.len()).collect();
-    let mut rng = rand::thread_rng();
-
     while remaining_bits_in_message > 0 {
-        all_indices.shuffle(&mut rng);
-        let mut success_counter = 0;
-
-        let mut step_from = 0; //use up all indices before
-        while remaining_bits_in_message > 0 {
-            if step_from >= all_indices.len() { //step from is changed at the bottom of the while
-                if success_counter == 0 {
-                    return Err(DifCodeError::InternalCapacityReached(message_length_in_bits - remaining_bits_in_message));
-                } else {
-                    break;
-                }
+        let num_changeable = {
+            let output_map: &dyn EncodingContainer<T> = output_map;
+            parallel::sum_indices(original.len(), |i| {
+                calculate_next_allowed_change(original[i], output_map[i], change_constraint_calculator(i, original[i])).is_some() as usize
+            })
+        };
+        if num_changeable == 0 {
+            return Err(DifCodeError::InternalCapacityReached(message_length_in_bits - remaining_bits_in_message));
+        }
+        if num_changeable < remaining_bits_in_message {
+            apply_next_allowed_changes(original, change_constraint_calculator, 0..original.len(), num_changeable, &mut |_| true, output_map);
+            remaining_bits_in_message -= num_changeable;
+            continue;
+        }
+
+        match stratified_row_length {
+            None => {
+                let (mut num_unvisited, mut num_missing) = (num_changeable, remaining_bits_in_message);
+                apply_next_allowed_changes(original, change_constraint_calculator, 0..original.len(), remaining_bits_in_message, &mut |_| {
+                    let is_selected = rng.gen_range(0..num_unvisited) < num_missing;
+                    num_unvisited -= 1;
+                    num_missing -= is_selected as usize;
+                    is_selected
+                }, output_map);
             }
+            Some(row_length) => apply_stratified_last_round(original, change_constraint_calculator, row_length, num_changeable, remaining_bits_in_message, rng, output_map)
+        }
+        remaining_bits_in_message = 0;
+    }
+
+    Ok(())
+}
+
+//The last round of the stratified map: the container is divided into square tiles (of rows of row_length values) that hold one change on average.
+//    Each tile gets its share of the changes, rounded up or down by a running total with a random offset (visiting the tiles in serpentine order,
+//    so neighbouring tiles balance each other's rounding), and changes that many of its changeable indices, picked uniformly at random.
+fn apply_stratified_last_round<T: Sample>(original: &dyn EncodingContainer<T>, change_constraint_calculator: &(dyn Fn(usize, T) -> (T, bool) + Sync),
+                                          row_length: usize, num_changeable: usize, num_selected: usize,
+                                          rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<T>) {
+    let tile_length = ((original.len() as f64 / num_selected as f64).sqrt().round() as usize).max(1);
+    let num_tiles_per_row = row_length.div_ceil(tile_length);
+    let tile_of = |i: usize| (i % row_length) / tile_length;
+    let offset = rng.gen_range(0..num_changeable) as u128;
+    let num_selected_until = |num_visited: usize| ((num_visited as u128 * num_selected as u128 + offset) / num_changeable as u128) as usize;
 
-            let step_to = (step_from + remaining_bits_in_message).min(all_indices.len());
-            let selected_indices = &all_indices[step_from..step_to];
-
-            for sel_i in selected_indices {
-                let original_value = original[*sel_i];
-                let allowed_change = output_map[*sel_i];
-                let old_difference = distance(original_value, allowed_change);
-                let (max_difference, initial_direction_positive) = change_constraint_calculator(*sel_i, original_value);
-                //with max differences != to the numbits boundaries this can
-
-                if old_difference < max_difference {
-                    let num_bits_currently_encodable = get_max_num_bits_encodable(old_difference);
-                    if let Some(mut new_difference) = calculate_worst_case_difference_for(num_bits_currently_encodable + 1) {
-                        if new_difference <= max_difference {
-                            if original_value == allowed_change {
-                                let is_direction_positive = initial_direction_positive;
-                                if attempt_change(output_map, *sel_i, new_difference - old_difference, is_direction_positive) {
-                                    success_counter += 1;
-                                }
-                            } else {
-                                let desired_direction_positive = allowed_change > original_value;//keep same direction as before...
-                                if attempt_change(output_map, *sel_i, new_difference - old_difference, desired_direction_positive) {
-                                    success_counter += 1;
-                                }
-                            }
-                        }
+    let mut num_visited = 0;
+    for (tile_row_index, tile_row_from) in (0..original.len()).step_by(tile_length * row_length).enumerate() {
+        let tile_row = tile_row_from..(tile_row_from + tile_length * row_length).min(original.len());
+        let mut num_unvisited_per_tile = {
+            let output_map: &dyn EncodingContainer<T> = output_map;
+            parallel::map_ranges(tile_row.len(), |range| {
+                let mut num_changeable_per_tile = vec![0usize; num_tiles_per_row];
+                for i in tile_row.start + range.start..tile_row.start + range.end {
+                    if calculate_next_allowed_change(original[i], output_map[i], change_constraint_calculator(i, original[i])).is_some() {
+                        num_changeable_per_tile[tile_of(i)] += 1;
                     }
                 }
-            }
-
-            remaining_bits_in_message -= success_counter;
-            step_from = step_to;
+                num_changeable_per_tile
+            }).into_iter().fold(vec![0usize; num_tiles_per_row], |sums, counts| sums.iter().zip(counts).map(|(sum, count)| sum + count).collect())
+        };
+        let mut num_missing_per_tile = vec![0usize; num_tiles_per_row];
+        let tile_order: Vec<usize> = if tile_row_index % 2 == 0 { (0..num_tiles_per_row).collect() } else { (0..num_tiles_per_row).rev().collect() };
+        for tile in tile_order {
+            num_missing_per_tile[tile] = num_selected_until(num_visited + num_unvisited_per_tile[tile]) - num_selected_until(num_visited);
+            num_visited += num_unvisited_per_tile[tile];
         }
+
+        let num_to_apply = num_missing_per_tile.iter().sum();
+        apply_next_allowed_changes(original, change_constraint_calculator, tile_row, num_to_apply, &mut |i| {
+            let tile = tile_of(i);
+            let is_selected = rng.gen_range(0..num_unvisited_per_tile[tile]) < num_missing_per_tile[tile];
+            num_unvisited_per_tile[tile] -= 1;
+            num_missing_per_tile[tile] -= is_selected as usize;
+            is_selected
+        }, output_map);
     }
+}
 
-    Ok(())
+//visits the indices in the range that can be changed by one more bit in index order and changes those that are selected,
+//    until num_to_apply changes are made. The changes are calculated in parallel, a batch at a time.
+fn apply_next_allowed_changes<T: Sample>(original: &dyn EncodingContainer<T>, change_constraint_calculator: &(dyn Fn(usize, T) -> (T, bool) + Sync),
+                                         indices: Range<usize>, mut num_to_apply: usize, select: &mut dyn FnMut(usize) -> bool,
+                                         output_map: &mut dyn EncodingContainer<T>) {
+    for batch_from in indices.clone().step_by(MAP_BATCH_LENGTH) {
+        if num_to_apply == 0 {
+            return
+        }
+        let batch_to = (batch_from + MAP_BATCH_LENGTH).min(indices.end);
+        let next_allowed_changes = {
+            let output_map: &dyn EncodingContainer<T> = output_map;
+            parallel::map_ranges(batch_to - batch_from, |range| {
+                (batch_from + range.start..batch_from + range.end).map(|i| {
+                    calculate_next_allowed_change(original[i], output_map[i], change_constraint_calculator(i, original[i]))
+                }).collect::<Vec<_>>()
+            }).concat()
+        };
+        for (i, next_allowed_change) in (batch_from..batch_to).zip(next_allowed_changes) {
+            if let Some(next_allowed_change) = next_allowed_change {
+                if select(i) {
+                    output_map[i] = next_allowed_change;
+                    num_to_apply -= 1;
+                }
+            }
+        }
+    }
 }
 
+//the number of indices whose changes are calculated at once
+const MAP_BATCH_LENGTH: usize = 1 << 20;
 
+//the allowed change that can encode one more bit than the current one, if that is within the constraint
+fn calculate_next_allowed_change<T: Sample>(original_value: T, allowed_change: T, (max_difference, initial_direction_positive): (T, bool)) -> Option<T> {
+    let old_difference = original_value.distance(allowed_change);
+    //with max differences != to the numbits boundaries this can
+    if old_difference >= max_difference {
+        return None
+    }
+    let num_bits_currently_encodable = get_max_num_bits_encodable_of_sample(old_difference);
+    let new_difference = calculate_worst_case_difference_of_sample::<T>(num_bits_currently_encodable + 1)?;
+    if new_difference > max_difference {
+        return None
+    }
+    let desired_distance = new_difference.to_u32() - old_difference.to_u32();
+    let desired_direction_positive = if original_value == allowed_change {
+        initial_direction_positive
+    } else {
+        allowed_change > original_value //keep same direction as before...
+    };
 
-fn attempt_change(container: &mut dyn EncodingContainer, index: usize, desired_distance: u8, desired_direction_positive: bool) -> bool {
-    let original_value = container[index];
+    let allowed_change = allowed_change.to_u32();
     if desired_direction_positive {
-        if original_value > 255 - desired_distance {
-            false
+        if allowed_change > T::MAX.to_u32() - desired_distance {
+            None
         } else {
-            container[index] = original_value + desired_distance;
-            true
+            Some(T::from_u32(allowed_change + desired_distance))
         }
     } else {
-        if original_value < desired_distance {
-            false
+        if allowed_change < desired_distance {
+            None
         } else {
-            container[index] = original_value - desired_distance;
-            true
+            Some(T::from_u32(allowed_change - desired_distance))
         }
     }
 }
@@ -133,23 +266,236 @@ fn attempt_change(container: &mut dyn EncodingContainer, index: usize, desired_d
 
 
 
+//the square around each value (of side length 2*radius+1) whose average limits the change
+pub const DEFAULT_AREA_AVERAGE_RADIUS: u32 = 10;
 pub fn create_minimal_evenly_random_max_area_average_allowed_changes_map(message: &[u8], original: &DifCodeImage) -> DifCodeResult<Vec<u8>> {
     let mut output_map = vec![0; original.len()];
     write_minimal_evenly_random_max_area_average_allowed_changes_map_for(message, original, &mut output_map)?;
     Ok(output_map)
 }
 pub fn write_minimal_evenly_random_max_area_average_allowed_changes_map_for(message: &[u8], original: &DifCodeImage, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
-    write_minimal_evenly_random_max_area_average_allowed_changes_map(get_length_in_bits(message), original, output_map)
+    write_allowed_changes_map_for_message(message, output_map, |length, rng, output_map| write_minimal_evenly_random_max_area_average_allowed_changes_map(length, original, DEFAULT_AREA_AVERAGE_RADIUS, ChannelWeighting::Uniform, rng, output_map))
 }
-pub fn write_minimal_evenly_random_max_area_average_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+pub fn write_minimal_evenly_random_max_area_average_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage, radius: u32, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
     let integral_image = original.generate_integral_image_for_rgb();
     let change_constraint_calculator = move |index, ov| {
         let (x, y, z) = DifCodeImage::index_to_xyz_with_wh(index, integral_image.width(), integral_image.height());
-        let average = integral_image.average_in_radius(x, y, z, 10);
-        let max_difference = distance(ov, average);
+        let average = integral_image.average_in_radius(x, y, z, radius);
+        let max_difference = channel_weighting.weigh(z, distance(ov, average));
         let initial_direction_positive = ov < average; //if ov is smaller than average, then we make a positive change
         (max_difference, initial_direction_positive)
     };
 
-    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, output_map)
-}
\ No newline at end of file
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_area_average_image16_allowed_changes_map(message: &[u8], original: &DifCodeImage16) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_area_average_image16_allowed_changes_map(length, original, DEFAULT_AREA_AVERAGE_RADIUS, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_area_average_image16_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage16, radius: u32, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    let integral_image = original.generate_integral_image_for_rgb();
+    let change_constraint_calculator = move |index, ov: u16| {
+        let (x, y, z) = original.index_to_xyz(index);
+        let average = integral_image.average_in_radius(x, y, z, radius);
+        (ov.distance(average), ov < average)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_area_average_luma_allowed_changes_map(message: &[u8], original: &DifCodeLumaImage) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_area_average_luma_allowed_changes_map(length, original, DEFAULT_AREA_AVERAGE_RADIUS, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_area_average_luma_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeLumaImage, radius: u32, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let integral_image = original.generate_integral_image();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y) = original.index_to_xy(index);
+        let average = integral_image.average_in_radius(x, y, 0, radius);
+        let max_difference = distance(ov, average);
+        let initial_direction_positive = ov < average;
+        (max_difference, initial_direction_positive)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_area_average_luma16_allowed_changes_map(message: &[u8], original: &DifCodeLumaImage16) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_area_average_luma16_allowed_changes_map(length, original, DEFAULT_AREA_AVERAGE_RADIUS, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_area_average_luma16_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeLumaImage16, radius: u32, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    let integral_image = original.generate_integral_image();
+    let change_constraint_calculator = move |index, ov: u16| {
+        let (x, y) = original.index_to_xy(index);
+        let average = integral_image.average_in_radius(x, y, 0, radius);
+        (ov.distance(average), ov < average)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+
+
+//alpha is only changed where the original alpha already varies around the pixel (e.g. anti aliased or soft edges),
+//    and only within that local range, so the alpha plane never gains visible structure. Uniform alpha (i.e. fully opaque areas) is never changed.
+const ALPHA_VARIATION_RADIUS: u32 = 1;
+pub(crate) fn calculate_alpha_change_constraint(original: &DifCodeRgbaImage, x: u32, y: u32, embed_in_alpha: bool) -> (u8, bool) {
+    if !embed_in_alpha {
+        return (0, true)
+    }
+    let ov = original.get_alpha(x, y);
+    let (min, max) = original.alpha_range_in_radius(x, y, ALPHA_VARIATION_RADIUS);
+    if ov - min >= max - ov {
+        (ov - min, false)
+    } else {
+        (max - ov, true)
+    }
+}
+
+pub fn create_minimal_evenly_random_rgba_allowed_changes_map(message: &[u8], original: &DifCodeRgbaImage, embed_in_alpha: bool) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_rgba_allowed_changes_map(length, original, 255, embed_in_alpha, ChannelWeighting::Uniform, rng, output_map))
+}
+pub fn write_minimal_evenly_random_rgba_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeRgbaImage, max_difference: u8, embed_in_alpha: bool, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        if z == DifCodeRgbaImage::ALPHA_CHANNEL {
+            calculate_alpha_change_constraint(original, x, y, embed_in_alpha)
+        } else {
+            (channel_weighting.weigh(z, max_difference), ov <= 255 / 2)
+        }
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_stratified_rgba_allowed_changes_map(message: &[u8], original: &DifCodeRgbaImage, embed_in_alpha: bool) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_stratified_rgba_allowed_changes_map(length, original, 255, embed_in_alpha, ChannelWeighting::Uniform, rng, output_map))
+}
+pub fn write_minimal_stratified_rgba_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeRgbaImage, max_difference: u8, embed_in_alpha: bool, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        if z == DifCodeRgbaImage::ALPHA_CHANNEL {
+            calculate_alpha_change_constraint(original, x, y, embed_in_alpha)
+        } else {
+            (channel_weighting.weigh(z, max_difference), ov <= 255 / 2)
+        }
+    };
+
+    write_minimal_stratified_allowed_changes_map(message_length_in_bits, original, original.width() as usize, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_area_average_rgba_allowed_changes_map(message: &[u8], original: &DifCodeRgbaImage, embed_in_alpha: bool) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_area_average_rgba_allowed_changes_map(length, original, DEFAULT_AREA_AVERAGE_RADIUS, embed_in_alpha, ChannelWeighting::Uniform, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_area_average_rgba_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeRgbaImage, radius: u32, embed_in_alpha: bool, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let integral_image = original.generate_integral_image_for_rgb();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        if z == DifCodeRgbaImage::ALPHA_CHANNEL {
+            return calculate_alpha_change_constraint(original, x, y, embed_in_alpha)
+        }
+        let average = integral_image.average_in_radius(x, y, z, radius);
+        let max_difference = channel_weighting.weigh(z, distance(ov, average));
+        let initial_direction_positive = ov < average;
+        (max_difference, initial_direction_positive)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+
+
+
+
+
+
+
+
+
+
+
+//changes hide best in texture, so each value may change by the standard deviation of the values around it and flat areas (e.g. sky, walls)
+//    are never changed. Like in the area average map the change is directed towards the local average.
+//    The minimum texture is scaled to the value range (i.e. 4 of 255 for 8 bit images).
+pub const DEFAULT_LOCAL_VARIANCE_RADIUS: u32 = 3;
+const MIN_TEXTURE_STANDARD_DEVIATION: u8 = 4;
+fn calculate_local_variance_change_constraint<T: Sample>(variance_image: &IntegralVarianceImage<T>, x: u32, y: u32, z: u32, radius: u32, ov: T) -> (T, bool) {
+    let standard_deviation = variance_image.standard_deviation_in_radius(x, y, z, radius);
+    let min_texture_standard_deviation = MIN_TEXTURE_STANDARD_DEVIATION as u32 * (T::MAX.to_u32() / u8::MAX as u32);
+    let max_difference = if standard_deviation.to_u32() < min_texture_standard_deviation { T::default() } else { standard_deviation };
+    (max_difference, ov < variance_image.average_in_radius(x, y, z, radius))
+}
+
+pub fn create_minimal_evenly_random_max_local_variance_allowed_changes_map(message: &[u8], original: &DifCodeImage) -> DifCodeResult<Vec<u8>> {
+    let mut output_map = vec![0; original.len()];
+    write_minimal_evenly_random_max_local_variance_allowed_changes_map_for(message, original, &mut output_map)?;
+    Ok(output_map)
+}
+pub fn write_minimal_evenly_random_max_local_variance_allowed_changes_map_for(message: &[u8], original: &DifCodeImage, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    write_allowed_changes_map_for_message(message, output_map, |length, rng, output_map| write_minimal_evenly_random_max_local_variance_allowed_changes_map(length, original, DEFAULT_LOCAL_VARIANCE_RADIUS, ChannelWeighting::Uniform, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_local_variance_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage, radius: u32, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let variance_image = original.generate_integral_variance_image_for_rgb();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = DifCodeImage::index_to_xyz_with_wh(index, variance_image.width(), variance_image.height());
+        let (max_difference, initial_direction_positive) = calculate_local_variance_change_constraint(&variance_image, x, y, z, radius, ov);
+        (channel_weighting.weigh(z, max_difference), initial_direction_positive)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_local_variance_image16_allowed_changes_map(message: &[u8], original: &DifCodeImage16) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_local_variance_image16_allowed_changes_map(length, original, DEFAULT_LOCAL_VARIANCE_RADIUS, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_local_variance_image16_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeImage16, radius: u32, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    let variance_image = original.generate_integral_variance_image_for_rgb();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        calculate_local_variance_change_constraint(&variance_image, x, y, z, radius, ov)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_local_variance_luma_allowed_changes_map(message: &[u8], original: &DifCodeLumaImage) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_local_variance_luma_allowed_changes_map(length, original, DEFAULT_LOCAL_VARIANCE_RADIUS, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_local_variance_luma_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeLumaImage, radius: u32, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let variance_image = original.generate_integral_variance_image();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y) = original.index_to_xy(index);
+        calculate_local_variance_change_constraint(&variance_image, x, y, 0, radius, ov)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map(message: &[u8], original: &DifCodeLumaImage16) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map(length, original, DEFAULT_LOCAL_VARIANCE_RADIUS, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeLumaImage16, radius: u32, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    let variance_image = original.generate_integral_variance_image();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y) = original.index_to_xy(index);
+        calculate_local_variance_change_constraint(&variance_image, x, y, 0, radius, ov)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+pub fn create_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map(message: &[u8], original: &DifCodeRgbaImage, embed_in_alpha: bool) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map(length, original, DEFAULT_LOCAL_VARIANCE_RADIUS, embed_in_alpha, ChannelWeighting::Uniform, rng, output_map))
+}
+pub fn write_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeRgbaImage, radius: u32, embed_in_alpha: bool, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let variance_image = original.generate_integral_variance_image_for_rgb();
+    let change_constraint_calculator = move |index, ov| {
+        let (x, y, z) = original.index_to_xyz(index);
+        if z == DifCodeRgbaImage::ALPHA_CHANNEL {
+            return calculate_alpha_change_constraint(original, x, y, embed_in_alpha)
+        }
+        let (max_difference, initial_direction_positive) = calculate_local_variance_change_constraint(&variance_image, x, y, z, radius, ov);
+        (channel_weighting.weigh(z, max_difference), initial_direction_positive)
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
@@ -1,5 +1,14 @@
+#[cfg(test)]
 mod tests;
 pub mod legacy_single_bit;
 pub mod multi_bit;
 pub mod bits_difference_converter;
-pub mod max_change_map_creator;
\ No newline at end of file
+pub mod max_change_map_creator;
+pub mod envelope;
+pub mod error_correction;
+pub mod options;
+pub mod key;
+pub mod traversal_order;
+pub mod channel_weighting;
+pub(crate) mod streaming;
+pub(crate) mod resynchronization;
\ No newline at end of file
@@ -1,55 +1,258 @@
-use jokrey_utilities::general::{BitIterator, BytesBuilder, distance, Pushable, StackSlice, Popable};
+use std::io::{Read, Write};
 
-use crate::difference_encoder::bits_difference_converter::{dynamic_bits_to_difference_if_allowed, get_min_num_bits_encodable, get_num_bits_decodable, dynamic_difference_to_bits, get_max_num_bits_encodable};
-use crate::util::{DifCodeImage, DifCodeResult, DifCodeError, EncodingContainer};
+use jokrey_utilities::general::{BitIterator, BytesBuilder, Pushable, StackSlice, Popable};
 
-pub fn encode_into_vec(message_bytes: &[u8], original: &dyn EncodingContainer, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<Vec<u8>> {
-    let mut encoded= vec![0; original.len()];
-    encode(message_bytes, original, allowed_changes_map, &mut encoded)?;
+use crate::difference_encoder::bits_difference_converter::{dynamic_bits_to_difference_if_allowed_with_key, get_min_num_bits_encodable_of_sample, get_num_bits_decodable_of_sample, dynamic_difference_to_bits_with_key, get_max_num_bits_encodable_of_sample, get_expected_num_bits_encodable_of_sample};
+use crate::difference_encoder::envelope::PayloadHeader;
+use crate::difference_encoder::error_correction::ErrorCorrectionReport;
+use crate::difference_encoder::key::DifCodeKey;
+use crate::difference_encoder::options::EncodingOptions;
+use crate::difference_encoder::streaming::{StreamedEmbedding, StreamedExtraction};
+use crate::difference_encoder::traversal_order::TraversalOrder;
+use crate::parallel;
+use crate::util::{DifCodeImage, DifCodeImage16, DifCodeRgbaImage, DifCodeLumaImage, DifCodeLumaImage16, DifCodeResult, DifCodeError, EncodingContainer, Sample};
+
+pub fn encode_into_vec<T: Sample>(message_bytes: &[u8], original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<T>> {
+    encode_into_vec_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_vec_with_options<T: Sample>(message_bytes: &[u8], options: &EncodingOptions, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<T>> {
+    let mut encoded= vec![T::default(); original.len()];
+    encode_with_options(message_bytes, options, original, allowed_changes_map, &mut encoded)?;
     Ok(encoded)
 }
 pub fn encode_into_image(message_bytes: &[u8], original: &DifCodeImage, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<DifCodeImage> {
+    encode_into_image_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_image_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeImage, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<DifCodeImage> {
     let mut encoded_image = DifCodeImage::with_capacity(original.width(), original.height());
-    encode(&message_bytes, original, allowed_changes_map, &mut encoded_image)?;
+    encode_with_options(&message_bytes, options, original, allowed_changes_map, &mut encoded_image)?;
     Ok(encoded_image)
 }
 pub fn encode_into_image_into_path(message_bytes: &[u8], original: &DifCodeImage, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
-    let encoded_image= encode_into_image(message_bytes, original, allowed_changes_map)?;
+    encode_into_image_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_image_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeImage, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    let encoded_image= encode_into_image_with_options(message_bytes, options, original, allowed_changes_map)?;
+    encoded_image.save(path)?;
+    Ok(())
+}
+pub fn encode_into_rgba_image(message_bytes: &[u8], original: &DifCodeRgbaImage, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<DifCodeRgbaImage> {
+    encode_into_rgba_image_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_rgba_image_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeRgbaImage, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<DifCodeRgbaImage> {
+    let mut encoded_image = DifCodeRgbaImage::with_capacity(original.width(), original.height());
+    encode_with_options(message_bytes, options, original, allowed_changes_map, &mut encoded_image)?;
+    Ok(encoded_image)
+}
+pub fn encode_into_rgba_image_into_path(message_bytes: &[u8], original: &DifCodeRgbaImage, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_rgba_image_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_rgba_image_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeRgbaImage, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    let encoded_image= encode_into_rgba_image_with_options(message_bytes, options, original, allowed_changes_map)?;
+    encoded_image.save(path)?;
+    Ok(())
+}
+pub fn encode_into_image16(message_bytes: &[u8], original: &DifCodeImage16, allowed_changes_map: &dyn EncodingContainer<u16>) -> DifCodeResult<DifCodeImage16> {
+    encode_into_image16_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_image16_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeImage16, allowed_changes_map: &dyn EncodingContainer<u16>) -> DifCodeResult<DifCodeImage16> {
+    let mut encoded_image = DifCodeImage16::with_capacity(original.width(), original.height());
+    encode_with_options(message_bytes, options, original, allowed_changes_map, &mut encoded_image)?;
+    Ok(encoded_image)
+}
+pub fn encode_into_image16_into_path(message_bytes: &[u8], original: &DifCodeImage16, allowed_changes_map: &dyn EncodingContainer<u16>, path: &str) -> DifCodeResult<()> {
+    encode_into_image16_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_image16_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeImage16, allowed_changes_map: &dyn EncodingContainer<u16>, path: &str) -> DifCodeResult<()> {
+    let encoded_image= encode_into_image16_with_options(message_bytes, options, original, allowed_changes_map)?;
+    encoded_image.save(path)?;
+    Ok(())
+}
+pub fn encode_into_luma_image(message_bytes: &[u8], original: &DifCodeLumaImage, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<DifCodeLumaImage> {
+    encode_into_luma_image_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_luma_image_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeLumaImage, allowed_changes_map: &dyn EncodingContainer) -> DifCodeResult<DifCodeLumaImage> {
+    let mut encoded_image = DifCodeLumaImage::with_capacity(original.width(), original.height());
+    encode_with_options(message_bytes, options, original, allowed_changes_map, &mut encoded_image)?;
+    Ok(encoded_image)
+}
+pub fn encode_into_luma_image_into_path(message_bytes: &[u8], original: &DifCodeLumaImage, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_luma_image_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_luma_image_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeLumaImage, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    let encoded_image= encode_into_luma_image_with_options(message_bytes, options, original, allowed_changes_map)?;
+    encoded_image.save(path)?;
+    Ok(())
+}
+pub fn encode_into_luma_image16(message_bytes: &[u8], original: &DifCodeLumaImage16, allowed_changes_map: &dyn EncodingContainer<u16>) -> DifCodeResult<DifCodeLumaImage16> {
+    encode_into_luma_image16_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_luma_image16_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeLumaImage16, allowed_changes_map: &dyn EncodingContainer<u16>) -> DifCodeResult<DifCodeLumaImage16> {
+    let mut encoded_image = DifCodeLumaImage16::with_capacity(original.width(), original.height());
+    encode_with_options(message_bytes, options, original, allowed_changes_map, &mut encoded_image)?;
+    Ok(encoded_image)
+}
+pub fn encode_into_luma_image16_into_path(message_bytes: &[u8], original: &DifCodeLumaImage16, allowed_changes_map: &dyn EncodingContainer<u16>, path: &str) -> DifCodeResult<()> {
+    encode_into_luma_image16_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_luma_image16_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeLumaImage16, allowed_changes_map: &dyn EncodingContainer<u16>, path: &str) -> DifCodeResult<()> {
+    let encoded_image= encode_into_luma_image16_with_options(message_bytes, options, original, allowed_changes_map)?;
     encoded_image.save(path)?;
     Ok(())
 }
+/// The encoded container is a copy of the original with changed values, everything around them is unchanged
+///     (e.g. the ac coefficients of a DifCodeJpeg, the palette colors of a DifCodeGif or the samples of a DifCodeWav).
+///     The container modules have the helpers that also save it (e.g. encode_into_jpeg_into_path).
+pub fn encode_into_container<T: Sample, C: EncodingContainer<T> + Clone>(message_bytes: &[u8], original: &C, allowed_changes_map: &dyn EncodingContainer<T>) -> DifCodeResult<C> {
+    encode_into_container_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map)
+}
+pub fn encode_into_container_with_options<T: Sample, C: EncodingContainer<T> + Clone>(message_bytes: &[u8], options: &EncodingOptions, original: &C, allowed_changes_map: &dyn EncodingContainer<T>) -> DifCodeResult<C> {
+    let mut encoded = original.clone();
+    encode_with_options(message_bytes, options, original, allowed_changes_map, &mut encoded)?;
+    Ok(encoded)
+}
+
+/// Encodes the message_bytes, preceded by an envelope header (see envelope), into the encoded container.
+/// The allowed_changes_map has to account for the header (the message based map creators do).
+/// Capacity errors report the number of message bits (excluding the header) that fit.
+pub fn encode<T: Sample>(message_bytes: &[u8], original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    encode_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, encoded)
+}
+/// Like encode, but with flags, optional error correction and an optional key (see EncodingOptions).
+/// The allowed_changes_map has to account for options.get_embedded_length_in_bits.
+pub fn encode_with_options<T: Sample>(message_bytes: &[u8], options: &EncodingOptions, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    let embedded = options.create_embedded_stream(message_bytes)?;
+    encode_raw_with_key(&embedded, options.key.as_ref(), original, allowed_changes_map, encoded).map_err(|err| options.payload_capacity_error(err))
+}
+
+/// Like encode, but the message is read from the reader while it is encoded, so it never has to be in memory as a whole.
+/// The encoded data is the same format as encode's (i.e. decodable by decode_into_vec and decode_into_writer).
+pub fn encode_from_reader<T: Sample>(message: &mut dyn Read, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    encode_from_reader_with_options(message, &EncodingOptions::default(), original, allowed_changes_map, encoded)
+}
+/// Like encode_with_options, but the message is read from the reader while it is encoded (see encode_from_reader).
+/// The envelope header (and the error correction blocks containing it) can only be created once the message has been read completely,
+///    so the first indices are reserved for it and only encode as many bits as they are guaranteed to fit.
+pub fn encode_from_reader_with_options<T: Sample>(message: &mut dyn Read, options: &EncodingOptions, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    if original.len() != encoded.len() || original.len() != allowed_changes_map.len() {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+    let key = options.key.as_ref();
+    let mut embedding = StreamedEmbedding::new(message, options)?;
+
+    let traversal_order = TraversalOrder::new(key, original.len());
+    let mut indices = traversal_order.iter();
+    let reserved_length_in_bits = embedding.reserved_length_in_bytes() * 8;
+    let mut reserved = Vec::new();
+    let mut reserved_capacity = 0;
+    //if the indices run out, the message may still fit (short error corrected messages do not fill all the blocks of the prefix)
+    while reserved_capacity < reserved_length_in_bits {
+        let i = match indices.next() {
+            Some(i) => i,
+            None => break
+        };
+        let num_bits = if original[i] == allowed_changes_map[i] {0} else {get_min_num_bits_encodable_of_sample(original[i].distance(allowed_changes_map[i])) as usize};
+        reserved.push((i, num_bits));
+        reserved_capacity += num_bits;
+    }
+
+    //the reserved indices that are not required by the prefix are filled with the first bits after it
+    let mut chunk_bits = Vec::new();
+    let mut chunk_position = 0;
+    let mut next_bit = || {
+        while chunk_position == chunk_bits.len() {
+            chunk_bits = bytes_to_bits(&embedding.next_chunk()?);
+            chunk_position = 0;
+        }
+        chunk_position += 1;
+        Some(chunk_bits[chunk_position - 1])
+    };
+    let num_reserved_bits_after_prefix = reserved_capacity.saturating_sub(reserved_length_in_bits);
+    let mut reserved_bits_after_prefix = Vec::with_capacity(num_reserved_bits_after_prefix);
+    while reserved_bits_after_prefix.len() < num_reserved_bits_after_prefix {
+        match next_bit() {
+            Some(bit) => reserved_bits_after_prefix.push(bit),
+            None => break
+        }
+    }
+    if let Err(num_bits_encoded) = encode_bits_greedily(&mut next_bit, key, &mut indices, original, allowed_changes_map, encoded) {
+        return Err(options.payload_capacity_error(DifCodeError::InternalCapacityReached(reserved_capacity + num_bits_encoded)))
+    }
+
+    let mut reserved_bits = bytes_to_bits(&embedding.finish()?);
+    reserved_bits.extend(reserved_bits_after_prefix);
+    if reserved_bits.len() > reserved_capacity {
+        return Err(options.payload_capacity_error(DifCodeError::InternalCapacityReached(reserved_capacity)))
+    }
+    let mut reserved_bits = reserved_bits.into_iter();
+    for (i, num_bits) in reserved {
+        let bits: Vec<bool> = reserved_bits.by_ref().take(num_bits).collect();
+        if bits.is_empty() {
+            encoded[i] = original[i];
+            continue;
+        }
+        let encoded_difference = dynamic_bits_to_difference_if_allowed_with_key(key, i, &bits, original[i].distance(allowed_changes_map[i]))
+            .ok_or(DifCodeError::Internal("guaranteed number of bits not encodable"))?;
+        apply_change(i, encoded, allowed_changes_map[i], original[i], encoded_difference);
+    }
+    Ok(())
+}
+
+fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
+    let mut bits = Vec::with_capacity(bytes.len() * 8);
+    for bit in BitIterator::new(bytes) {
+        bits.push(bit);
+    }
+    bits
+}
 
 /// Encodes each bit in the message_bytes slice into the encoded container,
 ///    making at most a change to the value defined in the allowed_changes_map(and only in that direction).
 /// All three containers are required to have the same size.
 /// Encoded's content will be fully overridden.
+/// No header is written, so the decoder cannot tell where the message ends (see encode).
 ///
-///
-pub fn encode(message_bytes: &[u8], original: &dyn EncodingContainer, allowed_changes_map: &dyn EncodingContainer, encoded: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+pub fn encode_raw<T: Sample>(message_bytes: &[u8], original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+    encode_raw_with_key(message_bytes, None, original, allowed_changes_map, encoded)
+}
+/// Like encode_raw, but the code tables and the order in which the indices are visited are permuted by the key
+///    (None uses the unkeyed tables and visits the indices in order).
+pub fn encode_raw_with_key<T: Sample>(message_bytes: &[u8], key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
     if original.len() != encoded.len() || original.len() != allowed_changes_map.len() {
         return Err(DifCodeError::InternalMismatchedContainerSizes)
     }
 
     let mut message_bit_iterator = BitIterator::new(message_bytes);
+    let traversal_order = TraversalOrder::new(key, original.len());
+    let mut indices = traversal_order.iter();
+    encode_bits_greedily(&mut || message_bit_iterator.next(), key, &mut indices, original, allowed_changes_map, encoded)
+        .map_err(DifCodeError::InternalCapacityReached)
+}
 
-    let mut arr_buf_8_bool_1 = [false; 8];
-    let mut bit_buffer = StackSlice::new(&mut arr_buf_8_bool_1);
+/// Encodes as many of the bits as fit at each of the indices (in the order given), all three containers have to be of the same size.
+/// Fails with the number of bits that were encoded, if there are bits left after the last index.
+pub(crate) fn encode_bits_greedily<T: Sample>(next_bit: &mut dyn FnMut() -> Option<bool>, key: Option<&DifCodeKey>, indices: &mut dyn Iterator<Item=usize>, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>, encoded: &mut dyn EncodingContainer<T>) -> Result<(), usize> {
+    let mut num_bits_queried = 0;
 
-    for i in 0..original.len() {
+    let mut arr_buf_24_bool_1 = [false; 24];
+    let mut bit_buffer = StackSlice::new(&mut arr_buf_24_bool_1[..T::MAX_NUM_BITS as usize]);
+
+    for i in indices {
         let original_value = original[i];
         let allowed_change = allowed_changes_map[i];
         if original_value == allowed_change {
             encoded[i] = original_value;
             continue;
         }
-        let max_allowed_change = distance(original_value, allowed_change);
-        let num_bits_at_least_encodable = get_min_num_bits_encodable(max_allowed_change);
+        let max_allowed_change = original_value.distance(allowed_change);
+        let num_bits_at_least_encodable = get_min_num_bits_encodable_of_sample(max_allowed_change);
         let bit_buffer_len_top = bit_buffer.len() as u8;
 
         //decode the number of bits that will definitely fit into the allowed difference interval
         let num_to_query = if bit_buffer_len_top >= num_bits_at_least_encodable {0} else {num_bits_at_least_encodable - bit_buffer_len_top};
         for _ in 0..num_to_query {
-            if let Some(message_bit) = message_bit_iterator.next() {
+            if let Some(message_bit) = next_bit() {
+                num_bits_queried += 1;
                 bit_buffer.push(message_bit);
             } else {
                 break;
@@ -58,9 +261,10 @@ pub fn encode(message_bytes: &[u8], original: &dyn EncodingContainer, allowed_ch
 
         //decode 1 more bit, to test whether it also fits
         if !bit_buffer.capacity_reached() && bit_buffer.len() as u8 <= num_bits_at_least_encodable {
-            if let Some(message_bit) = message_bit_iterator.next() {
+            if let Some(message_bit) = next_bit() {
+                num_bits_queried += 1;
                 bit_buffer.push(message_bit);
-                if let Some(encoded_difference) = dynamic_bits_to_difference_if_allowed(i, bit_buffer.as_slice(), max_allowed_change) {
+                if let Some(encoded_difference) = dynamic_bits_to_difference_if_allowed_with_key(key, i, bit_buffer.as_slice(), max_allowed_change) {
                     // if let Some(encoded_difference) = static_bits_to_difference_if_allowed(bit_buffer.as_slice(), max_allowed_change) {
                     // println!("apply1 - bit_buffer: {:?}", bit_buffer.as_slice());
                     // println!("apply1 - encoded_difference: {:?}", encoded_difference);
@@ -77,7 +281,7 @@ pub fn encode(message_bytes: &[u8], original: &dyn EncodingContainer, allowed_ch
         let len_before = bit_buffer.len();
         while !bit_buffer.is_empty() {
             let last_encodable_bits = bit_buffer.as_slice();
-            if let Some(encoded_difference) = dynamic_bits_to_difference_if_allowed(i, last_encodable_bits, max_allowed_change) {
+            if let Some(encoded_difference) = dynamic_bits_to_difference_if_allowed_with_key(key, i, last_encodable_bits, max_allowed_change) {
                 // if let Some(encoded_difference) = static_bits_to_difference_if_allowed(last_encodable_bits, max_allowed_change) {
                 // println!("apply2 - bit_buffer: {:?}", bit_buffer.as_slice());
                 // println!("apply2 - encoded_difference: {:?}", encoded_difference);
@@ -98,9 +302,9 @@ pub fn encode(message_bytes: &[u8], original: &dyn EncodingContainer, allowed_ch
         }
     }
 
-    if !bit_buffer.is_empty() || message_bit_iterator.num_remaining() > 0 {
+    if !bit_buffer.is_empty() || next_bit().is_some() {
         // println!("bit_buffer = {:?}", bit_buffer.as_slice());
-        return Err(DifCodeError::InternalCapacityReached(message_bytes.len()*8 - (message_bit_iterator.num_remaining() + bit_buffer.len())));
+        return Err(num_bits_queried - bit_buffer.len());
     }
 
 
@@ -108,90 +312,205 @@ pub fn encode(message_bytes: &[u8], original: &dyn EncodingContainer, allowed_ch
     Ok(())
 }
 
-fn apply_change(index: usize, encoded: &mut dyn EncodingContainer,
-                allowed_change: u8, original_value: u8, target_difference: u8) {
+fn apply_change<T: Sample>(index: usize, encoded: &mut dyn EncodingContainer<T>,
+                allowed_change: T, original_value: T, target_difference: T) {
     if allowed_change < original_value {
-        encoded[index] = original_value - target_difference
+        encoded[index] = T::from_u32(original_value.to_u32() - target_difference.to_u32())
     } else {
-        encoded[index] = original_value + target_difference;
+        encoded[index] = T::from_u32(original_value.to_u32() + target_difference.to_u32());
     }
 }
 
 
-pub fn decode_into_vec(original: &dyn EncodingContainer, encoded: &dyn EncodingContainer) -> DifCodeResult<Vec<u8>> {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodingReport {
+    pub header: PayloadHeader,
+    /// None if the message was encoded without error correction
+    pub error_correction: Option<ErrorCorrectionReport>,
+}
+
+/// Decodes the message, repairs it if it was encoded with error correction and validates it against its envelope header.
+pub fn decode_into_vec<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<u8>> {
+    decode_into_vec_with_key(None, original, encoded)
+}
+/// Like decode_into_vec, for messages encoded with a key (a wrong key fails like a wrong image, i.e. with an invalid header).
+pub fn decode_into_vec_with_key<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<u8>> {
+    Ok(decode_into_vec_with_report(key, original, encoded)?.1)
+}
+/// Like decode_into_vec_with_key, but also returns the validated header (for example to check whether the message is encrypted)
+///    and how many errors were repaired.
+pub fn decode_into_vec_with_report<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> DifCodeResult<(DecodingReport, Vec<u8>)> {
+    let mut payload = Vec::new();
+    let report = decode_into_writer_with_report(key, original, encoded, &mut payload)?;
+    Ok((report, payload))
+}
+pub fn decode<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message_buffer: &mut dyn Pushable<u8>) -> DifCodeResult<()> {
+    decode_with_key(None, original, encoded, message_buffer)
+}
+pub fn decode_with_key<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message_buffer: &mut dyn Pushable<u8>) -> DifCodeResult<()> {
+    for byte in decode_into_vec_with_key(key, original, encoded)? {
+        if !message_buffer.push(byte) {
+            return Err(DifCodeError::Internal("could not push byte"))
+        }
+    }
+    Ok(())
+}
+
+/// Like decode_into_vec, but the message is written to the writer while it is decoded, so it never has to be in memory as a whole.
+/// The message is written before its checksum can be validated, if this fails the writer has already received the corrupted message.
+pub fn decode_into_writer<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message: &mut dyn Write) -> DifCodeResult<()> {
+    decode_into_writer_with_key(None, original, encoded, message)
+}
+pub fn decode_into_writer_with_key<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message: &mut dyn Write) -> DifCodeResult<()> {
+    decode_into_writer_with_report(key, original, encoded, message)?;
+    Ok(())
+}
+/// Like decode_into_writer_with_key, but also returns the validated header and how many errors were repaired (see decode_into_vec_with_report).
+pub fn decode_into_writer_with_report<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message: &mut dyn Write) -> DifCodeResult<DecodingReport> {
     if original.len() != encoded.len() {
         return Err(DifCodeError::InternalMismatchedContainerSizes)
     }
 
-    let mut decoded_message = Vec::with_capacity(get_encoded_message_length_in_bits(original, encoded));
-    decode(original, encoded, &mut decoded_message)?;
+    let mut extraction = StreamedExtraction::new(message, T::MAX_NUM_BITS);
+    decode_bits_in_rounds(key, original, encoded, &mut |decoded_bits, num_bits_per_position| {
+        extraction.push(decoded_bits, num_bits_per_position)?;
+        //the remaining differences only encode filler
+        Ok(!extraction.is_complete())
+    })?;
+
+    let (header, error_correction) = extraction.finish()?;
+    Ok(DecodingReport { header, error_correction })
+}
+
+pub fn decode_raw_into_vec<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<u8>> {
+    decode_raw_into_vec_with_key(None, original, encoded)
+}
+pub fn decode_raw_into_vec_with_key<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> DifCodeResult<Vec<u8>> {
+    if original.len() != encoded.len() {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+
+    let mut decoded_message = Vec::with_capacity(get_encoded_message_length_in_bits(original, encoded) / 8);
+    decode_raw_with_key(key, original, encoded, &mut decoded_message)?;
     Ok(decoded_message)
 }
 
-pub fn decode(original: &dyn EncodingContainer, encoded: &dyn EncodingContainer, message_buffer: &mut dyn Pushable<u8>) -> DifCodeResult<()> {
+/// Pushes every bit decodable from the differences between the containers, without interpreting an envelope (see encode_raw).
+pub fn decode_raw<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message_buffer: &mut dyn Pushable<u8>) -> DifCodeResult<()> {
+    decode_raw_with_key(None, original, encoded, message_buffer)
+}
+pub fn decode_raw_with_key<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, message_buffer: &mut dyn Pushable<u8>) -> DifCodeResult<()> {
     if original.len() != encoded.len() {
         return Err(DifCodeError::InternalMismatchedContainerSizes)
     }
 
     let mut message_builder = BytesBuilder::new(message_buffer);
-
-    let mut bit_buffer = [false; 8];
-    let mut num_pushed_counter = 0;
-    for i in 0..original.len() {
-        let difference = distance(original[i], encoded[i]);
-        // println!("original[i]: {}", original[i]);
-        // println!("encoded[i]: {}", encoded[i]);
-        // println!("difference: {}", difference);
-        let num_bits_decodable = get_num_bits_decodable(difference) as usize;
-        // println!("num_bits_decodable: {}", num_bits_decodable);
-        dynamic_difference_to_bits(i, difference, &mut bit_buffer[0..num_bits_decodable]);
-        // println!("bit_buffer[0..num_bits_decodable]: {:?}", &bit_buffer[0..num_bits_decodable]);
-        // static_difference_to_bits(difference, &mut bit_buffer[0..num_bits_decodable]);
-        for j in 0..num_bits_decodable {
-            if !message_builder.push(bit_buffer[j]) {
+    decode_bits_in_rounds(key, original, encoded, &mut |decoded_bits, _| {
+        for &bit in decoded_bits {
+            if !message_builder.push(bit) {
                 return Err(DifCodeError::Internal("could not push bit"))
             }
-            num_pushed_counter+=1;
         }
-    }
+        Ok(true)
+    })
+}
+
+//the positions are decoded in parallel rounds, so only the bits of one round have to be held in memory
+const DECODING_ROUND_LENGTH_PER_THREAD: usize = 1 << 16;
 
-    // println!("num pushed: {}", num_pushed_counter);
+type DecodedBitsConsumer<'a> = dyn FnMut(&[bool], &[u8]) -> DifCodeResult<bool> + 'a;
 
+/// Calls consume with the bits decoded at consecutive positions of the traversal order (all of them in order)
+///    and the number of bits decoded at each of the positions, until it returns false.
+fn decode_bits_in_rounds<T: Sample>(key: Option<&DifCodeKey>, original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, consume: &mut DecodedBitsConsumer) -> DifCodeResult<()> {
+    let traversal_order = TraversalOrder::new(key, original.len());
+    let round_length = DECODING_ROUND_LENGTH_PER_THREAD * parallel::num_threads_for(original.len());
+    for round_start in (0..original.len()).step_by(round_length) {
+        let round_end = (round_start + round_length).min(original.len());
+        let decoded_bits_per_range = parallel::map_ranges(round_end - round_start, |range| {
+            let mut decoded_bits = Vec::new();
+            let mut num_bits_per_position = Vec::with_capacity(range.len());
+            let mut bit_buffer = [false; 24];
+            for position in round_start + range.start..round_start + range.end {
+                let i = traversal_order.index_at(position);
+                let difference = original[i].distance(encoded[i]);
+                let num_bits_decodable = get_num_bits_decodable_of_sample(difference) as usize;
+                dynamic_difference_to_bits_with_key(key, i, difference, &mut bit_buffer[0..num_bits_decodable]);
+                decoded_bits.extend_from_slice(&bit_buffer[0..num_bits_decodable]);
+                num_bits_per_position.push(num_bits_decodable as u8);
+            }
+            (decoded_bits, num_bits_per_position)
+        });
+        for (decoded_bits, num_bits_per_position) in decoded_bits_per_range {
+            if !consume(&decoded_bits, &num_bits_per_position)? {
+                return Ok(())
+            }
+        }
+    }
     Ok(())
 }
 
 
 
-pub fn get_encoded_message_length_in_bits(original: &dyn EncodingContainer, encoded: &dyn EncodingContainer) -> usize {
+pub fn get_encoded_message_length_in_bits<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>) -> usize {
     if original.len() != encoded.len() {
         panic!("original len != allowed_changes_map len");
     }
 
-    let mut counter: usize = 0;
-    for i in 0..original.len() {
-        counter += get_num_bits_decodable(distance(original[i], encoded[i])) as usize;
-    }
-    counter
+    parallel::sum_indices(original.len(), |i| get_num_bits_decodable_of_sample(original[i].distance(encoded[i])) as usize)
 }
-pub fn get_max_encodable_message_length_in_bits(original: &dyn EncodingContainer, allowed_changes_map: &dyn EncodingContainer) -> usize {
+pub fn get_max_encodable_message_length_in_bits<T: Sample>(original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>) -> usize {
     if original.len() != allowed_changes_map.len() {
         panic!("original len != allowed_changes_map len");
     }
 
-    let mut counter: usize = 0;
-    for i in 0..original.len() {
-        counter += get_max_num_bits_encodable(distance(original[i], allowed_changes_map[i])) as usize;
+    parallel::sum_indices(original.len(), |i| get_max_num_bits_encodable_of_sample(original[i].distance(allowed_changes_map[i])) as usize)
+}
+pub fn get_min_encodable_message_length_in_bits<T: Sample>(original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>) -> usize {
+    if original.len() != allowed_changes_map.len() {
+        panic!("original len != allowed_changes_map len");
     }
-    counter
+
+    parallel::sum_indices(original.len(), |i| get_min_num_bits_encodable_of_sample(original[i].distance(allowed_changes_map[i])) as usize)
 }
-pub fn get_min_encodable_message_length_in_bits(original: &dyn EncodingContainer, allowed_changes_map: &dyn EncodingContainer) -> usize {
+/// The average number of bits the map makes room for, if the message bits are random (like those of encrypted or compressed messages).
+pub fn get_expected_encodable_message_length_in_bits<T: Sample>(original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>) -> usize {
     if original.len() != allowed_changes_map.len() {
         panic!("original len != allowed_changes_map len");
     }
 
-    let mut counter: usize = 0;
-    for i in 0..original.len() {
-        counter += get_min_num_bits_encodable(distance(original[i], allowed_changes_map[i])) as usize;
+    let partial_sums = parallel::map_ranges(original.len(), |range| {
+        range.map(|i| get_expected_num_bits_encodable_of_sample(original[i].distance(allowed_changes_map[i]))).sum::<f64>()
+    });
+    partial_sums.into_iter().sum::<f64>() as usize
+}
+
+/// How long a message can be, in bytes after the overhead of the encoding options (envelope header and error correction).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityReport {
+    /// fits regardless of the message content
+    pub guaranteed_bytes: usize,
+    /// fits on average, if the message bits are random (e.g. encrypted or compressed messages)
+    pub expected_bytes: usize,
+    /// fits only if the message content happens to fit every index best
+    pub maximum_bytes: usize,
+}
+impl CapacityReport {
+    /// The capacity for the original message, if it grows by the given number of bytes before it is encoded (e.g. by encryption).
+    pub fn without_overhead(&self, overhead_in_bytes: usize) -> CapacityReport {
+        CapacityReport {
+            guaranteed_bytes: self.guaranteed_bytes.saturating_sub(overhead_in_bytes),
+            expected_bytes: self.expected_bytes.saturating_sub(overhead_in_bytes),
+            maximum_bytes: self.maximum_bytes.saturating_sub(overhead_in_bytes),
+        }
     }
-    counter
-}
\ No newline at end of file
+}
+/// The capacity of the allowed changes map for messages encoded with the given options.
+pub fn get_capacity_report<T: Sample>(options: &EncodingOptions, original: &dyn EncodingContainer<T>, allowed_changes_map: &dyn EncodingContainer<T>) -> CapacityReport {
+    let to_message_bytes = |embedded_length_in_bits| options.get_max_message_length_in_bits(embedded_length_in_bits) / 8;
+    CapacityReport {
+        guaranteed_bytes: to_message_bytes(get_min_encodable_message_length_in_bits(original, allowed_changes_map)),
+        expected_bytes: to_message_bytes(get_expected_encodable_message_length_in_bits(original, allowed_changes_map)),
+        maximum_bytes: to_message_bytes(get_max_encodable_message_length_in_bits(original, allowed_changes_map)),
+    }
+}
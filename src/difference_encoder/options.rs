@@ -0,0 +1,63 @@
+use crate::difference_encoder::envelope::{self, PayloadFlags, HEADER_LENGTH_IN_BYTES};
+use crate::difference_encoder::error_correction;
+use crate::difference_encoder::key::DifCodeKey;
+use crate::util::{DifCodeError, DifCodeResult};
+
+/// Everything besides the message and the containers that determines what encode embeds.
+/// The decoder does not require the options (except the key), they are recovered from the embedded data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncodingOptions {
+    pub flags: PayloadFlags,
+    /// Number of reed solomon parity symbols per block of 255 bytes (None for no error correction).
+    /// Up to half as many altered bytes per block can be repaired by the decoder.
+    pub error_correction: Option<u8>,
+    /// Permutes the code tables and the traversal order, the decoder requires the same key (None for the unkeyed tables in index order).
+    pub key: Option<DifCodeKey>,
+}
+impl EncodingOptions {
+    pub fn with_flags(mut self, flags: PayloadFlags) -> EncodingOptions {
+        self.flags = flags;
+        self
+    }
+    pub fn with_error_correction(mut self, parity_symbols: u8) -> EncodingOptions {
+        self.error_correction = Some(parity_symbols);
+        self
+    }
+    pub fn with_key(mut self, key: DifCodeKey) -> EncodingOptions {
+        self.key = Some(key);
+        self
+    }
+
+    /// The number of bits actually encoded for a message of the given length (i.e. including envelope and error correction).
+    pub fn get_embedded_length_in_bits(&self, message_length_in_bytes: usize) -> usize {
+        let enveloped_length = HEADER_LENGTH_IN_BYTES + message_length_in_bytes;
+        match self.error_correction {
+            None => enveloped_length * 8,
+            Some(parity_symbols) => error_correction::get_protected_length_in_bytes(enveloped_length, parity_symbols) * 8
+        }
+    }
+    /// Inverse of get_embedded_length_in_bits, i.e. how many message bits fit, if the given number of bits can be embedded.
+    pub fn get_max_message_length_in_bits(&self, embedded_length_in_bits: usize) -> usize {
+        match self.error_correction {
+            None => embedded_length_in_bits.saturating_sub(envelope::HEADER_LENGTH_IN_BITS),
+            Some(parity_symbols) => (error_correction::get_max_data_length_in_bytes(embedded_length_in_bits / 8, parity_symbols) * 8)
+                .saturating_sub(envelope::HEADER_LENGTH_IN_BITS)
+        }
+    }
+    /// Capacity errors of the raw bitstream count the overhead bits as well, this converts them into the number of message bits that fit.
+    pub fn payload_capacity_error(&self, err: DifCodeError) -> DifCodeError {
+        match err {
+            DifCodeError::InternalCapacityReached(num_bits) => DifCodeError::InternalCapacityReached(self.get_max_message_length_in_bits(num_bits)),
+            other => other
+        }
+    }
+
+    /// The bytes that are encoded into the container for the given message.
+    pub fn create_embedded_stream(&self, message_bytes: &[u8]) -> DifCodeResult<Vec<u8>> {
+        let enveloped = envelope::wrap(message_bytes, self.flags)?;
+        match self.error_correction {
+            None => Ok(enveloped),
+            Some(parity_symbols) => error_correction::protect(&enveloped, parity_symbols)
+        }
+    }
+}
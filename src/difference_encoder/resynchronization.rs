@@ -0,0 +1,336 @@
+use jokrey_utilities::general::BytesBuilder;
+
+use crate::difference_encoder::envelope::{self, HEADER_LENGTH_IN_BYTES};
+use crate::difference_encoder::error_correction::{self, BlockCoder, ErrorCorrectionReport, MAX_BLOCK_LENGTH, PREAMBLE_LENGTH_IN_BYTES, PROTECTED_PREAMBLE_LENGTH_IN_BYTES};
+use crate::util::{DifCodeError, DifCodeResult};
+
+//A value that is altered across the bit ranges of the differences (e.g. a difference of 0 that becomes 1) decodes to a different number of bits.
+//This inserts or drops bits and shifts all following bytes of the error corrected stream, so every following block would be uncorrectable.
+//The blocks are therefore read from the bits decoded at each position: if a block cannot be repaired at its expected start,
+//    it is assumed that a single position within the block (or the unit before it, i.e. the preamble or the previous block)
+//    decoded to a different number of bits. Its bits are replaced by as many erased (zero) bits as the assumption requires,
+//    so that the blocks are in sync again and the erased bits are repaired like any other altered byte.
+//Of the assumptions with the smallest shift that repair the blocks, the one that needs the fewest corrections is taken.
+//The preamble is resynchronized the same way, validated by the first block.
+//
+//Positions that decode no bits are not stored, an assumption that one of them decoded some bits
+//    is the same as inserting the bits in front of the next position that decodes any.
+const PREAMBLE_LENGTH_IN_BITS: usize = PROTECTED_PREAMBLE_LENGTH_IN_BYTES * 8;
+const MAX_BLOCK_LENGTH_IN_BITS: usize = MAX_BLOCK_LENGTH * 8;
+
+/// The number of bits that have to be decoded to know whether a stream is error corrected (see ResynchronizedRepair::detect).
+pub(crate) fn get_detection_length_in_bits(max_num_bits: u8) -> usize {
+    PREAMBLE_LENGTH_IN_BITS + MAX_BLOCK_LENGTH_IN_BITS + max_num_bits as usize
+}
+
+pub(crate) fn bits_to_bytes(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(bits.len() / 8);
+    let mut builder = BytesBuilder::new(&mut bytes);
+    for &bit in bits {
+        builder.push(bit);
+    }
+    bytes
+}
+
+/// The bits decoded at consecutive positions of the traversal order.
+#[derive(Default)]
+pub(crate) struct DecodedPositions {
+    bits: Vec<bool>,
+    //where the bits of each position start, only for the positions that decode at least one bit
+    starts: Vec<usize>,
+}
+impl DecodedPositions {
+    pub(crate) fn push(&mut self, bits: &[bool], num_bits_per_position: &[u8]) {
+        let mut start = self.bits.len();
+        for &num_bits in num_bits_per_position {
+            if num_bits > 0 {
+                self.starts.push(start);
+                start += num_bits as usize;
+            }
+        }
+        self.bits.extend_from_slice(bits);
+    }
+
+    pub(crate) fn bits(&self) -> &[bool] {
+        &self.bits
+    }
+
+    fn end_of(&self, position: usize) -> usize {
+        self.starts.get(position + 1).cloned().unwrap_or(self.bits.len())
+    }
+
+    /// The bits from..from+length as they are after the resynchronization, bits missing at the end are zero.
+    fn bits_after(&self, resynchronization: Option<&Resynchronization>, from: usize, length: usize) -> Vec<bool> {
+        let mut bits = Vec::with_capacity(length);
+        match resynchronization {
+            None => bits.extend_from_slice(&self.bits[from.min(self.bits.len())..(from + length).min(self.bits.len())]),
+            Some(resynchronization) => {
+                let rest = resynchronization.offset + resynchronization.num_dropped;
+                bits.extend_from_slice(&self.bits[from..resynchronization.offset]);
+                bits.resize(bits.len() + resynchronization.num_inserted, false);
+                bits.extend_from_slice(&self.bits[rest..(rest + length).min(self.bits.len())]);
+            }
+        }
+        bits.resize(length, false);
+        bits
+    }
+
+    fn apply(&mut self, resynchronization: &Resynchronization) {
+        let Resynchronization { offset, num_dropped, num_inserted } = *resynchronization;
+        self.bits.splice(offset..offset + num_dropped, std::iter::repeat_n(false, num_inserted));
+        let mut position = self.starts.partition_point(|start| *start < offset);
+        if num_dropped > 0 {
+            self.starts.remove(position);
+        }
+        if num_inserted > 0 {
+            self.starts.insert(position, offset);
+            position += 1;
+        }
+        for start in &mut self.starts[position..] {
+            *start = *start + num_inserted - num_dropped;
+        }
+    }
+
+    /// Forgets the positions before the one containing the bit at offset, returns by how many bits the following offsets moved.
+    fn forget_before(&mut self, offset: usize) -> usize {
+        //the bits are only removed once they make up half of the buffer, so that forgetting them stays cheap
+        let num_positions = self.starts.partition_point(|start| *start <= offset).saturating_sub(1);
+        if num_positions == 0 || offset < self.bits.len() / 2 {
+            return 0
+        }
+        let num_bits = self.starts[num_positions];
+        self.bits.drain(..num_bits);
+        self.starts.drain(..num_positions);
+        for start in &mut self.starts {
+            *start -= num_bits;
+        }
+        num_bits
+    }
+}
+
+/// The assumption that the position starting at offset decoded num_dropped bits, but should have decoded num_inserted bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Resynchronization {
+    offset: usize,
+    num_dropped: usize,
+    num_inserted: usize,
+}
+
+//tries the resynchronization of every position starting within from..to, with the smallest shift first,
+//    and returns the one whose evaluation succeeds with the fewest corrections
+fn search<R>(positions: &DecodedPositions, max_num_bits: u8, from: usize, to: usize, evaluate: impl Fn(&Resynchronization) -> Option<(R, usize)>) -> Option<(Resynchronization, R)> {
+    let first = positions.starts.partition_point(|start| *start < from);
+    let last = positions.starts.partition_point(|start| *start < to);
+    let max_num_bits = max_num_bits as usize;
+    for shift in 1..=max_num_bits {
+        let mut best: Option<(Resynchronization, R, usize)> = None;
+        for position in first..last {
+            let offset = positions.starts[position];
+            let num_bits = positions.end_of(position) - offset;
+            let candidates = [
+                //a position in front of it that decodes no bits
+                Some(Resynchronization { offset, num_dropped: 0, num_inserted: shift }),
+                Some(Resynchronization { offset, num_dropped: num_bits, num_inserted: num_bits + shift }).filter(|_| num_bits + shift <= max_num_bits),
+                Some(Resynchronization { offset, num_dropped: num_bits, num_inserted: num_bits.wrapping_sub(shift) }).filter(|_| num_bits >= shift),
+            ];
+            for resynchronization in candidates.iter().flatten() {
+                if let Some((result, num_corrections)) = evaluate(resynchronization) {
+                    if best.as_ref().is_none_or(|(_, _, best_num_corrections)| num_corrections < *best_num_corrections) {
+                        best = Some((*resynchronization, result, num_corrections));
+                    }
+                }
+            }
+        }
+        if let Some((resynchronization, result, _)) = best {
+            return Some((resynchronization, result))
+        }
+    }
+    None
+}
+
+fn read_preamble(bits: &[bool]) -> Option<(u8, usize)> {
+    error_correction::read_protected_preamble(&bits_to_bytes(bits)).ok()
+}
+//the number of bytes in which the copies of the preamble differ, counted as corrections of a resynchronized preamble
+//    (the majority of copies that are out of sync can still look valid, for example announce fewer parity symbols)
+fn count_preamble_disagreements(bits: &[bool]) -> usize {
+    let preamble = bits_to_bytes(bits);
+    (0..PREAMBLE_LENGTH_IN_BYTES).filter(|i| preamble[*i] != preamble[i + PREAMBLE_LENGTH_IN_BYTES] || preamble[*i] != preamble[i + 2 * PREAMBLE_LENGTH_IN_BYTES]).count()
+}
+
+fn repair_block(coder: &BlockCoder, bits: &[bool], data_length: usize) -> Option<RepairedBlock> {
+    let mut block = bits_to_bytes(&bits[..(data_length + coder.parity_symbols()) * 8]);
+    let corrected_symbols = coder.repair_block(&mut block)?;
+    block.truncate(data_length);
+    Some(RepairedBlock { data: block, corrected_symbols })
+}
+
+struct RepairedBlock {
+    data: Vec<u8>,
+    corrected_symbols: usize,
+}
+//the units repaired with (or without) a resynchronization, the preamble and the previous block only if the resynchronization changed them
+struct Repaired {
+    preamble: Option<(u8, usize)>,
+    previous_block: Option<RepairedBlock>,
+    block: RepairedBlock,
+}
+
+/// Repairs an error corrected stream block by block, while it is decoded, and resynchronizes it (see above).
+pub(crate) struct ResynchronizedRepair {
+    positions: DecodedPositions,
+    max_num_bits: u8,
+    coder: BlockCoder,
+    data_length: usize,
+    //the data length of all blocks before the current one
+    data_length_read: usize,
+    //where the previous unit (the preamble or a block) and the current block start within the positions
+    previous_start: usize,
+    current_start: usize,
+    //only passed on once the current block is repaired, a resynchronization may still change it (None after the preamble)
+    previous_block: Option<RepairedBlock>,
+    report: ErrorCorrectionReport,
+}
+impl ResynchronizedRepair {
+    /// Starts the repair if the decoded positions begin with an error correction preamble (see error_correction::is_protected),
+    ///    the positions are taken in that case.
+    /// A preamble is resynchronized only if the stream does not begin with an envelope header instead.
+    pub(crate) fn detect(positions: &mut DecodedPositions, max_num_bits: u8) -> Option<ResynchronizedRepair> {
+        let mut report = ErrorCorrectionReport { parity_symbols: 0, num_blocks: 0, corrected_symbols: 0, resynchronized_values: 0 };
+        let (parity_symbols, data_length) = match read_preamble(&positions.bits_after(None, 0, PREAMBLE_LENGTH_IN_BITS)) {
+            Some(preamble) => preamble,
+            None => {
+                if envelope::read_header(&bits_to_bytes(&positions.bits_after(None, 0, HEADER_LENGTH_IN_BYTES * 8))).is_ok() {
+                    return None
+                }
+                let (resynchronization, preamble) = search(positions, max_num_bits, 0, PREAMBLE_LENGTH_IN_BITS, |resynchronization| {
+                    let bits = positions.bits_after(Some(resynchronization), 0, PREAMBLE_LENGTH_IN_BITS + MAX_BLOCK_LENGTH_IN_BITS);
+                    let (parity_symbols, data_length) = read_preamble(&bits[..PREAMBLE_LENGTH_IN_BITS])?;
+                    let coder = BlockCoder::new(parity_symbols).ok()?;
+                    let block = repair_block(&coder, &bits[PREAMBLE_LENGTH_IN_BITS..], coder.data_per_block().min(data_length))?;
+                    Some(((parity_symbols, data_length), count_preamble_disagreements(&bits[..PREAMBLE_LENGTH_IN_BITS]) + block.corrected_symbols))
+                })?;
+                positions.apply(&resynchronization);
+                report.resynchronized_values += 1;
+                preamble
+            }
+        };
+        report.parity_symbols = parity_symbols;
+        Some(ResynchronizedRepair {
+            positions: std::mem::take(positions), max_num_bits, coder: BlockCoder::new(parity_symbols).ok()?,
+            data_length, data_length_read: 0, previous_start: 0, current_start: PREAMBLE_LENGTH_IN_BITS, previous_block: None, report,
+        })
+    }
+
+    /// Passes the data of every block that can be repaired to output.
+    pub(crate) fn push(&mut self, bits: &[bool], num_bits_per_position: &[u8], output: &mut dyn FnMut(&[u8]) -> DifCodeResult<()>) -> DifCodeResult<()> {
+        self.positions.push(bits, num_bits_per_position);
+        self.repair_available(false, output)
+    }
+
+    /// Repairs the remaining blocks (with the bits decoded so far), fails if the stream ended early or a block cannot be repaired.
+    pub(crate) fn finish(mut self, output: &mut dyn FnMut(&[u8]) -> DifCodeResult<()>) -> DifCodeResult<ErrorCorrectionReport> {
+        self.repair_available(true, output)?;
+        Ok(self.report)
+    }
+
+    fn repair_available(&mut self, is_final: bool, output: &mut dyn FnMut(&[u8]) -> DifCodeResult<()>) -> DifCodeResult<()> {
+        while self.data_length_read < self.data_length {
+            let block_end = self.current_start + (self.next_block_data_length() + self.coder.parity_symbols()) * 8;
+            //a resynchronization can require up to max_num_bits more bits
+            if !is_final && self.positions.bits.len() < block_end + self.max_num_bits as usize {
+                return Ok(())
+            }
+            let (resynchronization, repaired) = match self.repair(None) {
+                Some((repaired, _)) => (None, repaired),
+                None => match search(&self.positions, self.max_num_bits, self.previous_start, block_end, |resynchronization| self.repair(Some(resynchronization))) {
+                    Some((resynchronization, repaired)) => (Some(resynchronization), repaired),
+                    None if self.positions.bits.len() < block_end => return Err(DifCodeError::InvalidHeader("error corrected stream shorter than announced in preamble")),
+                    None => return Err(DifCodeError::UncorrectableErrors { block: self.report.num_blocks }),
+                }
+            };
+            self.accept(resynchronization, repaired, output)?;
+        }
+        Ok(())
+    }
+
+    fn next_block_data_length(&self) -> usize {
+        self.coder.data_per_block().min(self.data_length - self.data_length_read)
+    }
+
+    //repairs the current block (and the unit before it, if the resynchronization changes it),
+    //    returns the repaired units and the number of corrections in both
+    fn repair(&self, resynchronization: Option<&Resynchronization>) -> Option<(Repaired, usize)> {
+        let current_offset = self.current_start - self.previous_start;
+        let bits = self.positions.bits_after(resynchronization, self.previous_start, current_offset + MAX_BLOCK_LENGTH_IN_BITS);
+        let changes_previous = resynchronization.is_some_and(|resynchronization| resynchronization.offset < self.current_start);
+
+        let mut repaired = Repaired { preamble: None, previous_block: None, block: RepairedBlock { data: Vec::new(), corrected_symbols: 0 } };
+        let mut num_corrections = self.previous_block.as_ref().map_or(0, |previous| previous.corrected_symbols);
+        let reread_coder;
+        let mut coder = &self.coder;
+        let mut block_data_length = self.next_block_data_length();
+        if changes_previous {
+            match &self.previous_block {
+                None => {
+                    let (parity_symbols, data_length) = read_preamble(&bits[..current_offset])?;
+                    if parity_symbols as usize != self.coder.parity_symbols() {
+                        reread_coder = BlockCoder::new(parity_symbols).ok()?;
+                        coder = &reread_coder;
+                    }
+                    block_data_length = coder.data_per_block().min(data_length);
+                    num_corrections = count_preamble_disagreements(&bits[..current_offset]);
+                    repaired.preamble = Some((parity_symbols, data_length));
+                }
+                Some(previous) => {
+                    let previous_block = repair_block(coder, &bits, previous.data.len())?;
+                    num_corrections = previous_block.corrected_symbols;
+                    repaired.previous_block = Some(previous_block);
+                }
+            }
+        }
+        repaired.block = repair_block(coder, &bits[current_offset..], block_data_length)?;
+        num_corrections += repaired.block.corrected_symbols;
+        Some((repaired, num_corrections))
+    }
+
+    fn accept(&mut self, resynchronization: Option<Resynchronization>, repaired: Repaired, output: &mut dyn FnMut(&[u8]) -> DifCodeResult<()>) -> DifCodeResult<()> {
+        if let Some(resynchronization) = resynchronization {
+            self.positions.apply(&resynchronization);
+            self.report.resynchronized_values += 1;
+        }
+        if let Some((parity_symbols, data_length)) = repaired.preamble {
+            if parity_symbols as usize != self.coder.parity_symbols() {
+                self.coder = BlockCoder::new(parity_symbols)?;
+            }
+            self.report.parity_symbols = parity_symbols;
+            self.data_length = data_length;
+        }
+        if let Some(previous_block) = repaired.previous_block {
+            let replaced = self.previous_block.replace(previous_block);
+            self.report.corrected_symbols -= replaced.map_or(0, |replaced| replaced.corrected_symbols);
+            self.report.corrected_symbols += self.previous_block.as_ref().unwrap().corrected_symbols;
+        }
+        if let Some(previous_block) = self.previous_block.take() {
+            output(&previous_block.data)?;
+        }
+
+        let block = repaired.block;
+        self.report.corrected_symbols += block.corrected_symbols;
+        self.report.num_blocks += 1;
+        self.data_length_read += block.data.len();
+        self.previous_start = self.current_start;
+        self.current_start += (block.data.len() + self.coder.parity_symbols()) * 8;
+        //no later block can change the last one
+        if self.data_length_read == self.data_length {
+            output(&block.data)?;
+        } else {
+            self.previous_block = Some(block);
+        }
+
+        let num_forgotten = self.positions.forget_before(self.previous_start);
+        self.previous_start -= num_forgotten;
+        self.current_start -= num_forgotten;
+        Ok(())
+    }
+}
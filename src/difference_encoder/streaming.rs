@@ -0,0 +1,245 @@
+use std::io::{ErrorKind, Read, Write};
+
+use crate::difference_encoder::envelope::{self, PayloadFlags, PayloadHeader, StreamingCrc, FORMAT_VERSION, HEADER_LENGTH_IN_BYTES};
+use crate::difference_encoder::error_correction::{self, BlockCoder, ErrorCorrectionReport, PROTECTED_PREAMBLE_LENGTH_IN_BYTES};
+use crate::difference_encoder::resynchronization::{self, DecodedPositions, ResynchronizedRepair};
+use crate::difference_encoder::options::EncodingOptions;
+use crate::util::{DifCodeError, DifCodeResult};
+
+//The embedded stream (see EncodingOptions::create_embedded_stream) of a message that is read from a reader, or written to a writer, piece by piece.
+//The envelope header (and the error correction preamble) announce the length and the crc of the whole payload,
+//    so the prefix of the stream that contains them (the header, or the preamble and every block containing a byte of the header)
+//    is only known once the message has been read completely. The encoder reserves room for the prefix (see reserved_length_in_bytes)
+//    and fills it at the end, all following bytes are produced while the message is read.
+
+const READ_CHUNK_LENGTH: usize = 4096;
+
+pub(crate) struct StreamedEmbedding<'a> {
+    reader: &'a mut dyn Read,
+    flags: PayloadFlags,
+    block_coder: Option<BlockCoder>,
+    crc: StreamingCrc,
+    //the payload bytes that are part of the prefix (i.e. in the blocks containing the header)
+    prefix_payload: Vec<u8>,
+    prefix_payload_length: usize,
+    is_read_completely: bool,
+    error: Option<DifCodeError>,
+}
+impl<'a> StreamedEmbedding<'a> {
+    pub(crate) fn new(reader: &'a mut dyn Read, options: &EncodingOptions) -> DifCodeResult<StreamedEmbedding<'a>> {
+        let block_coder = options.error_correction.map(BlockCoder::new).transpose()?;
+        let prefix_payload_length = match &block_coder {
+            None => 0,
+            Some(coder) => num_prefix_blocks(coder) * coder.data_per_block() - HEADER_LENGTH_IN_BYTES,
+        };
+        Ok(StreamedEmbedding {
+            reader, flags: options.flags, block_coder, crc: StreamingCrc::new(),
+            prefix_payload: Vec::with_capacity(prefix_payload_length), prefix_payload_length,
+            is_read_completely: false, error: None,
+        })
+    }
+
+    /// The maximum length of the prefix, it is shorter only if the message ends within the first blocks.
+    pub(crate) fn reserved_length_in_bytes(&self) -> usize {
+        match &self.block_coder {
+            None => HEADER_LENGTH_IN_BYTES,
+            Some(coder) => PROTECTED_PREAMBLE_LENGTH_IN_BYTES + num_prefix_blocks(coder) * (coder.data_per_block() + coder.parity_symbols()),
+        }
+    }
+
+    /// The next bytes of the stream after the prefix, None once the message is read completely (or reading failed, see finish).
+    pub(crate) fn next_chunk(&mut self) -> Option<Vec<u8>> {
+        if self.is_read_completely || self.error.is_some() {
+            return None
+        }
+        while self.prefix_payload.len() < self.prefix_payload_length {
+            let missing = self.prefix_payload_length - self.prefix_payload.len();
+            let prefix_payload = self.read_payload(missing)?;
+            self.prefix_payload.extend_from_slice(&prefix_payload);
+        }
+
+        let chunk = match &self.block_coder {
+            None => self.read_payload(READ_CHUNK_LENGTH)?,
+            Some(coder) => {
+                let data_per_block = coder.data_per_block();
+                let data = self.read_payload(data_per_block * (READ_CHUNK_LENGTH / data_per_block).max(1))?;
+                let coder = self.block_coder.as_ref().unwrap();
+                let mut chunk = Vec::with_capacity(data.len() + data.len() / data_per_block * coder.parity_symbols() + coder.parity_symbols());
+                for block in data.chunks(data_per_block) {
+                    coder.protect_block(block, &mut chunk);
+                }
+                chunk
+            }
+        };
+        Some(chunk)
+    }
+
+    /// The prefix of the stream, fails if reading the message failed.
+    pub(crate) fn finish(mut self) -> DifCodeResult<Vec<u8>> {
+        //the prefix may not have been requested, for example if the message ends within it
+        while !self.is_read_completely && self.error.is_none() && self.next_chunk().is_some() {}
+        if let Some(error) = self.error {
+            return Err(error)
+        }
+        let payload_length = self.crc.payload_length() as u32;
+        let header = envelope::create_header(self.flags, payload_length, self.crc.finish(FORMAT_VERSION, self.flags));
+        match &self.block_coder {
+            None => Ok(header.to_vec()),
+            Some(coder) => {
+                let mut data = header.to_vec();
+                data.extend_from_slice(&self.prefix_payload);
+                let mut prefix = error_correction::create_preamble(HEADER_LENGTH_IN_BYTES as u32 + payload_length, coder.parity_symbols() as u8);
+                for block in data.chunks(coder.data_per_block()) {
+                    coder.protect_block(block, &mut prefix);
+                }
+                Ok(prefix)
+            }
+        }
+    }
+
+    //reads up to max_length bytes, less only at the end of the message, None if there are no more bytes
+    fn read_payload(&mut self, max_length: usize) -> Option<Vec<u8>> {
+        let mut payload = vec![0u8; max_length];
+        let mut length = 0;
+        while length < max_length {
+            match self.reader.read(&mut payload[length..]) {
+                Ok(0) => {
+                    self.is_read_completely = true;
+                    break;
+                }
+                Ok(num_read) => length += num_read,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.error = Some(DifCodeError::from(err));
+                    return None
+                }
+            }
+        }
+        payload.truncate(length);
+        self.crc.update(&payload);
+        if self.crc.payload_length() > (u32::MAX as usize) - HEADER_LENGTH_IN_BYTES {
+            self.error = Some(DifCodeError::Internal("payload too long for envelope"));
+            return None
+        }
+        if payload.is_empty() { None } else { Some(payload) }
+    }
+}
+
+//the blocks that contain at least one byte of the header
+fn num_prefix_blocks(coder: &BlockCoder) -> usize {
+    HEADER_LENGTH_IN_BYTES.div_ceil(coder.data_per_block())
+}
+
+
+
+/// Consumes the embedded stream as it is decoded, repairs it block by block (if it is error corrected)
+///    and writes the payload to the writer, while it validates the envelope.
+/// Note: The payload is written before its crc can be validated, a mismatch is only reported by finish.
+pub(crate) struct StreamedExtraction<'a> {
+    writer: &'a mut dyn Write,
+    max_num_bits: u8,
+    //until it is known whether the stream is error corrected
+    lookahead: Option<DecodedPositions>,
+    error_correction: Option<ResynchronizedRepair>,
+    //the decoded bits that do not make up a whole byte yet (if the stream is not error corrected)
+    remaining_bits: Vec<bool>,
+    header_bytes: Vec<u8>,
+    header: Option<PayloadHeader>,
+    crc: StreamingCrc,
+}
+impl<'a> StreamedExtraction<'a> {
+    /// max_num_bits is the maximum number of bits a single position can decode (see Sample::MAX_NUM_BITS).
+    pub(crate) fn new(writer: &'a mut dyn Write, max_num_bits: u8) -> StreamedExtraction<'a> {
+        StreamedExtraction {
+            writer, max_num_bits, lookahead: Some(DecodedPositions::default()), error_correction: None, remaining_bits: Vec::new(),
+            header_bytes: Vec::with_capacity(HEADER_LENGTH_IN_BYTES), header: None, crc: StreamingCrc::new(),
+        }
+    }
+
+    /// Whether the whole payload has been written (all further bytes are ignored).
+    pub(crate) fn is_complete(&self) -> bool {
+        match &self.header {
+            Some(header) => self.crc.payload_length() == header.payload_length as usize,
+            None => false
+        }
+    }
+
+    /// Consumes the bits decoded at the next positions, num_bits_per_position holds how many of them each position decoded.
+    pub(crate) fn push(&mut self, bits: &[bool], num_bits_per_position: &[u8]) -> DifCodeResult<()> {
+        if let Some(lookahead) = &mut self.lookahead {
+            lookahead.push(bits, num_bits_per_position);
+            if lookahead.bits().len() < resynchronization::get_detection_length_in_bits(self.max_num_bits) {
+                return Ok(())
+            }
+            return self.detect_error_correction()
+        }
+
+        match self.error_correction.take() {
+            None => self.push_enveloped_bits(bits),
+            Some(mut repair) => {
+                let result = repair.push(bits, num_bits_per_position, &mut |data| self.push_enveloped(data));
+                self.error_correction = Some(repair);
+                result
+            }
+        }
+    }
+
+    /// The header of the envelope and the error correction report, fails if the stream ended early or the crc does not match.
+    pub(crate) fn finish(mut self) -> DifCodeResult<(PayloadHeader, Option<ErrorCorrectionReport>)> {
+        //streams shorter than the lookahead
+        if self.lookahead.is_some() {
+            self.detect_error_correction()?;
+        }
+        let error_correction = match self.error_correction.take() {
+            Some(repair) => Some(repair.finish(&mut |data| self.push_enveloped(data))?),
+            None => None
+        };
+        let header = self.header.take().ok_or(DifCodeError::InvalidHeader("no header found (too few bits decoded)"))?;
+        if self.crc.payload_length() < header.payload_length as usize {
+            return Err(DifCodeError::InvalidHeader("payload shorter than announced in header"))
+        }
+        let actual = self.crc.finish(header.version, header.flags);
+        if actual != header.crc {
+            return Err(DifCodeError::ChecksumMismatch { expected: header.crc, actual })
+        }
+        self.writer.flush()?;
+        Ok((header, error_correction))
+    }
+
+    fn detect_error_correction(&mut self) -> DifCodeResult<()> {
+        let mut lookahead = self.lookahead.take().unwrap();
+        match ResynchronizedRepair::detect(&mut lookahead, self.max_num_bits) {
+            Some(repair) => {
+                self.error_correction = Some(repair);
+                self.push(&[], &[])
+            }
+            None => self.push_enveloped_bits(lookahead.bits())
+        }
+    }
+
+    fn push_enveloped_bits(&mut self, bits: &[bool]) -> DifCodeResult<()> {
+        self.remaining_bits.extend_from_slice(bits);
+        let num_whole_bits = self.remaining_bits.len() / 8 * 8;
+        let enveloped = resynchronization::bits_to_bytes(&self.remaining_bits[..num_whole_bits]);
+        self.remaining_bits.drain(..num_whole_bits);
+        self.push_enveloped(&enveloped)
+    }
+
+    fn push_enveloped(&mut self, mut enveloped: &[u8]) -> DifCodeResult<()> {
+        if self.header.is_none() {
+            let num_missing = (HEADER_LENGTH_IN_BYTES - self.header_bytes.len()).min(enveloped.len());
+            self.header_bytes.extend_from_slice(&enveloped[..num_missing]);
+            enveloped = &enveloped[num_missing..];
+            if self.header_bytes.len() < HEADER_LENGTH_IN_BYTES {
+                return Ok(())
+            }
+            self.header = Some(envelope::read_header(&self.header_bytes)?);
+        }
+        let payload_length = self.header.as_ref().unwrap().payload_length as usize;
+        let num_payload_bytes = (payload_length - self.crc.payload_length()).min(enveloped.len());
+        let payload = &enveloped[..num_payload_bytes];
+        self.crc.update(payload);
+        self.writer.write_all(payload)?;
+        Ok(())
+    }
+}
@@ -1,9 +1,28 @@
-use crate::util::{DifCodeImage, get_length_in_bits, EncodingContainer, DifCodeError};
-use crate::difference_encoder::bits_difference_converter::{dynamic_bits_to_difference, get_num_bits_decodable, dynamic_difference_to_bits, static_difference_to_bits, static_bits_to_difference, static_bits_to_difference_if_allowed};
-use crate::difference_encoder::multi_bit::{decode_into_vec, encode_into_image, encode_into_vec, decode, encode, get_max_encodable_message_length_in_bits, get_min_encodable_message_length_in_bits};
-use crate::difference_encoder::legacy_single_bit::{randomly_select_indices_within, encode_into_vec_at_indices, decode_into_vec_at_indices, encode_into_image_at_indices, randomly_select_indices};
-use crate::difference_encoder::max_change_map_creator::{create_minimal_evenly_random_allowed_changes_map, create_minimal_evenly_random_allowed_changes_map_for, write_minimal_evenly_random_max_area_average_allowed_changes_map_for, write_minimal_evenly_random_allowed_changes_map_for};
+use std::ops::Range;
+
+use crate::util::{DifCodeImage, DifCodeRgbaImage, DifCodeImage16, DifCodeLumaImage, DifCodeLumaImage16, Sample, get_length_in_bits, EncodingContainer, DifCodeError};
+use crate::integral_image::{IntegralRgbImage, IntegralVarianceImage};
+use crate::difference_encoder::bits_difference_converter::{dynamic_bits_to_difference, dynamic_bits_to_difference_with_key, get_num_bits_decodable, dynamic_difference_to_bits, dynamic_difference_to_bits_with_key, static_difference_to_bits, static_bits_to_difference, static_bits_to_difference_if_allowed};
+use crate::difference_encoder::multi_bit::{decode_into_vec, encode_into_image, encode_into_rgba_image, encode_into_image_with_options, encode_into_vec, decode, decode_raw, encode_raw, get_max_encodable_message_length_in_bits, get_min_encodable_message_length_in_bits, decode_into_vec_with_report, encode_into_vec_with_options, decode_raw_into_vec, decode_into_vec_with_key, encode_into_image16, encode_into_luma_image, encode_into_luma_image16, encode_into_container, encode_from_reader, encode_from_reader_with_options, decode_into_writer, decode_into_writer_with_report, decode_raw_into_vec_with_key, get_encoded_message_length_in_bits, get_expected_encodable_message_length_in_bits, get_capacity_report};
+use crate::difference_encoder::legacy_single_bit::{randomly_select_indices_within, encode_into_vec_at_indices, decode_into_vec_at_indices, encode_into_image_at_indices, randomly_select_indices, encode_at_indices};
+use crate::difference_encoder::max_change_map_creator::{create_minimal_evenly_random_allowed_changes_map, create_minimal_evenly_random_allowed_changes_map_for, write_minimal_evenly_random_max_area_average_allowed_changes_map_for, write_minimal_evenly_random_allowed_changes_map_for, write_minimal_evenly_random_allowed_changes_map_with, create_minimal_evenly_random_rgba_allowed_changes_map, create_minimal_evenly_random_max_area_average_rgba_allowed_changes_map, create_minimal_evenly_random_max_area_average_luma_allowed_changes_map, create_minimal_evenly_random_max_local_variance_allowed_changes_map, create_minimal_evenly_random_max_local_variance_luma_allowed_changes_map, write_minimal_stratified_allowed_changes_map_with, create_minimal_stratified_allowed_changes_map_for_image, create_minimal_stratified_rgba_allowed_changes_map, write_minimal_evenly_random_image_allowed_changes_map, create_minimal_evenly_random_max_area_average_image16_allowed_changes_map, create_minimal_evenly_random_max_area_average_luma16_allowed_changes_map, create_minimal_evenly_random_max_local_variance_image16_allowed_changes_map, create_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map, DEFAULT_AREA_AVERAGE_RADIUS, DEFAULT_LOCAL_VARIANCE_RADIUS};
+use crate::difference_encoder::envelope::{wrap, unwrap, crc32, PayloadFlags, HEADER_LENGTH_IN_BYTES};
+use crate::difference_encoder::error_correction::{protect, repair, get_protected_length_in_bytes, get_max_data_length_in_bytes, PROTECTED_PREAMBLE_LENGTH_IN_BYTES};
+use crate::difference_encoder::options::EncodingOptions;
+use crate::difference_encoder::key::DifCodeKey;
+use crate::jpeg::{DifCodeJpeg, create_minimal_evenly_random_jpeg_allowed_changes_map};
+use crate::animation::{DifCodeGif, DifCodeApng};
+use crate::audio::{DifCodeWav, create_minimal_evenly_random_max_amplitude_allowed_changes_map};
+use crate::raw_file::{DifCodeRawFile, create_minimal_evenly_random_raw_file_allowed_changes_map};
+use crate::color::{DeltaEFormula, LabConverter, create_minimal_evenly_random_max_delta_e_allowed_changes_map, create_minimal_evenly_random_max_delta_e_luma_allowed_changes_map, DEFAULT_MAX_DELTA_E, create_minimal_evenly_random_max_delta_e_image16_allowed_changes_map, create_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map};
+use crate::metrics::{get_quality_metrics, get_quality_metrics_of_planes};
+use crate::steganalysis::{get_steganalysis_scores, get_steganalysis_report, chi_square_attack, rs_analysis, sample_pair_analysis};
+use crate::difference_encoder::traversal_order::TraversalOrder;
+use crate::difference_encoder::channel_weighting::ChannelWeighting;
 use jokrey_utilities::time_keeper::TimeKeeper;
+use jokrey_utilities::general::{distance, BytesBuilder};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 #[test]
 fn test_encode_details() {
@@ -16,19 +35,20 @@ fn test_encode_details() {
 
     let mut encoded = vec![123u8; 32];
 
-    encode(message, &original, &allowed_changes_map, &mut encoded).expect("error encoding");
+    encode_raw(message, &original, &allowed_changes_map, &mut encoded).expect("error encoding");
 
     println!("encoded            : {:?}", encoded);
 
 
-    let allowed_changes_map = create_minimal_evenly_random_allowed_changes_map_for(message, &original, 2).expect("could not create map");
+    let mut allowed_changes_map = vec![0u8; original.len()];
+    write_minimal_evenly_random_allowed_changes_map_with(get_length_in_bits(message), &original, 2, &mut rand::thread_rng(), &mut allowed_changes_map).expect("could not create map");
 
     println!("original           : {:?}", original);
     println!("allowed_changes_map: {:?}", allowed_changes_map);
 
     encoded = vec![123u8; 32];
 
-    encode(message, &original, &allowed_changes_map, &mut encoded).expect("error encoding");
+    encode_raw(message, &original, &allowed_changes_map, &mut encoded).expect("error encoding");
 
     println!("encoded            : {:?}", encoded);
 }
@@ -84,14 +104,14 @@ fn generic_test() {
     let original = vec![0u8, 0u8];
     let allowed_changes = vec![255u8, 255u8];
     let mut encoded = vec![0u8, 0u8];
-    encode(message, &original, &allowed_changes, &mut encoded).unwrap();
+    encode_raw(message, &original, &allowed_changes, &mut encoded).unwrap();
     println!("message: {:?}", message);
     println!("original: {:?}", original);
     println!("allowed_changes: {:?}", allowed_changes);
     println!("encoded: {:?}", encoded);
 
     let mut decoded_message = vec![];
-    decode(&original, &encoded, &mut decoded_message).unwrap();
+    decode_raw(&original, &encoded, &mut decoded_message).unwrap();
     println!("decoded_message: {:?}", decoded_message);
 
     assert_eq!(&message[..], &decoded_message[..]);
@@ -296,13 +316,16 @@ fn test_multi_bit_encoding() {
     println!("message        : {:?}", message_bytes);
     println!("original       : {:?}", original);
 
-    let mut allowed_changes: Vec<u8> = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original).expect("could not create map");
+    //too small for the envelope header, so the raw bitstream is tested
+    let mut allowed_changes = vec![0u8; original.len()];
+    write_minimal_evenly_random_allowed_changes_map_with(get_length_in_bits(&message_bytes), &original, 255, &mut rand::thread_rng(), &mut allowed_changes).expect("could not create map");
     println!("allowed_changes: {:?}", allowed_changes);
 
-    let encoded= encode_into_vec(&message_bytes, &original, &mut allowed_changes).unwrap();
+    let mut encoded = vec![0u8; original.len()];
+    encode_raw(&message_bytes, &original, &allowed_changes, &mut encoded).unwrap();
     println!("encoded        : {:?}", encoded);
 
-    let decoded = decode_into_vec(&original, &encoded).unwrap();
+    let decoded = decode_raw_into_vec(&original, &encoded).unwrap();
     println!("decoded        : {:?}", decoded);
 
     assert_eq!(message_bytes, decoded);
@@ -602,7 +625,7 @@ fn test_image_without_save_single_bit() {
     let original_image = DifCodeImage::open(original_image_path).unwrap();
 
     let encoded_image = encode_into_image_at_indices(&message_bytes, &original_image,
-                                                     &randomly_select_indices(get_length_in_bits(&message_bytes), original_image.len())
+                                                     &randomly_select_indices(get_length_in_bits(&message_bytes), original_image.len(), &mut rand::thread_rng())
     ).expect("encoding failed");
 
     let decoded_message = decode_into_vec_at_indices(&original_image, &encoded_image).unwrap();
@@ -623,7 +646,7 @@ fn test_image_with_save_single_bit() {
     let original_image = DifCodeImage::open(original_image_path).unwrap();
 
     let encoded_image = encode_into_image_at_indices(&message_bytes, &original_image,
-                                                     &randomly_select_indices(get_length_in_bits(&message_bytes), original_image.len())
+                                                     &randomly_select_indices(get_length_in_bits(&message_bytes), original_image.len(), &mut rand::thread_rng())
     ).expect("encoding/saving failed");
     encoded_image.save(encoded_image_path).expect("saving image failed");
 
@@ -654,7 +677,8 @@ fn test_max_encodable_message_length() {
     let message_bytes: Vec<u8> = (0..1).map(|_| { rand::random::<u8>() }).collect();
     let original: Vec<u8> = (0..8).map(|x| x as u8).collect();
 
-    let allowed_changes_map = create_minimal_evenly_random_allowed_changes_map_for(&message_bytes, &original, 2).expect("failed to select indices");
+    let mut allowed_changes_map = vec![0u8; original.len()];
+    write_minimal_evenly_random_allowed_changes_map_with(get_length_in_bits(&message_bytes), &original, 2, &mut rand::thread_rng(), &mut allowed_changes_map).expect("failed to select indices");
     let min_encodable = get_min_encodable_message_length_in_bits(&original, &allowed_changes_map);
     let max_encodable = get_max_encodable_message_length_in_bits(&original, &allowed_changes_map);
 
@@ -693,4 +717,1186 @@ fn test_max_encodable_message_length() {
     // decode(&original, &encoded, &mut decoded).expect("decoding failed");
     //
     // println!("decoded      : {:?}", decoded);
-}
\ No newline at end of file
+}
+
+
+
+#[test]
+fn test_envelope_round_trip() {
+    assert_eq!(0xCBF43926, crc32(b"123456789"));
+
+    let message_bytes = b"Hallo dies ist ein Test!?".to_vec();
+    let enveloped = wrap(&message_bytes, PayloadFlags::ENCRYPTED).unwrap();
+    assert_eq!(HEADER_LENGTH_IN_BYTES + message_bytes.len(), enveloped.len());
+
+    let (header, payload) = unwrap(&enveloped).unwrap();
+    assert_eq!(message_bytes, payload);
+    assert_eq!(message_bytes.len() as u32, header.payload_length);
+    assert!(header.flags.contains(PayloadFlags::ENCRYPTED));
+    assert!(!header.flags.contains(PayloadFlags::COMPRESSED));
+
+    //trailing bytes are not part of the message
+    let mut with_trailing = enveloped.clone();
+    with_trailing.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(message_bytes, unwrap(&with_trailing).unwrap().1);
+
+    let mut corrupted = enveloped.clone();
+    corrupted[HEADER_LENGTH_IN_BYTES + 3] ^= 0b0001_0000;
+    assert!(matches!(unwrap(&corrupted), Err(DifCodeError::ChecksumMismatch { .. })));
+
+    let mut wrong_version = enveloped.clone();
+    wrong_version[2] = 200;
+    assert!(matches!(unwrap(&wrong_version), Err(DifCodeError::UnsupportedFormatVersion(200))));
+
+    assert!(matches!(unwrap(&enveloped[..HEADER_LENGTH_IN_BYTES + 2]), Err(DifCodeError::InvalidHeader(_))));
+    assert!(matches!(unwrap(&message_bytes), Err(DifCodeError::InvalidHeader(_))));
+}
+
+#[test]
+fn test_encode_writes_envelope() {
+    let message_bytes = vec![1u8, 123u8, 98u8];
+    let original: Vec<u8> = (0..500).map(|x| x as u8).collect();
+
+    let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original).expect("could not create map");
+    let encoded = encode_into_vec_with_options(&message_bytes, &EncodingOptions::default().with_flags(PayloadFlags::COMPRESSED), &original, &allowed_changes).unwrap();
+
+    let raw = decode_raw_into_vec(&original, &encoded).unwrap();
+    assert_eq!(HEADER_LENGTH_IN_BYTES + message_bytes.len(), raw.len());
+
+    let (report, decoded) = decode_into_vec_with_report(None, &original, &encoded).unwrap();
+    assert_eq!(message_bytes, decoded);
+    assert_eq!(PayloadFlags::COMPRESSED, report.header.flags);
+    assert_eq!(None, report.error_correction);
+
+    let mut pushed = Vec::new();
+    decode(&original, &encoded, &mut pushed).unwrap();
+    assert_eq!(message_bytes, pushed);
+
+    //unchanged containers hold no message
+    assert!(matches!(decode_into_vec(&original, &original), Err(DifCodeError::InvalidHeader(_))));
+
+    //a single altered value is detected
+    let mut altered = encoded.clone();
+    let changed_index = (0..original.len()).rev().find(|i| original[*i] != encoded[*i]).unwrap();
+    altered[changed_index] = original[changed_index];
+    assert!(decode_into_vec(&original, &altered).is_err());
+}
+
+
+#[test]
+fn test_error_correction_repairs_bytes() {
+    let data: Vec<u8> = (0..600).map(|_| { rand::random::<u8>() }).collect();
+    for parity_symbols in [2u8, 8, 32, 254].iter().cloned() {
+        let protected = protect(&data, parity_symbols).unwrap();
+        assert_eq!(get_protected_length_in_bytes(data.len(), parity_symbols), protected.len());
+        assert!(data.len() <= get_max_data_length_in_bytes(protected.len(), parity_symbols));
+
+        let (repaired, report) = repair(&protected).unwrap();
+        assert_eq!(data, repaired);
+        assert_eq!(0, report.corrected_symbols);
+
+        //alter as many bytes per block as can be repaired, including the (repeated) preamble
+        let mut altered = protected.clone();
+        altered[1] ^= 0xFF;
+        let block_length = 255;
+        let preamble_length = protected.len() - data.len() - report.num_blocks * parity_symbols as usize;
+        let mut expected_corrections = 0;
+        for block_start in (preamble_length..altered.len()).step_by(block_length) {
+            let block_end = (block_start + block_length).min(altered.len());
+            for i in 0..(parity_symbols as usize / 2).min(block_end - block_start) {
+                altered[block_start + (i * 7) % (block_end - block_start)] ^= (i as u8).wrapping_mul(31) | 1;
+                expected_corrections += 1;
+            }
+        }
+        let (repaired, report) = repair(&altered).unwrap();
+        assert_eq!(data, repaired);
+        assert_eq!(expected_corrections, report.corrected_symbols);
+    }
+
+    //too many errors are detected
+    let protected = protect(&data, 4).unwrap();
+    let mut altered = protected.clone();
+    for i in 0..3 {
+        altered[40 + i] ^= 0x55;
+    }
+    assert!(repair(&altered).is_err());
+}
+
+#[test]
+fn test_image_with_error_correction() {
+    //seeded, so that the altered values below are the same in every run
+    let mut rng = StdRng::seed_from_u64(4);
+    let message_bytes: Vec<u8> = (0..64).map(|_| { rng.gen::<u8>() }).collect();
+    let original_image = DifCodeImage::open("test/RealisticTestImage.jpg").unwrap();
+    let options = EncodingOptions::default().with_error_correction(16);
+
+    let mut allowed_changes = vec![0u8; original_image.len()];
+    write_minimal_evenly_random_allowed_changes_map_with(options.get_embedded_length_in_bits(message_bytes.len()), &original_image, 255, &mut rng, &mut allowed_changes).expect("could not create map");
+    let mut encoded_image = encode_into_image_with_options(&message_bytes, &options, &original_image, &allowed_changes).expect("encoding failed");
+
+    let (report, decoded) = decode_into_vec_with_report(None, &original_image, &encoded_image).unwrap();
+    assert_eq!(message_bytes, decoded);
+    assert_eq!(Some(0), report.error_correction.map(|r| r.corrected_symbols));
+
+    //alter changed values in a way that keeps the number of bits decodable (i.e. substitutes bits): a difference of 1 becomes 2 and vice versa
+    //the (triple redundant) preamble is left alone, it cannot be repaired if two copies get the same bit flipped
+    let mut num_altered = 0;
+    let mut num_bits_before = 0;
+    for i in 0..original_image.len() {
+        let difference = distance(original_image[i], encoded_image[i]);
+        let is_after_preamble = num_bits_before >= PROTECTED_PREAMBLE_LENGTH_IN_BYTES * 8;
+        num_bits_before += get_num_bits_decodable(difference) as usize;
+        if is_after_preamble && i % 7 == 0 && (difference == 1 || difference == 2) {
+            encoded_image[i] = if encoded_image[i] > original_image[i] { original_image[i] + 3 - difference } else { original_image[i] - (3 - difference) };
+            num_altered += 1;
+            if num_altered == 4 {
+                break;
+            }
+        }
+    }
+    assert_eq!(4, num_altered);
+    let (report, decoded) = decode_into_vec_with_report(None, &original_image, &encoded_image).unwrap();
+    assert_eq!(message_bytes, decoded);
+    assert!(report.error_correction.unwrap().corrected_symbols > 0);
+}
+
+#[test]
+fn test_image_with_error_correction_resynchronizes() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let message_bytes: Vec<u8> = (0..400).map(|_| { rng.gen::<u8>() }).collect();
+    let original_image = DifCodeImage::open("test/RealisticTestImage.jpg").unwrap();
+    let options = EncodingOptions::default().with_error_correction(16);
+    let embedded_length_in_bits = options.get_embedded_length_in_bits(message_bytes.len());
+
+    //unchanged values and differences of up to three bits
+    let allowed_changes: Vec<u8> = (0..original_image.len()).map(|i| {
+        let max_change = [0, 0, 2, 6, 14][rng.gen_range(0..5)];
+        if original_image[i] < 128 { original_image[i] + max_change } else { original_image[i] - max_change }
+    }).collect();
+    let encoded_image = encode_into_image_with_options(&message_bytes, &options, &original_image, &allowed_changes).expect("encoding failed");
+    let encoded: Vec<u8> = (0..encoded_image.len()).map(|i| encoded_image[i]).collect();
+
+    //the index and the offset (of its first decoded bit) of every value within the stream
+    let mut positions = Vec::new();
+    let mut offset = 0;
+    for i in TraversalOrder::new(None, original_image.len()).iter() {
+        if offset >= embedded_length_in_bits - 8 {
+            break;
+        }
+        positions.push((i, offset));
+        offset += get_num_bits_decodable(distance(original_image[i], encoded[i])) as usize;
+    }
+    let preamble_length_in_bits = PROTECTED_PREAMBLE_LENGTH_IN_BYTES * 8;
+    let with_difference = |i: usize, difference: u8| {
+        let original = original_image[i];
+        if encoded[i] >= original && original as usize + difference as usize <= 255 { original + difference } else { original - difference }
+    };
+
+    //alterations that change the number of bits decodable (inserting or dropping bits), within the preamble and within the blocks
+    //    the differences 3 to 6 decode two bits, 7 to 14 three bits
+    let cases = [(true, 0..=0, 1), (true, 1..=1, 0), (true, 2..=2, 3), (false, 0..=0, 1), (false, 1..=1, 0), (false, 2..=2, 3), (false, 3..=6, 7), (false, 7..=14, 6)];
+    for (is_in_preamble, from, to) in cases.iter().cloned() {
+        let candidates: Vec<usize> = positions.iter()
+            .filter(|(i, offset)| (*offset < preamble_length_in_bits) == is_in_preamble && from.contains(&distance(original_image[*i], encoded[*i])))
+            .map(|(i, _)| *i)
+            .collect();
+        assert!(!candidates.is_empty(), "no difference within {:?} (in preamble: {})", from, is_in_preamble);
+        let i = candidates[rng.gen_range(0..candidates.len())];
+        let mut altered = encoded.clone();
+        altered[i] = with_difference(i, to);
+
+        let (report, decoded) = decode_into_vec_with_report(None, &original_image, &altered).unwrap();
+        assert_eq!(message_bytes, decoded, "difference within {:?} altered to {} (in preamble: {})", from, to, is_in_preamble);
+        assert_eq!(1, report.error_correction.unwrap().resynchronized_values);
+    }
+
+    //arbitrary values altered by one
+    for _ in 0..12 {
+        let (i, offset) = positions[rng.gen_range(0..positions.len())];
+        let mut altered = encoded.clone();
+        altered[i] = if altered[i] == 255 || (altered[i] > 0 && rng.gen()) { altered[i] - 1 } else { altered[i] + 1 };
+
+        let (report, decoded) = decode_into_vec_with_report(None, &original_image, &altered).unwrap();
+        assert_eq!(message_bytes, decoded);
+        let report = report.error_correction.unwrap();
+        //the preamble is repaired by its majority vote, which is not reported
+        if offset >= preamble_length_in_bits {
+            assert!(report.corrected_symbols + report.resynchronized_values > 0);
+        }
+    }
+
+    //only a single resynchronization per block is tried, two that are far apart within the same (first) block are only detected
+    let mut altered = encoded.clone();
+    for block_offset in [600, 1400].iter() {
+        let (i, _) = positions.iter().find(|(i, offset)| *offset >= preamble_length_in_bits + block_offset && encoded[*i] == original_image[*i]).unwrap();
+        altered[*i] = with_difference(*i, 1);
+    }
+    assert!(decode_into_vec_with_report(None, &original_image, &altered).is_err());
+}
+
+
+
+#[test]
+fn test_keyed_code_tables() {
+    let key = DifCodeKey::from_password("secret");
+    assert_eq!(key, DifCodeKey::from_password("secret"));
+    assert_ne!(key, DifCodeKey::from_password("Secret"));
+
+    let bits = [true, false, true, true];
+    let mut num_tables_differing = 0;
+    for index in 0..100 {
+        let unkeyed = dynamic_bits_to_difference(index, &bits).unwrap();
+        let keyed = dynamic_bits_to_difference_with_key(Some(&key), index, &bits).unwrap();
+        assert_eq!(unkeyed, dynamic_bits_to_difference_with_key(None, index, &bits).unwrap());
+        assert_eq!(get_num_bits_decodable(unkeyed), get_num_bits_decodable(keyed));
+        if unkeyed != keyed {
+            num_tables_differing += 1;
+        }
+
+        let mut decoded_bits = [false; 4];
+        dynamic_difference_to_bits_with_key(Some(&key), index, keyed, &mut decoded_bits);
+        assert_eq!(bits, decoded_bits);
+    }
+    assert!(num_tables_differing > 50);
+}
+
+#[test]
+fn test_image_with_key() {
+    let message_bytes: Vec<u8> = (0..64).map(|_| { rand::random::<u8>() }).collect();
+    let original_image = DifCodeImage::open("test/RealisticTestImage.jpg").unwrap();
+    let key = DifCodeKey::from_password("correct horse battery staple");
+    let options = EncodingOptions::default().with_key(key);
+
+    let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_image).expect("could not create map");
+    let encoded_image = encode_into_image_with_options(&message_bytes, &options, &original_image, &allowed_changes).expect("encoding failed");
+
+    assert_eq!(message_bytes, decode_into_vec_with_key(Some(&key), &original_image, &encoded_image).unwrap());
+    //the image pair alone (or a wrong key) does not reveal the message
+    assert!(decode_into_vec(&original_image, &encoded_image).is_err());
+    assert!(decode_into_vec_with_key(Some(&DifCodeKey::from_password("wrong")), &original_image, &encoded_image).is_err());
+}
+
+#[test]
+fn test_traversal_order() {
+    let key = DifCodeKey::from_password("secret");
+    for len in [0usize, 1, 2, 3, 17, 64, 1000, 4097] {
+        assert_eq!((0..len).collect::<Vec<usize>>(), TraversalOrder::new(None, len).iter().collect::<Vec<usize>>());
+
+        let keyed: Vec<usize> = TraversalOrder::new(Some(&key), len).iter().collect();
+        assert_eq!(keyed, TraversalOrder::new(Some(&key), len).iter().collect::<Vec<usize>>());
+        let mut sorted = keyed.clone();
+        sorted.sort();
+        assert_eq!((0..len).collect::<Vec<usize>>(), sorted);
+        if len >= 17 {
+            assert_ne!((0..len).collect::<Vec<usize>>(), keyed);
+            assert_ne!(keyed, TraversalOrder::new(Some(&DifCodeKey::from_password("other")), len).iter().collect::<Vec<usize>>());
+        }
+    }
+}
+
+
+
+//the test image with an opaque center, fading out (soft edge) towards the border
+fn create_rgba_test_image() -> DifCodeRgbaImage {
+    let rgb = DifCodeImage::open("test/RealisticTestImage.jpg").unwrap();
+    let (w, h) = (rgb.width(), rgb.height());
+    let rgba = image::RgbaImage::from_fn(w, h, |x, y| {
+        let p = rgb.get_pixel(x, y).0;
+        let distance_to_border = x.min(y).min(w - 1 - x).min(h - 1 - y);
+        [p[0], p[1], p[2], (distance_to_border * 8).min(255) as u8].into()
+    });
+    DifCodeRgbaImage::from(rgba)
+}
+
+#[test]
+fn test_rgba_image_preserves_alpha() {
+    let message_bytes: Vec<u8> = (0..64).map(|_| { rand::random::<u8>() }).collect();
+    let original_image = create_rgba_test_image();
+    assert!(original_image.has_transparency());
+
+    let allowed_changes = create_minimal_evenly_random_rgba_allowed_changes_map(&message_bytes, &original_image, false).expect("could not create map");
+    let encoded_image = encode_into_rgba_image(&message_bytes, &original_image, &allowed_changes).expect("encoding failed");
+    for x in 0..original_image.width() {
+        for y in 0..original_image.height() {
+            assert_eq!(original_image.get_alpha(x, y), encoded_image.get_alpha(x, y));
+        }
+    }
+
+    let encoded_image_path = "test/RealisticTestImageRgba.png";
+    encoded_image.save(encoded_image_path).expect("saving image failed");
+    let encoded_image_reloaded = DifCodeRgbaImage::open(encoded_image_path).unwrap();
+    assert_eq!(encoded_image, encoded_image_reloaded);
+    assert_eq!(message_bytes, decode_into_vec(&original_image, &encoded_image_reloaded).unwrap());
+    //the alpha channel is not silently dropped
+    assert!(matches!(DifCodeImage::open(encoded_image_path), Err(image::ImageError::Unsupported(_))));
+    assert!(DifCodeLumaImage::open(encoded_image_path).is_err());
+}
+
+#[test]
+fn test_rgba_image_embeds_in_varied_alpha_only() {
+    let original_image = create_rgba_test_image();
+    //more than fits into the color channels of the soft edge alone, so the alpha of the edge has to be used as well
+    let message_bytes: Vec<u8> = (0..512).map(|_| { rand::random::<u8>() }).collect();
+
+    for allowed_changes in [create_minimal_evenly_random_rgba_allowed_changes_map(&message_bytes, &original_image, true).expect("could not create map"),
+                            create_minimal_evenly_random_max_area_average_rgba_allowed_changes_map(&message_bytes, &original_image, true).expect("could not create map")] {
+        let encoded_image = encode_into_rgba_image(&message_bytes, &original_image, &allowed_changes).expect("encoding failed");
+        assert_eq!(message_bytes, decode_into_vec(&original_image, &encoded_image).unwrap());
+
+        let mut num_alpha_changed = 0;
+        for x in 0..original_image.width() {
+            for y in 0..original_image.height() {
+                let (min, max) = original_image.alpha_range_in_radius(x, y, 1);
+                let encoded_alpha = encoded_image.get_alpha(x, y);
+                assert!(min <= encoded_alpha && encoded_alpha <= max);
+                if encoded_alpha != original_image.get_alpha(x, y) {
+                    num_alpha_changed += 1;
+                }
+            }
+        }
+        assert!(num_alpha_changed > 0);
+    }
+}
+
+#[test]
+fn test_u16_vec() {
+    let message_bytes: Vec<u8> = (0..128).map(|_| { rand::random::<u8>() }).collect();
+    let original: Vec<u16> = (0..2048).map(|_| { rand::random::<u16>() }).collect();
+
+    for max_difference in [2u16, 14, 254, 4094, u16::MAX] {
+        let allowed_changes = create_minimal_evenly_random_allowed_changes_map_for(&message_bytes, &original, max_difference).expect("could not create map");
+        for i in 0..original.len() {
+            assert!(original[i].distance(allowed_changes[i]) <= max_difference);
+        }
+        let encoded = encode_into_vec(&message_bytes, &original, &allowed_changes).expect("encoding failed");
+        for i in 0..original.len() {
+            assert!(original[i].distance(encoded[i]) <= original[i].distance(allowed_changes[i]));
+        }
+        assert_eq!(message_bytes, decode_into_vec(&original, &encoded).unwrap());
+    }
+}
+
+#[test]
+fn test_image16() {
+    let message_bytes: Vec<u8> = (0..2048).map(|_| { rand::random::<u8>() }).collect();
+    let rgb = DifCodeImage::open("test/RealisticTestImage.jpg").unwrap();
+    let original_image = DifCodeImage16::from(image::DynamicImage::ImageRgb8(rgb.raw().clone()).into_rgb16());
+
+    let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_image).expect("could not create map");
+    let encoded_image = encode_into_image16(&message_bytes, &original_image, &allowed_changes).expect("encoding failed");
+
+    let encoded_image_path = "test/RealisticTestImage16.png";
+    encoded_image.save(encoded_image_path).expect("saving image failed");
+    let encoded_image_reloaded = DifCodeImage16::open(encoded_image_path).unwrap();
+    assert_eq!(encoded_image, encoded_image_reloaded);
+    assert_eq!(message_bytes, decode_into_vec(&original_image, &encoded_image_reloaded).unwrap());
+}
+
+#[test]
+fn test_luma_image_stays_grayscale() {
+    let message_bytes: Vec<u8> = (0..256).map(|_| { rand::random::<u8>() }).collect();
+    let original_image = DifCodeLumaImage::from(image::open("test/RealisticTestImage.jpg").unwrap().into_luma8());
+
+    for allowed_changes in [create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_image).expect("could not create map"),
+                            create_minimal_evenly_random_max_area_average_luma_allowed_changes_map(&message_bytes, &original_image).expect("could not create map")] {
+        let encoded_image = encode_into_luma_image(&message_bytes, &original_image, &allowed_changes).expect("encoding failed");
+
+        let encoded_image_path = "test/RealisticTestImageLuma.png";
+        encoded_image.save(encoded_image_path).expect("saving image failed");
+        assert_eq!(image::ColorType::L8, image::open(encoded_image_path).unwrap().color());
+        let encoded_image_reloaded = DifCodeLumaImage::open(encoded_image_path).unwrap();
+        assert_eq!(encoded_image, encoded_image_reloaded);
+        assert_eq!(message_bytes, decode_into_vec(&original_image, &encoded_image_reloaded).unwrap());
+    }
+}
+
+#[test]
+fn test_luma_image16_stays_grayscale() {
+    let message_bytes: Vec<u8> = (0..1024).map(|_| { rand::random::<u8>() }).collect();
+    let original_image = DifCodeLumaImage16::from(image::open("test/RealisticTestImage.jpg").unwrap().into_luma16());
+
+    let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_image).expect("could not create map");
+    let encoded_image = encode_into_luma_image16(&message_bytes, &original_image, &allowed_changes).expect("encoding failed");
+
+    let encoded_image_path = "test/RealisticTestImageLuma16.png";
+    encoded_image.save(encoded_image_path).expect("saving image failed");
+    assert_eq!(image::ColorType::L16, image::open(encoded_image_path).unwrap().color());
+    let encoded_image_reloaded = DifCodeLumaImage16::open(encoded_image_path).unwrap();
+    assert_eq!(encoded_image, encoded_image_reloaded);
+    assert_eq!(message_bytes, decode_into_vec(&original_image, &encoded_image_reloaded).unwrap());
+}
+
+#[test]
+fn test_jpeg_rewrite_keeps_coefficients() {
+    let original_bytes = std::fs::read("test/RealisticTestImage.jpg").unwrap();
+    let original_jpeg = DifCodeJpeg::from_memory(&original_bytes).unwrap();
+
+    //only the entropy coding is redone, so the decoded pixels are identical
+    let rewritten_bytes = original_jpeg.to_bytes();
+    assert_eq!(original_jpeg, DifCodeJpeg::from_memory(&rewritten_bytes).unwrap());
+    assert_eq!(image::load_from_memory(&original_bytes).unwrap().into_rgb8().into_raw(),
+               image::load_from_memory(&rewritten_bytes).unwrap().into_rgb8().into_raw());
+}
+
+#[test]
+fn test_jpeg() {
+    let message_bytes: Vec<u8> = (0..512).map(|_| { rand::random::<u8>() }).collect();
+    let original_jpeg = DifCodeJpeg::open("test/RealisticTestImage.jpg").unwrap();
+
+    let allowed_changes = create_minimal_evenly_random_jpeg_allowed_changes_map(&message_bytes, &original_jpeg).expect("could not create map");
+    let encoded_jpeg = encode_into_container(&message_bytes, &original_jpeg, &allowed_changes).expect("encoding failed");
+    for i in 0..original_jpeg.len() {
+        let (original_coefficient, encoded_coefficient) = (original_jpeg.get_coefficient(i), encoded_jpeg.get_coefficient(i));
+        if original_coefficient == 0 {
+            assert_eq!(0, encoded_coefficient);
+        } else {
+            assert_eq!(original_coefficient.signum(), encoded_coefficient.signum());
+            assert!(encoded_coefficient.abs() >= original_coefficient.abs());
+        }
+    }
+
+    let encoded_jpeg_path = "test/RealisticTestImageDct.jpg";
+    encoded_jpeg.save(encoded_jpeg_path).expect("saving jpeg failed");
+    image::open(encoded_jpeg_path).expect("encoded jpeg is not a valid jpeg");
+    let encoded_jpeg_reloaded = DifCodeJpeg::open(encoded_jpeg_path).unwrap();
+    assert_eq!(encoded_jpeg, encoded_jpeg_reloaded);
+    assert_eq!(message_bytes, decode_into_vec(&original_jpeg, &encoded_jpeg_reloaded).unwrap());
+}
+
+
+
+fn create_test_gif(with_global_palette: bool) -> Vec<u8> {
+    let image = image::open("test/RealisticTestImage.jpg").unwrap().into_rgb8();
+    let crop = |x: u32, y: u32, w: u32, h: u32| image::imageops::crop_imm(&image, x, y, w, h).to_image().into_raw();
+    let gray_palette: Vec<u8> = (0..=255u8).flat_map(|v| [v, v, v]).collect();
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, 64, 48, if with_global_palette { &gray_palette } else { &[] }).unwrap();
+        encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+        let mut first = gif::Frame::from_rgb_speed(64, 48, &crop(0, 0, 64, 48), 10);
+        if with_global_palette {
+            first.palette = None;
+            first.buffer = crop(0, 0, 64, 48).chunks(3).map(|rgb| rgb[1]).collect::<Vec<u8>>().into();
+        }
+        first.delay = 10;
+        encoder.write_frame(&first).unwrap();
+        let mut second = gif::Frame::from_rgb_speed(32, 16, &crop(20, 20, 32, 16), 10);
+        (second.left, second.top, second.delay, second.dispose) = (8, 4, 25, gif::DisposalMethod::Background);
+        encoder.write_frame(&second).unwrap();
+        let mut third = gif::Frame::from_rgb_speed(16, 16, &crop(40, 10, 16, 16), 10);
+        (third.left, third.top, third.delay, third.dispose) = (30, 20, 50, gif::DisposalMethod::Previous);
+        encoder.write_frame(&third).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn test_gif() {
+    for with_global_palette in [true, false] {
+        let original_gif = DifCodeGif::from_memory(&create_test_gif(with_global_palette)).unwrap();
+        assert_eq!(3, original_gif.num_frames());
+        //rewriting an unchanged gif keeps the container (i.e. no global palette appears)
+        assert_eq!(original_gif, DifCodeGif::from_memory(&original_gif.to_bytes().unwrap()).unwrap());
+
+        let message_bytes: Vec<u8> = (0..128).map(|_| { rand::random::<u8>() }).collect();
+        let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_gif).expect("could not create map");
+        let encoded_gif = encode_into_container(&message_bytes, &original_gif, &allowed_changes).expect("encoding failed");
+
+        let encoded_gif_path = "test/AnimatedTestImagePalettes.gif";
+        encoded_gif.save(encoded_gif_path).expect("saving gif failed");
+        image::open(encoded_gif_path).expect("encoded gif is not a valid gif");
+        let encoded_gif_reloaded = DifCodeGif::open(encoded_gif_path).unwrap();
+        assert_eq!(encoded_gif, encoded_gif_reloaded);
+        assert_eq!(original_gif.frame_timings(), encoded_gif_reloaded.frame_timings());
+        assert_eq!(vec![(10, gif::DisposalMethod::Keep), (25, gif::DisposalMethod::Background), (50, gif::DisposalMethod::Previous)],
+                   encoded_gif_reloaded.frame_timings());
+        assert_eq!(message_bytes, decode_into_vec(&original_gif, &encoded_gif_reloaded).unwrap());
+    }
+}
+
+
+fn create_test_apng(with_separate_default_image: bool) -> Vec<u8> {
+    let image = image::open("test/RealisticTestImage.jpg").unwrap().into_rgba8();
+    let crop = |x: u32, y: u32, w: u32, h: u32| {
+        let mut frame = image::imageops::crop_imm(&image, x, y, w, h).to_image();
+        //varied alpha, which has to stay untouched
+        frame.pixels_mut().enumerate().for_each(|(i, pixel)| pixel.0[3] = (i % 256) as u8);
+        frame.into_raw()
+    };
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, 64, 48);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(3, 0).unwrap();
+        encoder.set_sep_def_img(with_separate_default_image).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        if with_separate_default_image {
+            writer.write_image_data(&crop(60, 60, 64, 48)).unwrap();
+        }
+        writer.set_frame_delay(1, 10).unwrap();
+        writer.write_image_data(&crop(0, 0, 64, 48)).unwrap();
+        writer.set_frame_dimension(32, 16).unwrap();
+        writer.set_frame_position(8, 4).unwrap();
+        writer.set_frame_delay(1, 4).unwrap();
+        writer.set_dispose_op(png::DisposeOp::Background).unwrap();
+        writer.set_blend_op(png::BlendOp::Over).unwrap();
+        writer.write_image_data(&crop(20, 20, 32, 16)).unwrap();
+        writer.reset_frame_position().unwrap();
+        writer.set_frame_dimension(16, 16).unwrap();
+        writer.set_frame_position(30, 20).unwrap();
+        writer.set_frame_delay(1, 2).unwrap();
+        writer.set_dispose_op(png::DisposeOp::Previous).unwrap();
+        writer.write_image_data(&crop(40, 10, 16, 16)).unwrap();
+        writer.finish().unwrap();
+    }
+    bytes
+}
+
+fn apng_alpha_of_frames(bytes: &[u8]) -> Vec<Vec<u8>> {
+    use image::AnimationDecoder;
+    let decoder = image::codecs::png::PngDecoder::new(std::io::Cursor::new(bytes)).unwrap().apng();
+    decoder.into_frames().map(|frame| frame.unwrap().into_buffer().pixels().map(|pixel| pixel.0[3]).collect()).collect()
+}
+
+#[test]
+fn test_apng() {
+    for with_separate_default_image in [false, true] {
+        let original_bytes = create_test_apng(with_separate_default_image);
+        let original_apng = DifCodeApng::from_memory(&original_bytes).unwrap();
+        assert_eq!(3 + with_separate_default_image as usize, original_apng.num_frames());
+        //all frames, without alpha
+        assert_eq!((64 * 48 * (1 + with_separate_default_image as usize) + 32 * 16 + 16 * 16) * 3, original_apng.len());
+
+        let message_bytes: Vec<u8> = (0..1024).map(|_| { rand::random::<u8>() }).collect();
+        let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_apng).expect("could not create map");
+        let encoded_apng = encode_into_container(&message_bytes, &original_apng, &allowed_changes).expect("encoding failed");
+
+        let encoded_apng_path = "test/AnimatedTestImageFrames.png";
+        encoded_apng.save(encoded_apng_path).expect("saving apng failed");
+        let encoded_bytes = std::fs::read(encoded_apng_path).unwrap();
+        let encoded_apng_reloaded = DifCodeApng::from_memory(&encoded_bytes).unwrap();
+        assert_eq!(encoded_apng, encoded_apng_reloaded);
+        assert_eq!(apng_alpha_of_frames(&original_bytes), apng_alpha_of_frames(&encoded_bytes));
+
+        let original_controls = original_apng.frame_controls();
+        let encoded_controls = encoded_apng_reloaded.frame_controls();
+        assert_eq!(with_separate_default_image, encoded_controls[0].is_none());
+        for (original_control, encoded_control) in original_controls.iter().zip(&encoded_controls) {
+            let fields = |control: &Option<png::FrameControl>| control.map(|c| (c.width, c.height, c.x_offset, c.y_offset, c.delay_num, c.delay_den, c.dispose_op, c.blend_op));
+            assert_eq!(fields(original_control), fields(encoded_control));
+        }
+        assert_eq!(message_bytes, decode_into_vec(&original_apng, &encoded_apng_reloaded).unwrap());
+    }
+}
+
+
+
+//a sine per channel, silent in the first quarter, followed by a metadata chunk of odd length
+fn create_test_wav(bits_per_sample: u16, num_channels: u16, num_frames: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for frame in 0..num_frames {
+        for channel in 0..num_channels {
+            let volume = if frame < num_frames / 4 { 0.0 } else { 0.5 };
+            let value = volume * (frame as f64 * (channel + 1) as f64 * 0.05).sin();
+            match bits_per_sample {
+                8 => data.push((128.0 + value * 127.0) as u8),
+                16 => data.extend_from_slice(&((value * 32767.0) as i16).to_le_bytes()),
+                _ => data.extend_from_slice(&((value * 8388607.0) as i32).to_le_bytes()[..3]),
+            }
+        }
+    }
+    let block_align = num_channels * bits_per_sample / 8;
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&1u16.to_le_bytes());
+    fmt.extend_from_slice(&num_channels.to_le_bytes());
+    fmt.extend_from_slice(&44100u32.to_le_bytes());
+    fmt.extend_from_slice(&(44100 * block_align as u32).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+    let mut body = b"WAVE".to_vec();
+    for (id, payload) in [(b"fmt ", &fmt[..]), (b"data", &data[..]), (b"LIST", &b"INFOISFTtest"[..])] {
+        body.extend_from_slice(id);
+        body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        body.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+fn check_wav<T: Sample>(original_bytes: &[u8], path: &str) {
+    let original_wav = DifCodeWav::<T>::from_memory(original_bytes).unwrap();
+    assert_eq!(original_bytes, &original_wav.to_bytes()[..]);
+
+    let message_bytes: Vec<u8> = (0..256).map(|_| { rand::random::<u8>() }).collect();
+    let allowed_changes = create_minimal_evenly_random_max_amplitude_allowed_changes_map(&message_bytes, &original_wav).expect("could not create map");
+    //silence is never changed (unless the amplitude radius of 256 frames reaches the sine)
+    for i in 0..(original_wav.num_frames() / 4 - 256) * original_wav.num_channels() {
+        assert_eq!(original_wav[i], allowed_changes[i]);
+    }
+    let encoded_wav = encode_into_container(&message_bytes, &original_wav, &allowed_changes).expect("encoding failed");
+    encoded_wav.save(path).expect("saving wav failed");
+    let encoded_wav_reloaded = DifCodeWav::<T>::open(path).unwrap();
+    assert_eq!(encoded_wav, encoded_wav_reloaded);
+    assert_eq!(message_bytes, decode_into_vec(&original_wav, &encoded_wav_reloaded).unwrap());
+
+    let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes, &original_wav).expect("could not create map");
+    let encoded_wav = encode_into_container(&message_bytes, &original_wav, &allowed_changes).expect("encoding failed");
+    assert_eq!(message_bytes, decode_into_vec(&original_wav, &DifCodeWav::<T>::from_memory(&encoded_wav.to_bytes()).unwrap()).unwrap());
+}
+
+#[test]
+fn test_wav() {
+    check_wav::<u8>(&create_test_wav(8, 1, 8000), "test/AudioTest8Bit.wav");
+    check_wav::<u16>(&create_test_wav(16, 2, 8000), "test/AudioTest16Bit.wav");
+    check_wav::<u32>(&create_test_wav(24, 2, 8000), "test/AudioTest24Bit.wav");
+    check_wav::<u32>(&create_test_wav(24, 5, 2000), "test/AudioTest24Bit.wav");
+
+    //the sample type has to match
+    assert!(matches!(DifCodeWav::<u16>::from_memory(&create_test_wav(8, 1, 100)), Err(DifCodeError::InvalidAudio(_))));
+    assert!(matches!(DifCodeWav::<u32>::from_memory(&create_test_wav(16, 1, 100)), Err(DifCodeError::InvalidAudio(_))));
+}
+
+
+
+#[test]
+fn test_raw_file_protected_ranges() {
+    let file = DifCodeRawFile::from_bytes(vec![0u8; 100])
+        .with_protected_range(10..20)
+        .with_protected_range(40..50)
+        .with_protected_range(15..25) //overlapping
+        .with_protected_range(25..30) //adjacent
+        .with_protected_range(0..0)
+        .with_protected_range(95..200); //cut off at the end
+    assert_eq!(&[10..30, 40..50, 95..100], file.protected_ranges());
+    assert_eq!(35, file.num_protected_bytes());
+    let protected: Vec<usize> = (0..100).filter(|i| file.is_protected(*i)).collect();
+    let expected: Vec<usize> = (10..30).chain(40..50).chain(95..100).collect();
+    assert_eq!(expected, protected);
+
+    let file = file.with_protected_range(5..97);
+    assert_eq!(&[Range { start: 5, end: 100 }], file.protected_ranges());
+
+    //unknown formats are not protected at all
+    assert!(DifCodeRawFile::from_memory(&[1, 2, 3, 4]).protected_ranges().is_empty());
+}
+
+fn check_raw_file(original_bytes: &[u8], path: &str) -> DifCodeRawFile {
+    let original_file = DifCodeRawFile::from_memory(original_bytes).with_protected_range(original_bytes.len() - 4..original_bytes.len());
+    let message_bytes: Vec<u8> = (0..64).map(|_| { rand::random::<u8>() }).collect();
+    let allowed_changes = create_minimal_evenly_random_raw_file_allowed_changes_map(&message_bytes, &original_file, 255).expect("could not create map");
+    let encoded_file = encode_into_container(&message_bytes, &original_file, &allowed_changes).expect("encoding failed");
+    for i in 0..original_file.len() {
+        if original_file.is_protected(i) {
+            assert_eq!(original_file[i], encoded_file[i], "protected byte {} changed", i);
+        }
+    }
+    assert_ne!(original_file, encoded_file);
+
+    encoded_file.save(path).expect("saving raw file failed");
+    let encoded_file_reloaded = DifCodeRawFile::open(path).unwrap();
+    assert_eq!(message_bytes, decode_into_vec(&original_file, &encoded_file_reloaded).unwrap());
+    original_file
+}
+
+#[test]
+fn test_raw_file() {
+    //13 pixels of 3 bytes are padded to 40 bytes per row
+    let mut bmp = Vec::new();
+    let image = image::RgbImage::from_fn(13, 40, |x, y| image::Rgb([(x * 19) as u8, (y * 6) as u8, 128]));
+    image::DynamicImage::ImageRgb8(image.clone()).write_to(&mut std::io::Cursor::new(&mut bmp), image::ImageOutputFormat::Bmp).unwrap();
+    let original_file = check_raw_file(&bmp, "test/RawFileTest.bmp");
+    let pixel_data_offset = u32::from_le_bytes([bmp[10], bmp[11], bmp[12], bmp[13]]) as usize;
+    assert!((0..pixel_data_offset).all(|i| original_file.is_protected(i)));
+    assert!((pixel_data_offset..pixel_data_offset + 39).all(|i| !original_file.is_protected(i)));
+    assert!(original_file.is_protected(pixel_data_offset + 39));
+    //still a valid bitmap of the same size
+    let encoded = image::open("test/RawFileTest.bmp").expect("encoded bitmap not readable").into_rgb8();
+    assert_eq!(image.dimensions(), encoded.dimensions());
+
+    let wav = create_test_wav(16, 2, 2000);
+    let original_file = check_raw_file(&wav, "test/RawFileTest.wav");
+    assert!(original_file.is_protected(0) && !original_file.is_protected(wav.len() - 40));
+    DifCodeWav::<u16>::open("test/RawFileTest.wav").expect("encoded wav not readable");
+
+    //a fully protected file has no capacity
+    let original_file = DifCodeRawFile::from_bytes(vec![7u8; 1000]).with_protected_range(0..1000);
+    assert!(matches!(create_minimal_evenly_random_raw_file_allowed_changes_map(&[1, 2, 3], &original_file, 255), Err(DifCodeError::InternalCapacityReached(0))));
+}
+
+#[test]
+fn test_streaming() {
+    let original_image = DifCodeImage::open("test/RealisticTestImage.jpg").unwrap();
+    let key = DifCodeKey::from_password("streamed");
+    for options in [EncodingOptions::default(), EncodingOptions::default().with_error_correction(16), EncodingOptions::default().with_error_correction(200).with_key(key)].iter() {
+        for message_length in [0usize, 5, 300, 20000].iter().cloned() {
+            let message_bytes: Vec<u8> = (0..message_length).map(|_| { rand::random::<u8>() }).collect();
+            let mut allowed_changes = vec![0u8; original_image.len()];
+            write_minimal_evenly_random_allowed_changes_map_with(options.get_embedded_length_in_bits(message_bytes.len()), &original_image, 255, &mut rand::thread_rng(), &mut allowed_changes).expect("could not create map");
+
+            let mut encoded_image = DifCodeImage::with_capacity(original_image.width(), original_image.height());
+            encode_from_reader_with_options(&mut &message_bytes[..], options, &original_image, &allowed_changes, &mut encoded_image).expect("encoding failed");
+            //same format as the non streaming functions
+            assert_eq!(message_bytes, decode_into_vec_with_key(options.key.as_ref(), &original_image, &encoded_image).unwrap());
+
+            let mut decoded = Vec::new();
+            let report = decode_into_writer_with_report(options.key.as_ref(), &original_image, &encoded_image, &mut decoded).unwrap();
+            assert_eq!(message_bytes, decoded);
+            assert_eq!(message_length as u32, report.header.payload_length);
+            assert_eq!(options.error_correction, report.error_correction.map(|r| r.parity_symbols));
+
+            let encoded_image = encode_into_image_with_options(&message_bytes, options, &original_image, &allowed_changes).expect("encoding failed");
+            let mut decoded = Vec::new();
+            decode_into_writer_with_report(options.key.as_ref(), &original_image, &encoded_image, &mut decoded).unwrap();
+            assert_eq!(message_bytes, decoded);
+        }
+    }
+
+    //the same capacity errors as encode
+    let message_bytes: Vec<u8> = (0..2000).map(|_| { rand::random::<u8>() }).collect();
+    let allowed_changes = create_minimal_evenly_random_allowed_changes_map(&message_bytes[..1000], &original_image).expect("could not create map");
+    let mut encoded_image = DifCodeImage::with_capacity(original_image.width(), original_image.height());
+    match encode_from_reader(&mut &message_bytes[..], &original_image, &allowed_changes, &mut encoded_image) {
+        Err(DifCodeError::InternalCapacityReached(num_bits)) => assert!((1000 * 8..2000 * 8).contains(&num_bits)),
+        other => panic!("expected capacity error, got {:?}", other)
+    }
+    let mut decoded = Vec::new();
+    assert!(decode_into_writer(&original_image, &original_image, &mut decoded).is_err());
+}
+
+#[test]
+fn test_parallel_paths_match_sequential() {
+    //large enough to be split over several threads
+    let (w, h) = (400u32, 300u32);
+    let values: Vec<u8> = (0..w * h * 3).map(|_| { rand::random::<u8>() }).collect();
+    let integral_image = IntegralRgbImage::generate(w, h, |x, y, z| values[((x * h + y) * 3 + z) as usize]);
+    let mut sequential = IntegralRgbImage::<u8>::zeros(w, h);
+    for x in 0..w {
+        for y in 0..h {
+            for z in 0..3 {
+                let value = values[((x * h + y) * 3 + z) as usize] as u64;
+                sequential[[x, y, z]] = value + sequential.get_xmm_or_0(x, y, z) + sequential.get_ymm_or_0(x, y, z) - sequential.get_xmm_and_ymm_or_0(x, y, z);
+            }
+        }
+    }
+    for x in 0..w {
+        for y in 0..h {
+            for z in 0..3 {
+                assert_eq!(sequential.get_at(x, y, z), integral_image.get_at(x, y, z));
+            }
+        }
+    }
+
+    let original: Vec<u8> = (0..300000).map(|_| { rand::random::<u8>() }).collect();
+    let encoded: Vec<u8> = original.iter().map(|&ov| if rand::random::<u8>() < 64 { ov.saturating_add(rand::random::<u8>() % 8) } else { ov }).collect();
+    let key = DifCodeKey::from_password("parallel");
+    for key in [None, Some(&key)].iter().cloned() {
+        let mut sequential = Vec::new();
+        let mut message_builder = BytesBuilder::new(&mut sequential);
+        let mut num_bits = 0;
+        for i in TraversalOrder::new(key, original.len()).iter() {
+            let difference = distance(original[i], encoded[i]);
+            let mut bits = vec![false; get_num_bits_decodable(difference) as usize];
+            dynamic_difference_to_bits_with_key(key, i, difference, &mut bits);
+            for bit in bits {
+                message_builder.push(bit);
+                num_bits += 1;
+            }
+        }
+        assert_eq!(sequential, decode_raw_into_vec_with_key(key, &original, &encoded).unwrap());
+        assert_eq!(num_bits, get_encoded_message_length_in_bits(&original, &encoded));
+    }
+}
+
+#[test]
+fn test_map_is_minimal_and_spread() {
+    let original: Vec<u8> = (0..300000).map(|_| { rand::random::<u8>() }).collect();
+    for message_length_in_bits in [1usize, 1000, 300000, 700000].iter().cloned() {
+        let mut allowed_changes_map = vec![0u8; original.len()];
+        write_minimal_evenly_random_allowed_changes_map_with(message_length_in_bits, &original, 255, &mut rand::thread_rng(), &mut allowed_changes_map).expect("could not create map");
+        assert_eq!(message_length_in_bits, get_min_encodable_message_length_in_bits(&original, &allowed_changes_map));
+
+        //the last round picks its indices at random, rather than the first ones
+        if message_length_in_bits >= 1000 {
+            let num_changed_in_second_half = (original.len() / 2..original.len()).filter(|&i| original[i] != allowed_changes_map[i]).count();
+            assert!(num_changed_in_second_half > message_length_in_bits.min(original.len()) / 4);
+        }
+    }
+
+    let mut allowed_changes_map = vec![0u8; original.len()];
+    match write_minimal_evenly_random_allowed_changes_map_with(original.len() * 3, &original, 3, &mut rand::thread_rng(), &mut allowed_changes_map) {
+        Err(DifCodeError::InternalCapacityReached(num_bits)) => assert_eq!(get_min_encodable_message_length_in_bits(&original, &allowed_changes_map), num_bits),
+        other => panic!("expected capacity error, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_seeded_map_and_encoding_are_reproducible() {
+    let original: Vec<u8> = (0..100000).map(|_| { rand::random::<u8>() }).collect();
+    let message_bytes: Vec<u8> = (0..1000).map(|_| { rand::random::<u8>() }).collect();
+    let create_map = |rng: &mut StdRng| {
+        let mut allowed_changes_map = vec![0u8; original.len()];
+        write_minimal_evenly_random_allowed_changes_map_with(EncodingOptions::default().get_embedded_length_in_bits(message_bytes.len()), &original, 255, rng, &mut allowed_changes_map).expect("could not create map");
+        allowed_changes_map
+    };
+    assert_eq!(create_map(&mut StdRng::seed_from_u64(42)), create_map(&mut StdRng::seed_from_u64(42)));
+    assert_ne!(create_map(&mut StdRng::seed_from_u64(42)), create_map(&mut StdRng::seed_from_u64(43)));
+
+    let key = DifCodeKey::from_password("seed");
+    let map = create_map(&mut key.map_rng());
+    assert_eq!(map, create_map(&mut key.map_rng()));
+    let encoded = encode_into_vec(&message_bytes, &original, &map).expect("encoding failed");
+    assert_eq!(encoded, encode_into_vec(&message_bytes, &original, &map).expect("encoding failed"));
+    assert_eq!(message_bytes, decode_into_vec(&original, &encoded).expect("decoding failed"));
+
+    let legacy_encode = |rng: &mut StdRng| {
+        let selected_indices = randomly_select_indices(get_length_in_bits(&message_bytes), original.len(), rng);
+        let mut encoded = vec![0u8; original.len()];
+        encode_at_indices(&message_bytes, &original, &selected_indices, rng, &mut encoded).expect("encoding failed");
+        encoded
+    };
+    let encoded = legacy_encode(&mut StdRng::seed_from_u64(7));
+    assert_eq!(encoded, legacy_encode(&mut StdRng::seed_from_u64(7)));
+    assert_ne!(encoded, legacy_encode(&mut StdRng::seed_from_u64(8)));
+    assert_eq!(message_bytes, decode_into_vec_at_indices(&original, &encoded).expect("decoding failed"));
+}
+
+#[test]
+fn test_capacity_report() {
+    let original: Vec<u8> = (0..100000).map(|_| { rand::random::<u8>() }).collect();
+    //content aware maps allow differences between the bit count boundaries, where the number of bits that fit depends on the message
+    let allowed_changes_map: Vec<u8> = original.iter().map(|&ov| ov.saturating_add(rand::random::<u8>() % 40)).collect();
+
+    //the encoder fits about the expected number of random bits
+    let expected_length_in_bits = get_expected_encodable_message_length_in_bits(&original, &allowed_changes_map);
+    let message_bytes: Vec<u8> = (0..get_max_encodable_message_length_in_bits(&original, &allowed_changes_map) / 8 + 1).map(|_| { rand::random::<u8>() }).collect();
+    let mut encoded = vec![0u8; original.len()];
+    match encode_raw(&message_bytes, &original, &allowed_changes_map, &mut encoded) {
+        Err(DifCodeError::InternalCapacityReached(num_bits)) => assert!((num_bits as i64 - expected_length_in_bits as i64).abs() < expected_length_in_bits as i64 / 100, "{} vs {}", num_bits, expected_length_in_bits),
+        other => panic!("expected capacity error, got {:?}", other)
+    }
+
+    for options in [EncodingOptions::default(), EncodingOptions::default().with_error_correction(32)].iter() {
+        let report = get_capacity_report(options, &original, &allowed_changes_map);
+        assert!(report.guaranteed_bytes < report.expected_bytes && report.expected_bytes < report.maximum_bytes);
+        assert_eq!(report.guaranteed_bytes, options.get_max_message_length_in_bits(get_min_encodable_message_length_in_bits(&original, &allowed_changes_map)) / 8);
+
+        let message_bytes: Vec<u8> = (0..report.guaranteed_bytes).map(|_| { rand::random::<u8>() }).collect();
+        let encoded = encode_into_vec_with_options(&message_bytes, options, &original, &allowed_changes_map).expect("guaranteed capacity did not fit");
+        assert_eq!(message_bytes, decode_into_vec(&original, &encoded).expect("decoding failed"));
+        assert_eq!(report.guaranteed_bytes - 16, report.without_overhead(16).guaranteed_bytes);
+    }
+}
+
+#[test]
+fn test_integral_image_areas() {
+    let (w, h) = (37u32, 23u32);
+    let values: Vec<u8> = (0..w * h * 3).map(|_| { rand::random::<u8>() }).collect();
+    let integral_image = IntegralRgbImage::generate(w, h, |x, y, z| values[((x * h + y) * 3 + z) as usize]);
+    let sum_of = |x_range: std::ops::RangeInclusive<u32>, y_range: std::ops::RangeInclusive<u32>, z: u32| -> u64 {
+        x_range.flat_map(|x| y_range.clone().map(move |y| (x, y))).map(|(x, y)| values[((x * h + y) * 3 + z) as usize] as u64).sum()
+    };
+    //both corners are included, also at the edges of the image
+    for &(x1, y1, x2, y2) in [(0u32, 0u32, 0u32, 0u32), (0, 0, w - 1, h - 1), (3, 4, 3, 20), (5, 0, 36, 9), (36, 22, 36, 22)].iter() {
+        for z in 0..3 {
+            assert_eq!(sum_of(x1..=x2, y1..=y2, z), integral_image.calculate_area_sum(x1, y1, x2, y2, z));
+        }
+    }
+    for &(x, y, radius) in [(0u32, 0u32, 3u32), (36, 22, 3), (18, 11, 5), (2, 20, 0), (18, 11, 100)].iter() {
+        let (x_range, y_range) = (x.saturating_sub(radius)..=(x + radius).min(w - 1), y.saturating_sub(radius)..=(y + radius).min(h - 1));
+        let num_pixels = (x_range.clone().count() * y_range.clone().count()) as u64;
+        assert_eq!((sum_of(x_range, y_range, 1) / num_pixels) as u8, integral_image.average_in_radius(x, y, 1, radius));
+    }
+
+    //more white pixels than fit into u32 sums (of the squares)
+    let white_image = IntegralVarianceImage::generate(1000, 1000, |_, _, _| 255u8);
+    assert_eq!(255, white_image.average_in_radius(500, 500, 0, 1000));
+    assert_eq!(0.0, white_image.variance_in_radius(500, 500, 2, 1000));
+    let (w, h) = (4200u32, 4100u32);
+    let white_image = IntegralRgbImage::generate(w, h, |_, _, _| 255u8);
+    assert_eq!(255 * w as u64 * h as u64, white_image.calculate_area_sum(0, 0, w - 1, h - 1, 1));
+}
+
+#[test]
+fn test_local_variance_map() {
+    let (w, h) = (64u32, 48u32);
+    let values: Vec<u8> = (0..w * h * 3).map(|_| { rand::random::<u8>() }).collect();
+    let variance_image = IntegralVarianceImage::generate(w, h, |x, y, z| values[((x * h + y) * 3 + z) as usize]);
+    for &(x, y, z) in [(0u32, 0u32, 0u32), (10, 20, 1), (63, 47, 2), (30, 5, 0)].iter() {
+        let area: Vec<f64> = (x.saturating_sub(4)..=(x + 4).min(w - 1)).flat_map(|ax| (y.saturating_sub(4)..=(y + 4).min(h - 1)).map(move |ay| (ax, ay)))
+            .map(|(ax, ay)| values[((ax * h + ay) * 3 + z) as usize] as f64).collect();
+        let mean = area.iter().sum::<f64>() / area.len() as f64;
+        let standard_deviation = (area.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / area.len() as f64).sqrt();
+        assert!((variance_image.standard_deviation_in_radius(x, y, z, 4) as f64 - standard_deviation).abs() <= 1.0);
+    }
+
+    //the left half is flat, the right half noise
+    let image = DifCodeImage::from(image::RgbImage::from_fn(w, h, |x, _| {
+        if x < w / 2 { image::Rgb([90, 140, 200]) } else { image::Rgb([rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>()]) }
+    }));
+    let message_bytes: Vec<u8> = (0..300).map(|_| { rand::random::<u8>() }).collect();
+    let allowed_changes_map = create_minimal_evenly_random_max_local_variance_allowed_changes_map(&message_bytes, &image).expect("could not create map");
+    for i in 0..image.len() {
+        let (x, _, _) = image.index_to_xyz(i);
+        if x < w / 2 - 4 {
+            assert_eq!(image[i], allowed_changes_map[i]);
+        }
+    }
+    let encoded_image = encode_into_image(&message_bytes, &image, &allowed_changes_map).expect("encoding failed");
+    assert_eq!(message_bytes, decode_into_vec(&image, &encoded_image).expect("decoding failed"));
+
+    let luma_image = DifCodeLumaImage::from(image::GrayImage::from_fn(w, h, |x, _| image::Luma([if x < w / 2 { 30 } else { rand::random::<u8>() }])));
+    let allowed_changes_map = create_minimal_evenly_random_max_local_variance_luma_allowed_changes_map(&message_bytes[..100], &luma_image).expect("could not create map");
+    assert!((0..luma_image.len()).filter(|&i| luma_image.index_to_xy(i).0 < w / 2 - 4).all(|i| luma_image[i] == allowed_changes_map[i]));
+}
+
+#[test]
+fn test_stratified_map() {
+    let (w, h) = (96u32, 96u32);
+    let image = DifCodeImage::from(image::RgbImage::from_fn(w, h, |_, _| image::Rgb([rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>()])));
+    let message_length_in_bits = 1000;
+    let mut allowed_changes_map = vec![0u8; image.len()];
+    write_minimal_stratified_allowed_changes_map_with(message_length_in_bits, &image, w as usize, 255, &mut StdRng::seed_from_u64(3), &mut allowed_changes_map).expect("could not create map");
+    //a single round (every value can take one bit), so exactly as many values as bits are changed
+    assert_eq!(message_length_in_bits, (0..image.len()).filter(|&i| image[i] != allowed_changes_map[i]).count());
+
+    //every 16x16 block (of all channels) gets close to its share of the changes
+    let expected_per_block = message_length_in_bits as f64 * (16 * 16 * 3) as f64 / image.len() as f64;
+    for block_x in 0..w / 16 {
+        for block_y in 0..h / 16 {
+            let num_changes = (0..image.len()).filter(|&i| {
+                let (x, y, _) = image.index_to_xyz(i);
+                x / 16 == block_x && y / 16 == block_y && image[i] != allowed_changes_map[i]
+            }).count();
+            assert!((num_changes as f64 - expected_per_block).abs() <= 8.0, "{} changes in block, expected {}", num_changes, expected_per_block);
+        }
+    }
+
+    let message_bytes: Vec<u8> = (0..2000).map(|_| { rand::random::<u8>() }).collect();
+    let allowed_changes_map = create_minimal_stratified_allowed_changes_map_for_image(&message_bytes, &image).expect("could not create map");
+    let encoded_image = encode_into_image(&message_bytes, &image, &allowed_changes_map).expect("encoding failed");
+    assert_eq!(message_bytes, decode_into_vec(&image, &encoded_image).expect("decoding failed"));
+
+    let rgba_image = DifCodeRgbaImage::from(image::RgbaImage::from_fn(w, h, |_, _| image::Rgba([rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>(), 255])));
+    let allowed_changes_map = create_minimal_stratified_rgba_allowed_changes_map(&message_bytes, &rgba_image, true).expect("could not create map");
+    assert!((0..rgba_image.len()).filter(|&i| rgba_image.index_to_xyz(i).2 == DifCodeRgbaImage::ALPHA_CHANNEL).all(|i| rgba_image[i] == allowed_changes_map[i]));
+    let encoded_image = encode_into_rgba_image(&message_bytes, &rgba_image, &allowed_changes_map).expect("encoding failed");
+    assert_eq!(message_bytes, decode_into_vec(&rgba_image, &encoded_image).expect("decoding failed"));
+}
+
+#[test]
+fn test_channel_weighting() {
+    assert_eq!(15u8, ChannelWeighting::Uniform.weigh(1, 15u8));
+    assert_eq!([17u8, 9, 44, 15], [0, 1, 2, 3].map(|channel| ChannelWeighting::Perceptual.weigh(channel, 15u8)));
+    assert_eq!(255u8, ChannelWeighting::Perceptual.weigh(2, 200u8));
+    assert_eq!([0u16, 30, 15], [0, 1, 2].map(|channel| ChannelWeighting::Custom([0.0, 2.0, 1.0]).weigh(channel, 15u16)));
+
+    let (w, h) = (40u32, 30u32);
+    let image = DifCodeImage::from(image::RgbImage::from_fn(w, h, |_, _| image::Rgb([rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>()])));
+    //attempt more than fits, so every channel takes as much as its weighted max difference allows
+    let mut allowed_changes_map = vec![0u8; image.len()];
+    let result = write_minimal_evenly_random_image_allowed_changes_map(image.len() * 8, &image, 15, ChannelWeighting::Perceptual, &mut StdRng::seed_from_u64(5), &mut allowed_changes_map);
+    assert!(matches!(result, Err(DifCodeError::InternalCapacityReached(_))));
+    let mut max_change_per_channel = [0u8; 3];
+    for i in 0..image.len() {
+        let z = image.index_to_xyz(i).2 as usize;
+        max_change_per_channel[z] = max_change_per_channel[z].max(distance(image[i], allowed_changes_map[i]));
+    }
+    assert!(max_change_per_channel[0] <= 17 && max_change_per_channel[1] <= 9 && max_change_per_channel[2] <= 44);
+    assert!(max_change_per_channel[1] < max_change_per_channel[0] && max_change_per_channel[0] < max_change_per_channel[2]);
+
+    let message_bytes: Vec<u8> = (0..500).map(|_| { rand::random::<u8>() }).collect();
+    let mut allowed_changes_map = vec![0u8; image.len()];
+    write_minimal_evenly_random_image_allowed_changes_map(EncodingOptions::default().get_embedded_length_in_bits(message_bytes.len()), &image, 15, ChannelWeighting::Perceptual, &mut StdRng::seed_from_u64(5), &mut allowed_changes_map).expect("could not create map");
+    let encoded_image = encode_into_image(&message_bytes, &image, &allowed_changes_map).expect("encoding failed");
+    assert_eq!(message_bytes, decode_into_vec(&image, &encoded_image).expect("decoding failed"));
+}
+
+#[test]
+fn test_delta_e_map() {
+    //pairs from the CIEDE2000 test data of Sharma, Wu, Dalal
+    for &(first, second, expected) in [([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425), ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+                                       ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492), ([90.8027, -2.0831, 1.441], [91.1528, -1.6435, 0.0447], 1.4441)].iter() {
+        assert!((DeltaEFormula::Ciede2000.difference(first, second) - expected).abs() < 0.0001);
+    }
+    assert!((DeltaEFormula::Cie76.difference([50.0, 0.0, 0.0], [50.0, 3.0, 4.0]) - 5.0).abs() < 1e-9);
+    let converter = LabConverter::new();
+    let white = converter.to_lab([255, 255, 255]);
+    assert!((white[0] - 100.0).abs() < 0.01 && white[1].abs() < 0.01 && white[2].abs() < 0.01);
+
+    let image = DifCodeImage::open("test/RealisticTestImage.jpg").expect("failed to load test image");
+    let message_bytes: Vec<u8> = (0..2000).map(|_| { rand::random::<u8>() }).collect();
+    for &formula in [DeltaEFormula::Cie76, DeltaEFormula::Ciede2000].iter() {
+        let allowed_changes_map = create_minimal_evenly_random_max_delta_e_allowed_changes_map(&message_bytes, &image, formula, DEFAULT_MAX_DELTA_E).expect("could not create map");
+        let encoded_image = encode_into_image(&message_bytes, &image, &allowed_changes_map).expect("encoding failed");
+        for (x, y, pixel) in image.raw().enumerate_pixels() {
+            let difference = formula.difference(converter.to_lab(pixel.0), converter.to_lab(encoded_image.get_pixel(x, y).0));
+            assert!(difference <= DEFAULT_MAX_DELTA_E, "{:?} at {}, {}", difference, x, y);
+        }
+        assert_eq!(message_bytes, decode_into_vec(&image, &encoded_image).expect("decoding failed"));
+    }
+
+    let luma_image = DifCodeLumaImage::from(image::GrayImage::from_fn(60, 40, |x, y| image::Luma([((x * 4 + y) % 256) as u8])));
+    let allowed_changes_map = create_minimal_evenly_random_max_delta_e_luma_allowed_changes_map(&message_bytes[..100], &luma_image, DeltaEFormula::Ciede2000, 1.0).expect("could not create map");
+    for i in 0..luma_image.len() {
+        let changed = allowed_changes_map[i];
+        assert!(DeltaEFormula::Ciede2000.difference(converter.to_lab([luma_image[i]; 3]), converter.to_lab([changed; 3])) <= 1.0);
+    }
+}
+
+#[test]
+fn test_image16_maps() {
+    let (w, h) = (64u32, 48u32);
+    //the left half is flat, the right half noise
+    let image = DifCodeImage16::from(image::ImageBuffer::from_fn(w, h, |x, y| {
+        if x < w / 2 { image::Rgb([23130u16, 36000, 51400]) } else { image::Rgb([rand::random::<u16>(), rand::random::<u16>(), (x * 1000 + y) as u16]) }
+    }));
+    let luma_image = DifCodeLumaImage16::from(image::ImageBuffer::from_fn(w, h, |x, y| image::Luma([image.get_rgorb(x, y, 0)])));
+    let message_bytes: Vec<u8> = (0..600).map(|_| { rand::random::<u8>() }).collect();
+
+    let integral_image = image.generate_integral_image_for_rgb();
+    //clipped at the top edge
+    let average = (w / 2 - 1..=w / 2 + 5).flat_map(|x| (0..=5).map(move |y| (x, y))).map(|(x, y)| image.get_rgorb(x, y, 1) as u64).sum::<u64>() / 42;
+    assert_eq!(average as u16, integral_image.average_in_radius(w / 2 + 2, 2, 1, 3));
+    let variance_image = image.generate_integral_variance_image_for_rgb();
+    assert_eq!(0, variance_image.standard_deviation_in_radius(5, 5, 0, 3));
+    assert!(variance_image.standard_deviation_in_radius(w - 5, 5, 0, 3) > 10000);
+
+    let allowed_changes_map = create_minimal_evenly_random_max_area_average_image16_allowed_changes_map(&message_bytes, &image).expect("could not create map");
+    for i in 0..image.len() {
+        let (x, y, z) = image.index_to_xyz(i);
+        assert!(image[i].distance(allowed_changes_map[i]) <= image[i].distance(integral_image.average_in_radius(x, y, z, DEFAULT_AREA_AVERAGE_RADIUS)));
+    }
+    let encoded_image = encode_into_image16(&message_bytes, &image, &allowed_changes_map).expect("encoding failed");
+    assert_eq!(message_bytes, decode_into_vec(&image, &encoded_image).expect("decoding failed"));
+
+    for allowed_changes_map in [create_minimal_evenly_random_max_local_variance_image16_allowed_changes_map(&message_bytes, &image).expect("could not create map"),
+                                create_minimal_evenly_random_max_delta_e_image16_allowed_changes_map(&message_bytes, &image, DeltaEFormula::Ciede2000, DEFAULT_MAX_DELTA_E).expect("could not create map")] {
+        let encoded_image = encode_into_image16(&message_bytes, &image, &allowed_changes_map).expect("encoding failed");
+        assert_eq!(message_bytes, decode_into_vec(&image, &encoded_image).expect("decoding failed"));
+    }
+    let allowed_changes_map = create_minimal_evenly_random_max_local_variance_image16_allowed_changes_map(&message_bytes, &image).expect("could not create map");
+    for i in 0..image.len() {
+        if image.index_to_xyz(i).0 < w / 2 - DEFAULT_LOCAL_VARIANCE_RADIUS {
+            assert_eq!(image[i], allowed_changes_map[i]);
+        }
+    }
+
+    //every color the encoder can produce within the map stays within the color difference
+    let converter = LabConverter::new();
+    let allowed_changes_map = create_minimal_evenly_random_max_delta_e_image16_allowed_changes_map(&message_bytes, &image, DeltaEFormula::Ciede2000, DEFAULT_MAX_DELTA_E).expect("could not create map");
+    let mut changed = image.raw().clone();
+    for (i, &allowed_change) in allowed_changes_map.iter().enumerate() {
+        let (x, y, z) = image.index_to_xyz(i);
+        changed.get_pixel_mut(x, y).0[z as usize] = allowed_change;
+    }
+    for (x, y, pixel) in image.raw().enumerate_pixels() {
+        assert!(DeltaEFormula::Ciede2000.difference(converter.to_lab16(pixel.0), converter.to_lab16(changed.get_pixel(x, y).0)) <= DEFAULT_MAX_DELTA_E);
+    }
+    //8 bit colors are every 257th 16 bit color, the 16 bit colors in between are distinct
+    assert!(DeltaEFormula::Cie76.difference(converter.to_lab([90, 140, 200]), converter.to_lab16([90 * 257, 140 * 257, 200 * 257])) < 1e-9);
+    assert!(converter.to_lab16([90 * 257 + 1, 140 * 257, 200 * 257]) != converter.to_lab16([90 * 257, 140 * 257, 200 * 257]));
+
+    for allowed_changes_map in [create_minimal_evenly_random_max_area_average_luma16_allowed_changes_map(&message_bytes[..200], &luma_image).expect("could not create map"),
+                                create_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map(&message_bytes[..200], &luma_image).expect("could not create map"),
+                                create_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map(&message_bytes[..200], &luma_image, DeltaEFormula::Cie76, DEFAULT_MAX_DELTA_E).expect("could not create map")] {
+        let encoded_image = encode_into_luma_image16(&message_bytes[..200], &luma_image, &allowed_changes_map).expect("encoding failed");
+        assert_eq!(&message_bytes[..200], &decode_into_vec(&luma_image, &encoded_image).expect("decoding failed")[..]);
+    }
+}
+
+#[test]
+fn test_quality_metrics() {
+    let (w, h) = (23u32, 17u32);
+    let image = DifCodeImage::from(image::RgbImage::from_fn(w, h, |_, _| image::Rgb([rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>()])));
+    let identical = get_quality_metrics(&image, &image).expect("could not measure");
+    assert_eq!((f64::INFINITY, 1.0, 0.0, 0), (identical.psnr, identical.ssim, identical.mse, identical.max_absolute_change));
+
+    let mut encoded = DifCodeImage::from(image.raw().clone());
+    encoded[100] = if image[100] < 128 { image[100] + 10 } else { image[100] - 10 };
+    encoded[500] = if image[500] < 128 { image[500] + 3 } else { image[500] - 3 };
+    let metrics = get_quality_metrics(&image, &encoded).expect("could not measure");
+    let mse = 109.0 / image.len() as f64;
+    assert_eq!(10, metrics.max_absolute_change);
+    assert!((metrics.mse - mse).abs() < 1e-12);
+    assert!((metrics.psnr - 10.0 * (255.0 * 255.0 / mse).log10()).abs() < 1e-9);
+
+    //the sliding windows against a direct computation of every 7x7 window
+    for i in 0..image.len() {
+        encoded[i] = image[i].saturating_add(rand::random::<u8>() % 16);
+    }
+    let mut ssim_sum = 0.0;
+    for z in 0..3 {
+        for top in 0..=h - 7 {
+            for left in 0..=w - 7 {
+                let window: Vec<(f64, f64)> = (left..left + 7).flat_map(|x| (top..top + 7).map(move |y| (x, y)))
+                    .map(|(x, y)| (image.get_rgorb(x, y, z) as f64, encoded.get_rgorb(x, y, z) as f64)).collect();
+                let (mean_o, mean_e) = (window.iter().map(|v| v.0).sum::<f64>() / 49.0, window.iter().map(|v| v.1).sum::<f64>() / 49.0);
+                let variance_o = window.iter().map(|v| (v.0 - mean_o) * (v.0 - mean_o)).sum::<f64>() / 49.0;
+                let variance_e = window.iter().map(|v| (v.1 - mean_e) * (v.1 - mean_e)).sum::<f64>() / 49.0;
+                let covariance = window.iter().map(|v| (v.0 - mean_o) * (v.1 - mean_e)).sum::<f64>() / 49.0;
+                let (c1, c2) = (2.55 * 2.55, 7.65 * 7.65);
+                ssim_sum += ((2.0 * mean_o * mean_e + c1) * (2.0 * covariance + c2)) / ((mean_o * mean_o + mean_e * mean_e + c1) * (variance_o + variance_e + c2));
+            }
+        }
+    }
+    let metrics = get_quality_metrics(&image, &encoded).expect("could not measure");
+    assert!((metrics.ssim - ssim_sum / (3 * (w - 6) * (h - 6)) as f64).abs() < 1e-9);
+    assert!(metrics.ssim < 1.0 && metrics.psnr < identical.psnr);
+
+    //images smaller than the window and other plane layouts
+    let luma_image = DifCodeLumaImage::from(image::GrayImage::from_fn(4, 3, |x, y| image::Luma([(x * 40 + y) as u8])));
+    let luma_encoded = DifCodeLumaImage::from(image::GrayImage::from_fn(4, 3, |x, y| image::Luma([(x * 40 + y + 1) as u8])));
+    let metrics = get_quality_metrics_of_planes(&luma_image, &luma_encoded, 4, 3).expect("could not measure");
+    assert_eq!((1.0, 1), (metrics.mse, metrics.max_absolute_change));
+    assert!(metrics.ssim > 0.99 && metrics.ssim < 1.0);
+
+    let smaller = DifCodeImage::from(image::RgbImage::new(w, h - 1));
+    assert!(matches!(get_quality_metrics(&image, &smaller), Err(DifCodeError::InternalMismatchedContainerSizes)));
+}
+
+#[test]
+fn test_steganalysis() {
+    let original = DifCodeImage::open("test/RealisticTestImage.jpg").expect("could not open image");
+    let clean = get_steganalysis_scores(&original).expect("could not analyze");
+    assert!(clean.chi_square < 0.01 && clean.rs < 0.05 && clean.spa < 0.05);
+    assert!(!clean.is_flagged());
+
+    //lsb replacement of a known fraction of the values is detected at about that fraction
+    let mut rng = StdRng::seed_from_u64(25);
+    for &fraction in [0.25f64, 0.5, 1.0].iter() {
+        let mut replaced = DifCodeImage::from(original.raw().clone());
+        for i in 0..replaced.len() {
+            if rand::Rng::gen_bool(&mut rng, fraction) {
+                replaced[i] = (replaced[i] & !1) | rand::Rng::gen_range(&mut rng, 0..2u8);
+            }
+        }
+        let report = get_steganalysis_report(&original, &replaced).expect("could not analyze");
+        assert_eq!(clean, report.original);
+        assert!((report.encoded.rs - fraction).abs() < 0.1);
+        assert!((report.encoded.spa - fraction).abs() < 0.1);
+        assert!((report.increase().rs - (report.encoded.rs - clean.rs)).abs() < 1e-12);
+        assert!(report.encoded.is_flagged());
+        if fraction == 1.0 {
+            assert!(report.encoded.chi_square > 0.99);
+        }
+    }
+
+    //pairs (0, 1) and (4, 5) are even, (2, 3) contributes (30 - 20)^2 / 20 = 5 with 2 degrees of freedom, i.e. e^(-5/2)
+    let values: Vec<u8> = [(0u8, 10usize), (1, 10), (2, 30), (3, 10), (4, 20), (5, 20)].iter().flat_map(|&(value, count)| vec![value; count]).collect();
+    assert!((chi_square_attack(&values) - (-2.5f64).exp()).abs() < 1e-9);
+    let values: Vec<u8> = (0..1000).map(|i| (i % 128) as u8 * 2).collect();
+    assert!(chi_square_attack(&values) < 1e-9);
+
+    //too small to be analyzed
+    assert_eq!(0.0, rs_analysis(&vec![1u8, 2, 3], 3));
+    assert_eq!(0.0, sample_pair_analysis(&vec![1u8, 5, 9, 13], 1));
+}
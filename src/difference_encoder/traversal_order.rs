@@ -0,0 +1,86 @@
+use rand::Rng;
+
+use crate::difference_encoder::key::DifCodeKey;
+
+const NUM_ROUNDS: usize = 4;
+
+/// Bijective mapping of the positions 0..len onto the container indices 0..len, i.e. the order in which encode and decode visit the container.
+/// Without a key the order is the identity, with a key it is a pseudo random permutation derived from the key.
+/// The permutation is computed per position (a feistel network on the next power of 4, with cycle walking back into 0..len),
+///    so no list of all indices has to be held in memory.
+#[derive(Debug, Clone)]
+pub struct TraversalOrder {
+    len: usize,
+    half_bits: u32,
+    round_keys: Option<[u64; NUM_ROUNDS]>,
+}
+impl TraversalOrder {
+    pub fn identity(len: usize) -> TraversalOrder {
+        TraversalOrder { len, half_bits: 0, round_keys: None }
+    }
+    pub fn new(key: Option<&DifCodeKey>, len: usize) -> TraversalOrder {
+        match key {
+            None => TraversalOrder::identity(len),
+            Some(key) => {
+                let mut half_bits = 1;
+                while (1u128 << (2 * half_bits)) < len as u128 {
+                    half_bits += 1;
+                }
+                let mut rng = key.traversal_rng();
+                let mut round_keys = [0u64; NUM_ROUNDS];
+                for round_key in round_keys.iter_mut() {
+                    *round_key = rng.gen();
+                }
+                TraversalOrder { len, half_bits, round_keys: Some(round_keys) }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The container index visited at the given position.
+    /// Panics if position >= len.
+    pub fn index_at(&self, position: usize) -> usize {
+        assert!(position < self.len, "position out of bounds");
+        match &self.round_keys {
+            None => position,
+            Some(round_keys) => {
+                //the feistel network permutes the larger domain, so repeated application eventually lands back in 0..len
+                let mut index = self.permute(round_keys, position as u64);
+                while index >= self.len as u64 {
+                    index = self.permute(round_keys, index);
+                }
+                index as usize
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=usize> + '_ {
+        (0..self.len).map(move |position| self.index_at(position))
+    }
+
+    fn permute(&self, round_keys: &[u64; NUM_ROUNDS], value: u64) -> u64 {
+        let mask = (1u64 << self.half_bits) - 1;
+        let mut left = value >> self.half_bits;
+        let mut right = value & mask;
+        for round_key in round_keys.iter() {
+            let new_right = left ^ (mix(right ^ round_key) & mask);
+            left = right;
+            right = new_right;
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+//splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,199 @@
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+use std::thread;
+
+use ndarray::{Array3, Axis, s};
+
+use crate::parallel;
+use crate::util::Sample;
+
+//Integral images (summed area tables) of the three channels of an image: every entry is the sum of all values above and left of it, itself included.
+//So the sum (and with it the average or variance) of any rectangular area takes four lookups, independent of its size.
+//The sums are u64, so they cannot overflow for any 8 bit image that fits into memory (not even for squared values)
+//    and for 16 bit images only with more than 2^32 pixels (for squared values).
+
+pub struct IntegralRgbImage<T: Sample = u8>(Array3<u64>, PhantomData<T>);
+impl<T: Sample> IntegralRgbImage<T> {
+    pub fn zeros(w: u32, h: u32) -> IntegralRgbImage<T> {
+        let x = Array3::<u64>::zeros([w as usize, h as usize, 3]);
+        IntegralRgbImage(x, PhantomData)
+    }
+    /// The columns are summed up in parallel and then the rows.
+    pub fn generate<F>(w: u32, h: u32, get_rgorb: F) -> IntegralRgbImage<T> where F: Fn(u32, u32, u32) -> T + Sync {
+        IntegralRgbImage::generate_from_values(w, h, |x, y, z| get_rgorb(x, y, z).to_u32() as u64)
+    }
+    /// Integral image of the squared values (see IntegralVarianceImage).
+    pub fn generate_squared<F>(w: u32, h: u32, get_rgorb: F) -> IntegralRgbImage<T> where F: Fn(u32, u32, u32) -> T + Sync {
+        IntegralRgbImage::generate_from_values(w, h, |x, y, z| get_rgorb(x, y, z).to_u32() as u64 * get_rgorb(x, y, z).to_u32() as u64)
+    }
+    fn generate_from_values<F>(w: u32, h: u32, get_value: F) -> IntegralRgbImage<T> where F: Fn(u32, u32, u32) -> u64 + Sync {
+        let mut integral_image = IntegralRgbImage::zeros(w, h);
+        if w == 0 || h == 0 {
+            return integral_image
+        }
+        let get_value = &get_value;
+        let num_threads = parallel::num_threads_for(w as usize * h as usize);
+        let columns_per_thread = (w as usize).div_ceil(num_threads);
+        thread::scope(|scope| {
+            for (chunk_index, mut columns) in integral_image.0.axis_chunks_iter_mut(Axis(0), columns_per_thread).enumerate() {
+                scope.spawn(move || {
+                    for (column_index, mut column) in columns.outer_iter_mut().enumerate() {
+                        let x = (chunk_index * columns_per_thread + column_index) as u32;
+                        for z in 0..3 {
+                            let mut sum = 0u64;
+                            for y in 0..h {
+                                sum += get_value(x, y, z);
+                                column[[y as usize, z as usize]] = sum;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        let rows_per_thread = (h as usize).div_ceil(num_threads);
+        thread::scope(|scope| {
+            for mut rows in integral_image.0.axis_chunks_iter_mut(Axis(1), rows_per_thread) {
+                scope.spawn(move || {
+                    for x in 1..w as usize {
+                        let (previous, mut current) = rows.multi_slice_mut((s![x - 1, .., ..], s![x, .., ..]));
+                        current.zip_mut_with(&previous, |sum, previous_sum| *sum += *previous_sum);
+                    }
+                });
+            }
+        });
+        integral_image
+    }
+
+    /// The average of the square of side length 2*radius+1 around x, y (clipped at the edges of the image, so it never is empty).
+    pub fn average_in_radius(&self, x: u32, y: u32, z: u32, radius: u32) -> T {
+        let (x_min, y_min, x_max, y_max) = self.area_in_radius(x, y, radius);
+        T::from_u32((self.calculate_area_sum(x_min, y_min, x_max, y_max, z) / num_pixels_in_area(x_min, y_min, x_max, y_max)) as u32)
+    }
+
+    //the inclusive bounds of the square around x, y
+    fn area_in_radius(&self, x: u32, y: u32, radius: u32) -> (u32, u32, u32, u32) {
+        let x_min = x.saturating_sub(radius);
+        let y_min = y.saturating_sub(radius);
+        let x_max = x.saturating_add(radius).min(self.width() - 1);
+        let y_max = y.saturating_add(radius).min(self.height() - 1);
+        (x_min, y_min, x_max, y_max)
+    }
+
+    pub fn get_at_p(&self, p: (u32, u32, u32)) -> u64 {
+        self.get_at(p.0, p.1, p.2)
+    }
+    pub fn get_at(&self, x: u32, y: u32, z: u32) -> u64 {
+        self[[x, y, z]]
+    }
+    pub fn width(&self) -> u32 {
+        self.0.shape()[0] as u32
+    }
+    pub fn height(&self) -> u32 {
+        self.0.shape()[1] as u32
+    }
+
+    pub fn calculate_area_sum_at_ps(&self, p1: (u32, u32, u32), p2: (u32, u32, u32)) -> u64 {
+        if p1.2 != p2.2 {
+            panic!("z is unequal in the points");
+        }
+        self.calculate_area_sum(p1.0, p1.1, p2.0, p2.1, p1.2)
+    }
+    /// The sum of the values in the rectangle from p1 to p2, both corners included.
+    pub fn calculate_area_sum(&self, p1_x: u32, p1_y: u32, p2_x: u32, p2_y: u32, z: u32) -> u64 {
+        if p1_x > p2_x || p1_y > p2_y {
+            panic!("p1 > p2");
+        }
+
+        //the sums left of and above the rectangle are subtracted, the sum of the area left of and above both was subtracted twice
+        self.get_at(p2_x, p2_y, z) + self.get_xmm_and_ymm_or_0(p1_x, p1_y, z)
+            - self.get_xmm_or_0(p1_x, p2_y, z) - self.get_ymm_or_0(p2_x, p1_y, z)
+    }
+
+
+
+    pub fn get_ymm_or_0(&self, x: u32, y: u32, z: u32) -> u64 {
+        if y > 0 {
+            self[[x, y - 1, z]]
+        } else {
+            0
+        }
+    }
+    pub fn get_xmm_or_0(&self, x: u32, y: u32, z: u32) -> u64 {
+        if x > 0 {
+            self[[x - 1, y, z]]
+        } else {
+            0
+        }
+    }
+    pub fn get_xmm_and_ymm_or_0(&self, x: u32, y: u32, z: u32) -> u64 {
+        if x > 0 && y > 0 {
+            self[[x - 1, y - 1, z]]
+        } else {
+            0
+        }
+    }
+}
+
+fn num_pixels_in_area(x_min: u32, y_min: u32, x_max: u32, y_max: u32) -> u64 {
+    (x_max - x_min + 1) as u64 * (y_max - y_min + 1) as u64
+}
+
+/// Integral images of the values and of their squares, so that the variance of any area is as cheap as its average.
+pub struct IntegralVarianceImage<T: Sample = u8> {
+    sums: IntegralRgbImage<T>,
+    squared_sums: IntegralRgbImage<T>,
+}
+impl<T: Sample> IntegralVarianceImage<T> {
+    pub fn generate<F>(w: u32, h: u32, get_rgorb: F) -> IntegralVarianceImage<T> where F: Fn(u32, u32, u32) -> T + Sync {
+        IntegralVarianceImage {
+            sums: IntegralRgbImage::generate(w, h, &get_rgorb),
+            squared_sums: IntegralRgbImage::generate_squared(w, h, &get_rgorb),
+        }
+    }
+    /// Over the same area as IntegralRgbImage::average_in_radius.
+    pub fn average_in_radius(&self, x: u32, y: u32, z: u32, radius: u32) -> T {
+        self.sums.average_in_radius(x, y, z, radius)
+    }
+    /// The (population) variance over the same area as average_in_radius, 0 for uniform areas.
+    pub fn variance_in_radius(&self, x: u32, y: u32, z: u32, radius: u32) -> f64 {
+        let (x_min, y_min, x_max, y_max) = self.sums.area_in_radius(x, y, radius);
+        let num_pixels = num_pixels_in_area(x_min, y_min, x_max, y_max) as u128;
+        let sum = self.sums.calculate_area_sum(x_min, y_min, x_max, y_max, z) as u128;
+        let squared_sum = self.squared_sums.calculate_area_sum(x_min, y_min, x_max, y_max, z) as u128;
+        //E[v^2] - E[v]^2, scaled by n^2 to stay in integers until the division
+        (squared_sum * num_pixels - sum * sum) as f64 / (num_pixels * num_pixels) as f64
+    }
+    /// Over the same area as average_in_radius, 0 for uniform areas.
+    pub fn standard_deviation_in_radius(&self, x: u32, y: u32, z: u32, radius: u32) -> T {
+        T::from_u32(self.variance_in_radius(x, y, z, radius).sqrt() as u32)
+    }
+    pub fn width(&self) -> u32 {
+        self.sums.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.sums.height()
+    }
+}
+
+impl<T: Sample> Index<[usize; 3]> for IntegralRgbImage<T> {
+    type Output = u64;
+    fn index(&self, s: [usize; 3]) -> &u64 {
+        self.0.index(s)
+    }
+}
+impl<T: Sample> IndexMut<[usize; 3]> for IntegralRgbImage<T> {
+    fn index_mut(&mut self, s: [usize; 3]) -> &mut u64 {
+        self.0.index_mut(s)
+    }
+}
+impl<T: Sample> Index<[u32; 3]> for IntegralRgbImage<T> {
+    type Output = u64;
+    fn index(&self, s: [u32; 3]) -> &u64 {
+        self.0.index([s[0] as usize, s[1] as usize, s[2] as usize])
+    }
+}
+impl<T: Sample> IndexMut<[u32; 3]> for IntegralRgbImage<T> {
+    fn index_mut(&mut self, s: [u32; 3]) -> &mut u64 {
+        self.0.index_mut([s[0] as usize, s[1] as usize, s[2] as usize])
+    }
+}
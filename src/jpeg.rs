@@ -0,0 +1,719 @@
+use std::fs;
+use std::ops::{Index, IndexMut};
+use std::path::Path;
+
+use crate::util::{DifCodeError, DifCodeResult, EncodingContainer};
+use crate::difference_encoder::multi_bit::encode_into_container_with_options;
+use crate::difference_encoder::options::EncodingOptions;
+use crate::difference_encoder::max_change_map_creator::{create_allowed_changes_map_for_message, write_minimal_evenly_random_allowed_changes_map};
+use crate::rand::RngCore;
+
+//Baseline (sequential, huffman coded) jpeg, parsed down to the quantized dct coefficients.
+//The coefficients are written back as they are (no decoding to pixels, no re-quantization), only the entropy coding is redone.
+//    The huffman tables of the original cannot be reused, because changed coefficients may require symbols it does not contain,
+//    so the standard tables (itu t.81 annex k.3), which contain every symbol of 8 bit baseline, are written instead.
+
+/// Coefficients are stored offset by this value, so that they fit the unsigned u16 sample of the container.
+pub const JPEG_COEFFICIENT_OFFSET: i32 = 2048;
+/// The largest magnitude an ac coefficient of an 8 bit baseline jpeg can have (huffman category 10).
+pub const JPEG_MAX_AC_COEFFICIENT: i32 = 1023;
+
+const NUM_COEFFICIENTS_PER_BLOCK: usize = 64;
+const NUM_AC_COEFFICIENTS_PER_BLOCK: usize = 63;
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DHT: u8 = 0xC4;
+const DRI: u8 = 0xDD;
+const SOF0: u8 = 0xC0;
+const SOF1: u8 = 0xC1;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+
+//the component indices of a scan and their (dc, ac) table selectors
+type ScanHeader = (Vec<usize>, Vec<(usize, usize)>);
+
+/// A jpeg as an encoding container of its ac coefficients (all blocks, all components, zigzag order),
+///    the dc coefficients are never part of the container (changes to them would be visible as blocking).
+/// Only baseline and extended sequential huffman coded jpegs with 8 bit precision are supported (not progressive, not arithmetic coded).
+#[derive(Clone)]
+pub struct DifCodeJpeg {
+    segments: Vec<Segment>,
+    width: u32,
+    height: u32,
+    max_h: usize,
+    max_v: usize,
+    components: Vec<Component>,
+    //all blocks of all components, 64 coefficients per block, in zigzag order and offset by JPEG_COEFFICIENT_OFFSET
+    coefficients: Vec<u16>,
+}
+
+#[derive(Clone)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    //including the padding to full mcus
+    blocks_per_line: usize,
+    blocks_per_column: usize,
+    first_block: usize,
+}
+
+#[derive(Clone)]
+enum Segment {
+    //kept as is (frame header, quantization tables, app data, comments, ...)
+    Raw(u8, Vec<u8>),
+    //the indices of the components in the scan
+    Scan(Vec<usize>),
+}
+
+impl DifCodeJpeg {
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn num_components(&self) -> usize { self.components.len() }
+
+    /// The ac coefficient (not offset) at the container index.
+    pub fn get_coefficient(&self, index: usize) -> i32 {
+        self[index] as i32 - JPEG_COEFFICIENT_OFFSET
+    }
+
+    pub fn open(path: &str) -> DifCodeResult<DifCodeJpeg> {
+        DifCodeJpeg::from_memory(&fs::read(path)?)
+    }
+    pub fn save(&self, path: &str) -> DifCodeResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> DifCodeResult<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn from_memory(buffer: &[u8]) -> DifCodeResult<DifCodeJpeg> {
+        if buffer.len() < 4 || buffer[0] != 0xFF || buffer[1] != SOI {
+            return Err(DifCodeError::InvalidJpeg("missing start of image marker"))
+        }
+
+        let mut jpeg = DifCodeJpeg { segments: Vec::new(), width: 0, height: 0, max_h: 1, max_v: 1, components: Vec::new(), coefficients: Vec::new() };
+        let mut dc_tables: [Option<HuffmanDecoder>; 4] = [None, None, None, None];
+        let mut ac_tables: [Option<HuffmanDecoder>; 4] = [None, None, None, None];
+        let mut restart_interval = 0;
+
+        let mut pos = 2;
+        loop {
+            //markers may be preceded by any number of fill bytes
+            while pos < buffer.len() && buffer[pos] == 0xFF && pos + 1 < buffer.len() && buffer[pos + 1] == 0xFF {
+                pos += 1;
+            }
+            if pos + 1 >= buffer.len() || buffer[pos] != 0xFF {
+                return Err(DifCodeError::InvalidJpeg("expected marker"))
+            }
+            let marker = buffer[pos + 1];
+            pos += 2;
+            if marker == EOI {
+                break
+            }
+            if pos + 2 > buffer.len() {
+                return Err(DifCodeError::InvalidJpeg("truncated segment"))
+            }
+            let length = read_u16(buffer, pos) as usize;
+            if length < 2 || pos + length > buffer.len() {
+                return Err(DifCodeError::InvalidJpeg("truncated segment"))
+            }
+            let payload = &buffer[pos + 2..pos + length];
+            pos += length;
+
+            match marker {
+                SOF0 | SOF1 => {
+                    jpeg.parse_frame_header(payload)?;
+                    jpeg.segments.push(Segment::Raw(marker, payload.to_vec()));
+                }
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    return Err(DifCodeError::InvalidJpeg("only baseline and extended sequential huffman coded jpegs are supported (not progressive, lossless or arithmetic coded)"))
+                }
+                DHT => parse_huffman_tables(payload, &mut dc_tables, &mut ac_tables)?,
+                DRI => {
+                    if payload.len() != 2 {
+                        return Err(DifCodeError::InvalidJpeg("invalid restart interval"))
+                    }
+                    restart_interval = read_u16(payload, 0) as usize;
+                }
+                SOS => {
+                    let (scan, table_selectors) = jpeg.parse_scan_header(payload)?;
+                    let scan_length = find_entropy_coded_segment_end(buffer, pos) - pos;
+                    let mut reader = BitReader::new(&buffer[pos..pos + scan_length]);
+                    pos += scan_length;
+
+                    let mut decoders = Vec::with_capacity(scan.len());
+                    for (dc_table, ac_table) in table_selectors {
+                        match (&dc_tables[dc_table], &ac_tables[ac_table]) {
+                            (Some(dc), Some(ac)) => decoders.push((dc, ac)),
+                            _ => return Err(DifCodeError::InvalidJpeg("scan references undefined huffman table"))
+                        }
+                    }
+
+                    let mut predictions = vec![0i32; scan.len()];
+                    for (mcu_index, mcu) in jpeg.scan_mcus(&scan).iter().enumerate() {
+                        if restart_interval > 0 && mcu_index > 0 && mcu_index % restart_interval == 0 {
+                            reader.restart()?;
+                            predictions.iter_mut().for_each(|prediction| *prediction = 0);
+                        }
+                        for (scan_component, block) in mcu {
+                            let (dc, ac) = decoders[*scan_component];
+                            jpeg.decode_block(&mut reader, dc, ac, &mut predictions[*scan_component], *block)?;
+                        }
+                    }
+                    jpeg.segments.push(Segment::Scan(scan));
+                }
+                _ => jpeg.segments.push(Segment::Raw(marker, payload.to_vec()))
+            }
+        }
+
+        if jpeg.components.is_empty() || !jpeg.segments.iter().any(|segment| matches!(segment, Segment::Scan(_))) {
+            return Err(DifCodeError::InvalidJpeg("missing frame or scan"))
+        }
+        Ok(jpeg)
+    }
+
+    /// Encodes the coefficients back into a jpeg file, with the standard huffman tables and without restart markers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let dc_encoders = [HuffmanEncoder::new(&STANDARD_DC_LUMINANCE_BITS, &STANDARD_DC_VALUES), HuffmanEncoder::new(&STANDARD_DC_CHROMINANCE_BITS, &STANDARD_DC_VALUES)];
+        let ac_encoders = [HuffmanEncoder::new(&STANDARD_AC_LUMINANCE_BITS, &STANDARD_AC_LUMINANCE_VALUES), HuffmanEncoder::new(&STANDARD_AC_CHROMINANCE_BITS, &STANDARD_AC_CHROMINANCE_VALUES)];
+
+        let mut bytes = vec![0xFF, SOI];
+        let mut tables_written = false;
+        for segment in &self.segments {
+            match segment {
+                Segment::Raw(marker, payload) => write_segment(&mut bytes, *marker, payload),
+                Segment::Scan(scan) => {
+                    if !tables_written {
+                        write_segment(&mut bytes, DHT, &standard_huffman_tables_payload());
+                        tables_written = true;
+                    }
+                    let mut header = vec![scan.len() as u8];
+                    for component_index in scan {
+                        let table = table_for(*component_index) as u8;
+                        header.push(self.components[*component_index].id);
+                        header.push(table << 4 | table);
+                    }
+                    header.extend_from_slice(&[0, 63, 0]);
+                    write_segment(&mut bytes, SOS, &header);
+
+                    let mut writer = BitWriter::new(&mut bytes);
+                    let mut predictions = vec![0i32; scan.len()];
+                    for mcu in self.scan_mcus(scan) {
+                        for (scan_component, block) in mcu {
+                            let table = table_for(scan[scan_component]);
+                            self.encode_block(&mut writer, &dc_encoders[table], &ac_encoders[table], &mut predictions[scan_component], block);
+                        }
+                    }
+                    writer.flush();
+                }
+            }
+        }
+        bytes.extend_from_slice(&[0xFF, EOI]);
+        bytes
+    }
+
+    fn parse_frame_header(&mut self, payload: &[u8]) -> DifCodeResult<()> {
+        if !self.components.is_empty() {
+            return Err(DifCodeError::InvalidJpeg("multiple frames"))
+        }
+        if payload.len() < 6 || payload[0] != 8 {
+            return Err(DifCodeError::InvalidJpeg("only 8 bit precision is supported"))
+        }
+        self.height = read_u16(payload, 1) as u32;
+        self.width = read_u16(payload, 3) as u32;
+        let num_components = payload[5] as usize;
+        if self.width == 0 || self.height == 0 || num_components == 0 || payload.len() != 6 + num_components * 3 {
+            return Err(DifCodeError::InvalidJpeg("invalid frame header"))
+        }
+
+        for c in 0..num_components {
+            let (id, sampling) = (payload[6 + c * 3], payload[7 + c * 3]);
+            let (h, v) = ((sampling >> 4) as usize, (sampling & 0x0F) as usize);
+            if h == 0 || h > 4 || v == 0 || v > 4 {
+                return Err(DifCodeError::InvalidJpeg("invalid sampling factor"))
+            }
+            self.components.push(Component { id, h, v, blocks_per_line: 0, blocks_per_column: 0, first_block: 0 });
+        }
+        self.max_h = self.components.iter().map(|c| c.h).max().unwrap();
+        self.max_v = self.components.iter().map(|c| c.v).max().unwrap();
+        let mcus_per_line = (self.width as usize).div_ceil(8 * self.max_h);
+        let mcus_per_column = (self.height as usize).div_ceil(8 * self.max_v);
+
+        let mut num_blocks = 0;
+        for component in self.components.iter_mut() {
+            component.blocks_per_line = mcus_per_line * component.h;
+            component.blocks_per_column = mcus_per_column * component.v;
+            component.first_block = num_blocks;
+            num_blocks += component.blocks_per_line * component.blocks_per_column;
+        }
+        self.coefficients = vec![JPEG_COEFFICIENT_OFFSET as u16; num_blocks * NUM_COEFFICIENTS_PER_BLOCK];
+        Ok(())
+    }
+
+    fn parse_scan_header(&self, payload: &[u8]) -> DifCodeResult<ScanHeader> {
+        if self.components.is_empty() {
+            return Err(DifCodeError::InvalidJpeg("scan before frame header"))
+        }
+        let num_components = *payload.first().unwrap_or(&0) as usize;
+        if num_components == 0 || num_components > 4 || payload.len() != 4 + num_components * 2 {
+            return Err(DifCodeError::InvalidJpeg("invalid scan header"))
+        }
+        let mut scan = Vec::with_capacity(num_components);
+        let mut table_selectors = Vec::with_capacity(num_components);
+        for c in 0..num_components {
+            let (id, tables) = (payload[1 + c * 2], payload[2 + c * 2]);
+            match self.components.iter().position(|component| component.id == id) {
+                Some(component_index) => scan.push(component_index),
+                None => return Err(DifCodeError::InvalidJpeg("scan references undefined component"))
+            }
+            table_selectors.push(((tables >> 4) as usize & 3, (tables & 0x0F) as usize & 3));
+        }
+        let (spectral_start, spectral_end, approximation) = (payload[1 + num_components * 2], payload[2 + num_components * 2], payload[3 + num_components * 2]);
+        if spectral_start != 0 || spectral_end != 63 || approximation != 0 {
+            return Err(DifCodeError::InvalidJpeg("only sequential scans are supported"))
+        }
+        Ok((scan, table_selectors))
+    }
+
+    //the (index of the component in the scan, block index) of each mcu, in the order they are coded
+    fn scan_mcus(&self, scan: &[usize]) -> Vec<Vec<(usize, usize)>> {
+        let mut mcus = Vec::new();
+        if scan.len() == 1 {
+            //non interleaved, one block per mcu and only the blocks covering the (sub sampled) component, not the padding
+            let component = &self.components[scan[0]];
+            let blocks_per_line = (self.width as usize * component.h).div_ceil(self.max_h).div_ceil(8);
+            let blocks_per_column = (self.height as usize * component.v).div_ceil(self.max_v).div_ceil(8);
+            for y in 0..blocks_per_column {
+                for x in 0..blocks_per_line {
+                    mcus.push(vec![(0, component.first_block + y * component.blocks_per_line + x)]);
+                }
+            }
+        } else {
+            let mcus_per_line = (self.width as usize).div_ceil(8 * self.max_h);
+            let mcus_per_column = (self.height as usize).div_ceil(8 * self.max_v);
+            for mcu_y in 0..mcus_per_column {
+                for mcu_x in 0..mcus_per_line {
+                    let mut mcu = Vec::new();
+                    for (scan_component, component_index) in scan.iter().enumerate() {
+                        let component = &self.components[*component_index];
+                        for y in 0..component.v {
+                            for x in 0..component.h {
+                                mcu.push((scan_component, component.first_block + (mcu_y * component.v + y) * component.blocks_per_line + mcu_x * component.h + x));
+                            }
+                        }
+                    }
+                    mcus.push(mcu);
+                }
+            }
+        }
+        mcus
+    }
+
+    fn decode_block(&mut self, reader: &mut BitReader, dc: &HuffmanDecoder, ac: &HuffmanDecoder, prediction: &mut i32, block: usize) -> DifCodeResult<()> {
+        let coefficients = &mut self.coefficients[block * NUM_COEFFICIENTS_PER_BLOCK..(block + 1) * NUM_COEFFICIENTS_PER_BLOCK];
+
+        let category = dc.decode(reader)?;
+        if category > 11 {
+            return Err(DifCodeError::InvalidJpeg("invalid dc coefficient"))
+        }
+        *prediction += reader.receive_extend(category);
+        coefficients[0] = (*prediction + JPEG_COEFFICIENT_OFFSET) as u16;
+
+        let mut k = 1;
+        while k < NUM_COEFFICIENTS_PER_BLOCK {
+            let run_size = ac.decode(reader)?;
+            let (run, size) = ((run_size >> 4) as usize, run_size & 0x0F);
+            if size == 0 {
+                if run == 15 {
+                    k += 16; //zero run length
+                    continue
+                }
+                break //end of block
+            }
+            k += run;
+            if k >= NUM_COEFFICIENTS_PER_BLOCK || size > 10 {
+                return Err(DifCodeError::InvalidJpeg("invalid ac coefficient"))
+            }
+            coefficients[k] = (reader.receive_extend(size) + JPEG_COEFFICIENT_OFFSET) as u16;
+            k += 1;
+        }
+        Ok(())
+    }
+
+    fn encode_block(&self, writer: &mut BitWriter, dc: &HuffmanEncoder, ac: &HuffmanEncoder, prediction: &mut i32, block: usize) {
+        let coefficients = &self.coefficients[block * NUM_COEFFICIENTS_PER_BLOCK..(block + 1) * NUM_COEFFICIENTS_PER_BLOCK];
+
+        let dc_value = coefficients[0] as i32 - JPEG_COEFFICIENT_OFFSET;
+        let difference = dc_value - *prediction;
+        *prediction = dc_value;
+        let category = category_of(difference);
+        dc.encode(writer, category);
+        writer.write_value(difference, category);
+
+        let mut run = 0;
+        for coefficient in &coefficients[1..] {
+            let value = *coefficient as i32 - JPEG_COEFFICIENT_OFFSET;
+            if value == 0 {
+                run += 1;
+                continue
+            }
+            while run > 15 {
+                ac.encode(writer, 0xF0); //zero run length
+                run -= 16;
+            }
+            let category = category_of(value);
+            ac.encode(writer, (run << 4) as u8 | category);
+            writer.write_value(value, category);
+            run = 0;
+        }
+        if run > 0 {
+            ac.encode(writer, 0x00); //end of block
+        }
+    }
+
+    fn container_index_to_coefficient_index(index: usize) -> usize {
+        (index / NUM_AC_COEFFICIENTS_PER_BLOCK) * NUM_COEFFICIENTS_PER_BLOCK + 1 + index % NUM_AC_COEFFICIENTS_PER_BLOCK
+    }
+}
+
+impl std::fmt::Debug for DifCodeJpeg {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DifCodeJpeg({}, {}, {} components)", self.width, self.height, self.components.len())
+    }
+}
+impl std::fmt::Display for DifCodeJpeg {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Jpeg({}, {})", self.width, self.height)
+    }
+}
+impl PartialEq<DifCodeJpeg> for DifCodeJpeg {
+    fn eq(&self, other: &DifCodeJpeg) -> bool {
+        self.width == other.width && self.height == other.height && self.coefficients == other.coefficients
+    }
+}
+impl IndexMut<usize> for DifCodeJpeg {
+    fn index_mut(&mut self, index: usize) -> &mut u16 {
+        &mut self.coefficients[DifCodeJpeg::container_index_to_coefficient_index(index)]
+    }
+}
+impl Index<usize> for DifCodeJpeg {
+    type Output = u16;
+    fn index(&self, index: usize) -> &u16 {
+        &self.coefficients[DifCodeJpeg::container_index_to_coefficient_index(index)]
+    }
+}
+impl EncodingContainer<u16> for DifCodeJpeg {
+    fn len(&self) -> usize {
+        self.coefficients.len() / NUM_COEFFICIENTS_PER_BLOCK * NUM_AC_COEFFICIENTS_PER_BLOCK
+    }
+}
+
+/// The encoded jpeg (see encode_into_container) only has changed ac coefficients, it is stored as is (no re-compression).
+pub fn encode_into_jpeg_into_path(message_bytes: &[u8], original: &DifCodeJpeg, allowed_changes_map: &dyn EncodingContainer<u16>, path: &str) -> DifCodeResult<()> {
+    encode_into_jpeg_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_jpeg_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeJpeg, allowed_changes_map: &dyn EncodingContainer<u16>, path: &str) -> DifCodeResult<()> {
+    encode_into_container_with_options(message_bytes, options, original, allowed_changes_map)?.save(path)
+}
+
+//only non zero ac coefficients are changed and only away from zero, so the zero runs (and with them most of the file size) stay the same
+//    and no coefficient flips its sign. The default max difference keeps each change within a few quantization steps.
+pub const JPEG_DEFAULT_MAX_DIFFERENCE: u16 = 6;
+pub fn create_minimal_evenly_random_jpeg_allowed_changes_map(message: &[u8], original: &DifCodeJpeg) -> DifCodeResult<Vec<u16>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_jpeg_allowed_changes_map(length, original, JPEG_DEFAULT_MAX_DIFFERENCE, rng, output_map))
+}
+pub fn write_minimal_evenly_random_jpeg_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeJpeg, max_difference: u16, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+    let change_constraint_calculator = move |_index, ov: u16| {
+        let coefficient = ov as i32 - JPEG_COEFFICIENT_OFFSET;
+        if coefficient == 0 {
+            (0, true)
+        } else {
+            let headroom = (JPEG_MAX_AC_COEFFICIENT - coefficient.abs()) as u16;
+            (max_difference.min(headroom), coefficient > 0)
+        }
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
+
+
+
+fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    (bytes[pos] as u16) << 8 | bytes[pos + 1] as u16
+}
+//number of bits required for the magnitude
+fn category_of(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+//the first component uses the luminance tables, all others the chrominance tables
+fn table_for(component_index: usize) -> usize {
+    if component_index == 0 { 0 } else { 1 }
+}
+
+fn write_segment(bytes: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    bytes.extend_from_slice(&[0xFF, marker]);
+    bytes.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    bytes.extend_from_slice(payload);
+}
+
+//the entropy coded data ends at the first marker that is neither stuffing (0xFF00) nor a restart marker
+fn find_entropy_coded_segment_end(buffer: &[u8], mut pos: usize) -> usize {
+    while pos + 1 < buffer.len() {
+        if buffer[pos] == 0xFF && buffer[pos + 1] != 0x00 && !(RST0..=RST7).contains(&buffer[pos + 1]) && buffer[pos + 1] != 0xFF {
+            return pos
+        }
+        pos += 1;
+    }
+    buffer.len()
+}
+
+fn parse_huffman_tables(mut payload: &[u8], dc_tables: &mut [Option<HuffmanDecoder>; 4], ac_tables: &mut [Option<HuffmanDecoder>; 4]) -> DifCodeResult<()> {
+    while !payload.is_empty() {
+        if payload.len() < 17 {
+            return Err(DifCodeError::InvalidJpeg("invalid huffman table"))
+        }
+        let (class, id) = (payload[0] >> 4, (payload[0] & 0x0F) as usize);
+        let mut bits = [0u8; 16];
+        bits.copy_from_slice(&payload[1..17]);
+        let num_values = bits.iter().map(|n| *n as usize).sum::<usize>();
+        if class > 1 || id > 3 || num_values > 256 || payload.len() < 17 + num_values {
+            return Err(DifCodeError::InvalidJpeg("invalid huffman table"))
+        }
+        let decoder = HuffmanDecoder::new(&bits, &payload[17..17 + num_values]);
+        if class == 0 {
+            dc_tables[id] = Some(decoder);
+        } else {
+            ac_tables[id] = Some(decoder);
+        }
+        payload = &payload[17 + num_values..];
+    }
+    Ok(())
+}
+
+fn standard_huffman_tables_payload() -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (class_and_id, bits, values) in [(0x00, &STANDARD_DC_LUMINANCE_BITS, &STANDARD_DC_VALUES[..]), (0x01, &STANDARD_DC_CHROMINANCE_BITS, &STANDARD_DC_VALUES[..]),
+                                         (0x10, &STANDARD_AC_LUMINANCE_BITS, &STANDARD_AC_LUMINANCE_VALUES[..]), (0x11, &STANDARD_AC_CHROMINANCE_BITS, &STANDARD_AC_CHROMINANCE_VALUES[..])] {
+        payload.push(class_and_id);
+        payload.extend_from_slice(bits);
+        payload.extend_from_slice(values);
+    }
+    payload
+}
+
+
+
+//canonical huffman decoding (itu t.81 f.2.2.3)
+#[derive(Clone)]
+struct HuffmanDecoder {
+    min_code: [i32; 17],
+    max_code: [i32; 17],
+    value_pointer: [usize; 17],
+    values: Vec<u8>,
+}
+impl HuffmanDecoder {
+    fn new(bits: &[u8; 16], values: &[u8]) -> HuffmanDecoder {
+        let mut decoder = HuffmanDecoder { min_code: [0; 17], max_code: [-1; 17], value_pointer: [0; 17], values: values.to_vec() };
+        let (mut code, mut k) = (0i32, 0usize);
+        for length in 1..=16 {
+            let num_codes = bits[length - 1] as usize;
+            decoder.value_pointer[length] = k;
+            decoder.min_code[length] = code;
+            if num_codes > 0 {
+                code += num_codes as i32;
+                k += num_codes;
+                decoder.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        decoder
+    }
+    fn decode(&self, reader: &mut BitReader) -> DifCodeResult<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | reader.read_bit() as i32;
+            if code <= self.max_code[length] {
+                return Ok(self.values[self.value_pointer[length] + (code - self.min_code[length]) as usize])
+            }
+        }
+        Err(DifCodeError::InvalidJpeg("invalid huffman code"))
+    }
+}
+
+struct HuffmanEncoder {
+    //(code, length) per symbol
+    codes: [(u16, u8); 256],
+}
+impl HuffmanEncoder {
+    fn new(bits: &[u8; 16], values: &[u8]) -> HuffmanEncoder {
+        let mut encoder = HuffmanEncoder { codes: [(0, 0); 256] };
+        let (mut code, mut k) = (0u16, 0usize);
+        for length in 1..=16 {
+            for _ in 0..bits[length - 1] {
+                encoder.codes[values[k] as usize] = (code, length as u8);
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        encoder
+    }
+    fn encode(&self, writer: &mut BitWriter, symbol: u8) {
+        let (code, length) = self.codes[symbol as usize];
+        writer.write_bits(code as u32, length);
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u8,
+    num_bits: u8,
+}
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0, bits: 0, num_bits: 0 }
+    }
+    fn read_bit(&mut self) -> u8 {
+        if self.num_bits == 0 {
+            //past the end or at a restart marker only zeros are read, a valid stream never gets there
+            self.bits = match (self.data.get(self.pos), self.data.get(self.pos + 1)) {
+                (Some(0xFF), Some(0x00)) => { self.pos += 2; 0xFF }
+                (Some(0xFF), _) | (None, _) => 0,
+                (Some(byte), _) => { self.pos += 1; *byte }
+            };
+            self.num_bits = 8;
+        }
+        self.num_bits -= 1;
+        (self.bits >> self.num_bits) & 1
+    }
+    fn receive_extend(&mut self, num_bits: u8) -> i32 {
+        let mut value = 0i32;
+        for _ in 0..num_bits {
+            value = value << 1 | self.read_bit() as i32;
+        }
+        if num_bits > 0 && value < 1 << (num_bits - 1) {
+            value - (1 << num_bits) + 1
+        } else {
+            value
+        }
+    }
+    //skips the remaining bits of the current byte and the restart marker following it
+    fn restart(&mut self) -> DifCodeResult<()> {
+        self.num_bits = 0;
+        match (self.data.get(self.pos), self.data.get(self.pos + 1)) {
+            (Some(0xFF), Some(marker)) if (RST0..=RST7).contains(marker) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(DifCodeError::InvalidJpeg("missing restart marker"))
+        }
+    }
+}
+
+struct BitWriter<'a> {
+    bytes: &'a mut Vec<u8>,
+    bits: u32,
+    num_bits: u8,
+}
+impl<'a> BitWriter<'a> {
+    fn new(bytes: &'a mut Vec<u8>) -> BitWriter<'a> {
+        BitWriter { bytes, bits: 0, num_bits: 0 }
+    }
+    fn write_bits(&mut self, bits: u32, num_bits: u8) {
+        for i in (0..num_bits).rev() {
+            self.bits = self.bits << 1 | (bits >> i) & 1;
+            self.num_bits += 1;
+            if self.num_bits == 8 {
+                let byte = self.bits as u8;
+                self.bytes.push(byte);
+                if byte == 0xFF {
+                    self.bytes.push(0x00); //stuffing
+                }
+                self.bits = 0;
+                self.num_bits = 0;
+            }
+        }
+    }
+    //negative values are written as their ones complement
+    fn write_value(&mut self, value: i32, category: u8) {
+        let bits = if value < 0 { value - 1 } else { value };
+        self.write_bits(bits as u32 & ((1u32 << category) - 1), category);
+    }
+    //pads the last byte with ones
+    fn flush(&mut self) {
+        if self.num_bits > 0 {
+            self.write_bits(0xFF, 8 - self.num_bits);
+        }
+    }
+}
+
+
+
+//itu t.81 annex k.3
+const STANDARD_DC_LUMINANCE_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const STANDARD_DC_CHROMINANCE_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const STANDARD_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const STANDARD_AC_LUMINANCE_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D];
+const STANDARD_AC_LUMINANCE_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52, 0xD1, 0xF0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7,
+    0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5,
+    0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2,
+    0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+    0xF9, 0xFA,
+];
+const STANDARD_AC_CHROMINANCE_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const STANDARD_AC_CHROMINANCE_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33, 0x52, 0xF0,
+    0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26,
+    0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5,
+    0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3,
+    0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA,
+    0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8,
+    0xF9, 0xFA,
+];
+
+
+
+#[test]
+fn test_subsampled_block_order_matches_decoder() {
+    //4:2:0, so each mcu holds 4 luma blocks and one block of each chroma component
+    let mut frame_header = vec![8, 0, 32, 0, 32, 3];
+    frame_header.extend_from_slice(&[1, 0x22, 0, 2, 0x11, 0, 3, 0x11, 0]);
+    let mut quantization_table = vec![0u8];
+    quantization_table.extend_from_slice(&[1; 64]);
+    let mut jpeg = DifCodeJpeg { segments: Vec::new(), width: 0, height: 0, max_h: 1, max_v: 1, components: Vec::new(), coefficients: Vec::new() };
+    jpeg.parse_frame_header(&frame_header).unwrap();
+    jpeg.segments = vec![Segment::Raw(0xDB, quantization_table), Segment::Raw(SOF0, frame_header), Segment::Scan(vec![0, 1, 2])];
+
+    //a distinct brightness per luma block (dc of 8 times the level shifted value with a quantization of 1)
+    let level_at = |bx: usize, by: usize| 40 + 10 * (bx + 4 * by) as i32;
+    for by in 0..4 {
+        for bx in 0..4 {
+            jpeg.coefficients[(by * 4 + bx) * NUM_COEFFICIENTS_PER_BLOCK] = ((level_at(bx, by) - 128) * 8 + JPEG_COEFFICIENT_OFFSET) as u16;
+        }
+    }
+
+    let bytes = jpeg.to_bytes();
+    assert!(jpeg == DifCodeJpeg::from_memory(&bytes).unwrap());
+    let decoded = image::load_from_memory(&bytes).unwrap().into_luma8();
+    for by in 0..4 {
+        for bx in 0..4 {
+            let decoded_level = decoded.get_pixel(bx as u32 * 8 + 4, by as u32 * 8 + 4).0[0] as i32;
+            assert!((decoded_level - level_at(bx, by)).abs() <= 1);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+extern crate image;
+extern crate rand;
+
+mod difference_encoder;
+mod util;
+mod jpeg;
+mod animation;
+mod audio;
+mod raw_file;
+mod parallel;
+mod integral_image;
+mod color;
+mod metrics;
+mod steganalysis;
+
+//curated public api - everything required to encode into, decode from and measure containers
+
+pub use crate::util::{DifCodeImage, DifCodeRgbaImage, DifCodeImage16, Rgb16Image, DifCodeLumaImage, DifCodeLumaImage16, Luma16Image, Sample, DifCodeError, DifCodeResult, EncodingContainer, get_length_in_bits};
+pub use crate::integral_image::{IntegralRgbImage, IntegralVarianceImage};
+
+pub use crate::jpeg::{DifCodeJpeg, JPEG_COEFFICIENT_OFFSET, JPEG_MAX_AC_COEFFICIENT, encode_into_jpeg_into_path, encode_into_jpeg_into_path_with_options, create_minimal_evenly_random_jpeg_allowed_changes_map, write_minimal_evenly_random_jpeg_allowed_changes_map, JPEG_DEFAULT_MAX_DIFFERENCE};
+pub use crate::animation::{DifCodeGif, DifCodeApng, is_gif, is_animated_png, encode_into_gif_into_path, encode_into_gif_into_path_with_options, encode_into_apng_into_path, encode_into_apng_into_path_with_options};
+pub use crate::audio::{DifCodeWav, IntegralAmplitude, is_wav, get_wav_bits_per_sample, encode_into_wav_into_path, encode_into_wav_into_path_with_options, create_minimal_evenly_random_max_amplitude_allowed_changes_map, write_minimal_evenly_random_max_amplitude_allowed_changes_map};
+pub use crate::raw_file::{DifCodeRawFile, get_format_protected_ranges, encode_into_raw_file_into_path, encode_into_raw_file_into_path_with_options, create_minimal_evenly_random_raw_file_allowed_changes_map, write_minimal_evenly_random_raw_file_allowed_changes_map};
+pub use crate::color::{DeltaEFormula, LabConverter, DEFAULT_MAX_DELTA_E,
+                       create_minimal_evenly_random_max_delta_e_allowed_changes_map, write_minimal_evenly_random_max_delta_e_allowed_changes_map,
+                       create_minimal_evenly_random_max_delta_e_luma_allowed_changes_map, write_minimal_evenly_random_max_delta_e_luma_allowed_changes_map,
+                       create_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map, write_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map,
+                       create_minimal_evenly_random_max_delta_e_image16_allowed_changes_map, write_minimal_evenly_random_max_delta_e_image16_allowed_changes_map,
+                       create_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map, write_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map};
+pub use crate::metrics::{QualityMetrics, get_quality_metrics, get_quality_metrics_of_planes};
+pub use crate::steganalysis::{SteganalysisScores, SteganalysisReport, get_steganalysis_scores, get_steganalysis_scores_of_planes, get_steganalysis_report, get_steganalysis_report_of_planes,
+                              chi_square_attack, rs_analysis, sample_pair_analysis};
+
+pub use crate::difference_encoder::multi_bit::{encode, encode_into_vec, encode_into_image, encode_into_image_into_path,
+                                               encode_with_options, encode_into_vec_with_options, encode_into_image_with_options, encode_into_image_into_path_with_options,
+                                               encode_into_rgba_image, encode_into_rgba_image_into_path, encode_into_rgba_image_with_options, encode_into_rgba_image_into_path_with_options,
+                                               encode_into_image16, encode_into_image16_into_path, encode_into_image16_with_options, encode_into_image16_into_path_with_options,
+                                               encode_into_luma_image, encode_into_luma_image_into_path, encode_into_luma_image_with_options, encode_into_luma_image_into_path_with_options,
+                                               encode_into_luma_image16, encode_into_luma_image16_into_path, encode_into_luma_image16_with_options, encode_into_luma_image16_into_path_with_options,
+                                               encode_into_container, encode_into_container_with_options,
+                                               encode_from_reader, encode_from_reader_with_options,
+                                               decode, decode_into_vec, decode_with_key, decode_into_vec_with_key, decode_into_vec_with_report, DecodingReport,
+                                               decode_into_writer, decode_into_writer_with_key, decode_into_writer_with_report,
+                                               encode_raw, encode_raw_with_key, decode_raw, decode_raw_into_vec, decode_raw_with_key, decode_raw_into_vec_with_key};
+pub use crate::difference_encoder::envelope::{PayloadFlags, PayloadHeader, FORMAT_VERSION, HEADER_LENGTH_IN_BYTES, HEADER_LENGTH_IN_BITS};
+pub use crate::difference_encoder::error_correction::ErrorCorrectionReport;
+pub use crate::difference_encoder::options::EncodingOptions;
+pub use crate::difference_encoder::key::DifCodeKey;
+pub use crate::difference_encoder::traversal_order::TraversalOrder;
+pub use crate::difference_encoder::channel_weighting::ChannelWeighting;
+
+pub use crate::difference_encoder::multi_bit::{get_encoded_message_length_in_bits, get_max_encodable_message_length_in_bits, get_min_encodable_message_length_in_bits, get_expected_encodable_message_length_in_bits,
+                                               get_capacity_report, CapacityReport};
+
+pub use crate::difference_encoder::max_change_map_creator::{create_minimal_evenly_random_allowed_changes_map, create_minimal_evenly_random_allowed_changes_map_for, create_minimal_evenly_random_allowed_changes_map_for_image,
+                                                            write_minimal_evenly_random_allowed_changes_map, write_minimal_evenly_random_allowed_changes_map_for, write_minimal_evenly_random_allowed_changes_map_with,
+                                                            create_minimal_stratified_allowed_changes_map_for_image, write_minimal_stratified_allowed_changes_map, write_minimal_stratified_allowed_changes_map_with,
+                                                            write_minimal_evenly_random_image_allowed_changes_map, write_minimal_stratified_image_allowed_changes_map,
+                                                            create_minimal_stratified_rgba_allowed_changes_map, write_minimal_stratified_rgba_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_area_average_allowed_changes_map, write_minimal_evenly_random_max_area_average_allowed_changes_map, write_minimal_evenly_random_max_area_average_allowed_changes_map_for, DEFAULT_AREA_AVERAGE_RADIUS, DEFAULT_LOCAL_VARIANCE_RADIUS,
+                                                            create_minimal_evenly_random_rgba_allowed_changes_map, write_minimal_evenly_random_rgba_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_area_average_rgba_allowed_changes_map, write_minimal_evenly_random_max_area_average_rgba_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_area_average_luma_allowed_changes_map, write_minimal_evenly_random_max_area_average_luma_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_area_average_image16_allowed_changes_map, write_minimal_evenly_random_max_area_average_image16_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_area_average_luma16_allowed_changes_map, write_minimal_evenly_random_max_area_average_luma16_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_local_variance_allowed_changes_map, write_minimal_evenly_random_max_local_variance_allowed_changes_map, write_minimal_evenly_random_max_local_variance_allowed_changes_map_for,
+                                                            create_minimal_evenly_random_max_local_variance_luma_allowed_changes_map, write_minimal_evenly_random_max_local_variance_luma_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map, write_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_local_variance_image16_allowed_changes_map, write_minimal_evenly_random_max_local_variance_image16_allowed_changes_map,
+                                                            create_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map, write_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map};
+
+//lower level access to the code tables and the original single bit scheme, not required for regular encoding/decoding.
+pub use crate::difference_encoder::{bits_difference_converter, legacy_single_bit};
@@ -1,28 +1,46 @@
 extern crate base64;
-extern crate image;
-extern crate rand;
 extern crate reqwest;
-extern crate threadpool;
 
 use std::ops::Deref;
 
+use rand::RngCore;
+
 use jokrey_utilities::encoding::tag_based::bytes::remote::authenticated::authentication_helper::{aes_crt_np_128_decrypt_from_decipherable, aes_crt_np_128_encrypt_into_decipherable};
 use jokrey_utilities::general::Wrapper;
 use jokrey_utilities::tui_menu_interface::{Choice, ChoiceConstrainedInput, InputItem, Menu, NonExistingPathInput, print_and_read_line};
 
-use crate::util::{DifCodeImage, DifCodeResult};
-use crate::difference_encoder::multi_bit::{decode_into_vec, encode_into_image_into_path};
-use crate::difference_encoder::max_change_map_creator::{create_minimal_evenly_random_allowed_changes_map_for_image, create_minimal_evenly_random_max_area_average_allowed_changes_map};
+use generic_image_steganography::{DifCodeKey, DifCodeResult, DifCodeError, DifCodeJpeg, DifCodeWav, EncodingContainer, EncodingOptions, PayloadFlags, Sample,
+                                  write_minimal_evenly_random_allowed_changes_map_with, write_minimal_evenly_random_max_area_average_allowed_changes_map,
+                                  write_minimal_evenly_random_rgba_allowed_changes_map, write_minimal_evenly_random_max_area_average_rgba_allowed_changes_map,
+                                  write_minimal_evenly_random_max_area_average_luma_allowed_changes_map, write_minimal_evenly_random_jpeg_allowed_changes_map, JPEG_DEFAULT_MAX_DIFFERENCE,
+                                  write_minimal_evenly_random_max_amplitude_allowed_changes_map, write_minimal_evenly_random_raw_file_allowed_changes_map,
+                                  write_minimal_evenly_random_max_local_variance_allowed_changes_map, write_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map,
+                                  write_minimal_evenly_random_max_local_variance_luma_allowed_changes_map,
+                                  write_minimal_stratified_allowed_changes_map_with, write_minimal_stratified_rgba_allowed_changes_map,
+                                  write_minimal_evenly_random_image_allowed_changes_map, write_minimal_stratified_image_allowed_changes_map, ChannelWeighting,
+                                  write_minimal_evenly_random_max_delta_e_allowed_changes_map, write_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map,
+                                  write_minimal_evenly_random_max_delta_e_luma_allowed_changes_map, DeltaEFormula, DEFAULT_MAX_DELTA_E,
+                                  write_minimal_evenly_random_max_area_average_image16_allowed_changes_map, write_minimal_evenly_random_max_area_average_luma16_allowed_changes_map,
+                                  write_minimal_evenly_random_max_local_variance_image16_allowed_changes_map, write_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map,
+                                  write_minimal_evenly_random_max_delta_e_image16_allowed_changes_map, write_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map,
+                                  DEFAULT_AREA_AVERAGE_RADIUS, DEFAULT_LOCAL_VARIANCE_RADIUS};
+
+use crate::container_image::{ContainerImage, ContainerFormat};
 
-mod difference_encoder;
-mod util;
 mod image_ui_util;
+mod cli;
+mod container_image;
 
 //test messages:
 //HHHHHHHHHHHHHHHHHHHHHHHHHHHHHHHHHHhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhhAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLllllllllllllllllllllllllllllOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOOooooooooooooooooooooooooooooo
 //Hallo dies ist ein Test!?
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     // image_ui_util::display_image_from_path("test/RealisticTestImageMultiBit.png");
 
     let encode_or_decode_choice = Choice::standalone("Encode or Decode?", vec!["Encode", "Decode"]);
@@ -50,16 +68,34 @@ fn encode_menu() {
         }
     });
     let encryption_chooser = ChoiceConstrainedInput::new("Encryption: ", vec!["AES"], |raw, _| Ok(raw.to_string()));
+    let key_chooser = new_key_chooser();
     let selection_algorithm_chooser = Choice::new_with_default("Pixel Selection Algorithm",
-                                                               vec!["Evenly Random Minimal Difference - No Max", "Evenly Random Minimal Difference - Area Average Max"],
+                                                               vec!["Evenly Random Minimal Difference - No Max", "Evenly Random Minimal Difference - Area Average Max", "Evenly Random Minimal Difference - Local Variance Max", "Stratified Minimal Difference - No Max", "Evenly Random Minimal Difference - Color Difference Max (CIEDE2000 2.3)"],
                                                                0);
+    let radius_chooser = Choice::new_with_default("Neighbourhood Radius (area average/local variance)",
+                                                  vec!["Default", "3 pixels", "5 pixels", "10 pixels", "20 pixels"],
+                                                  0);
+    let error_correction_chooser = Choice::new_with_default("Error Correction",
+                                                            vec!["None", "Low - 8 parity bytes per 255", "Medium - 32 parity bytes per 255", "High - 64 parity bytes per 255"],
+                                                            0);
+    let channel_weighting_chooser = Choice::new_with_default("Channel Weighting (color images only)",
+                                                             vec!["Uniform", "Perceptual - less green, more blue"],
+                                                             0);
+    let alpha_chooser = Choice::new_with_default("Alpha Channel (transparent images only)",
+                                                 vec!["Preserve", "Embed where already varied"],
+                                                 0);
     let image_chooser = new_image_chooser("Load Original Image");
     let output_path_chooser = NonExistingPathInput::new_nep("Output Image Path");
 
     Menu::run_root("Encrypt Your Message Into Your Images", vec![
         &message_chooser,
         &encryption_chooser,
+        &key_chooser,
         &selection_algorithm_chooser,
+        &radius_chooser,
+        &error_correction_chooser,
+        &channel_weighting_chooser,
+        &alpha_chooser,
         &image_chooser,
         &output_path_chooser
     ]);
@@ -67,15 +103,15 @@ fn encode_menu() {
     let message = message_chooser.get_value();
     if let Some(message) = message {
         let encryption = encryption_chooser.get_value();
-        let final_message_bytes = match encryption {
-            None => {message.raw().1.raw()}
+        let (final_message_bytes, flags) = match encryption {
+            None => {(message.raw().1.raw(), PayloadFlags::NONE)}
             Some(encryption) => {
                 match encryption.get_0().deref() {
                     "AES" => {
                         let pw_as_string = encryption.get_1();
-                        aes_crt_np_128_encrypt_into_decipherable(&message.raw().1.raw(), pw_as_string)
+                        (aes_crt_np_128_encrypt_into_decipherable(&message.raw().1.raw(), pw_as_string), PayloadFlags::ENCRYPTED)
                     }
-                    _ => message.raw().1.raw() //unknown encryption choice, impossible
+                    _ => (message.raw().1.raw(), PayloadFlags::NONE) //unknown encryption choice, impossible
                 }
             }
         };
@@ -83,13 +119,19 @@ fn encode_menu() {
         let image = image_chooser.get_value();
         if let Some(image) = image {
             let image = image.get_1();
-            if let Some(selection_algorithm) = get_selection_algorithm_from_choice(selection_algorithm_chooser) {
+            if let Some(selection_algorithm) = get_selection_algorithm_from_choice(selection_algorithm_chooser, radius_chooser) {
                 let output_path = output_path_chooser.get_value();
                 if let Some(output_path) = output_path {
                     println!("Encoding final message({:?}),\n    into image({}),\n    and storing in path:\n{}", &final_message_bytes, &image, &output_path);
-                    encode_into_image_into_path(&final_message_bytes, image,
-                                                &selection_algorithm(&final_message_bytes, image).expect("could not select indices to change"),
-                                                                 &output_path).expect("failed to encode");
+                    let mut options = with_error_correction_from_choice(EncodingOptions::default().with_flags(flags), error_correction_chooser);
+                    if let Some(key) = key_chooser.get_value() {
+                        options = options.with_key(*key.get_1());
+                    }
+                    let embed_in_alpha = alpha_chooser.get_value().as_deref() == Some("Embed where already varied");
+                    let channel_weighting = if channel_weighting_chooser.get_value().as_deref() == Some("Perceptual - less green, more blue") { ChannelWeighting::Perceptual } else { ChannelWeighting::Uniform };
+                    print_capacity(image, &selection_algorithm, embed_in_alpha, channel_weighting, &options, final_message_bytes.len());
+                    image.encode_into_path(&final_message_bytes, &options, &selection_algorithm, embed_in_alpha, channel_weighting, &mut rand::thread_rng(), &output_path).expect("failed to encode");
+                    print_encoded_analysis(image, &output_path);
                     // encode_into_image_into_path_at_indices(&final_message_bytes, image, &randomly_select_indices_within(&final_message_bytes, image), &output_path).expect("failed to encode");
                 } else {
                     println!("Missing image - cannot encode message into no image")
@@ -113,12 +155,14 @@ fn decode_menu() {
     let image1_chooser = new_image_chooser("Load Original/Encoded Image");
     let image2_chooser = new_image_chooser("Load Encoded/Original Image");
     let encryption_chooser = ChoiceConstrainedInput::new("Decryption: ", vec!["AES"], |raw, _| Ok(raw.to_string()));
+    let key_chooser = new_key_chooser();
     let decoding_chooser = Choice::new("Decoding: ", vec!["UTF8", "Base64"]);
 
     Menu::run_root("Decrypt Your Message From Images", vec![
         &image1_chooser,
         &image2_chooser,
         &encryption_chooser,
+        &key_chooser,
         &decoding_chooser
     ]);
 
@@ -130,11 +174,18 @@ fn decode_menu() {
         if let Some(image2) = image2 {
             let image2 = image2.get_1();
 
-            let decoded_raw_bytes = decode_into_vec(image2, image1); //order irrelevant
+            let key = key_chooser.get_value().map(|key| *key.get_1());
+            let decoded_raw_bytes = ContainerImage::decode_with_report(key.as_ref(), image2, image1); //order irrelevant
 
             match decoded_raw_bytes {
-                Ok(decoded_raw_bytes) => {
+                Ok((report, decoded_raw_bytes)) => {
+                    if let Some(error_correction) = report.error_correction {
+                        println!("Repaired {} altered bytes, resynchronized after {} altered values (error correction)", error_correction.corrected_symbols, error_correction.resynchronized_values);
+                    }
                     let encryption = encryption_chooser.get_value();
+                    if report.header.flags.contains(PayloadFlags::ENCRYPTED) && encryption.is_none() {
+                        println!("Note: the message is marked as encrypted, but no decryption was selected");
+                    }
                     let final_message_bytes = match encryption {
                         None => { decoded_raw_bytes }
                         Some(encryption) => {
@@ -173,30 +224,213 @@ fn decode_menu() {
 
 
 
-fn new_image_chooser(name: &str) -> ChoiceConstrainedInput<DifCodeImage> {
-    ChoiceConstrainedInput::new(name, vec!["URL", "Path"], |raw, choice| {
+fn new_image_chooser(name: &str) -> ChoiceConstrainedInput<ContainerImage> {
+    ChoiceConstrainedInput::new(name, vec!["URL", "Path", "JPEG Path (coded in the dct coefficients, output has to be .jpg)",
+                                           "Animation Path (gif palettes or all apng frames, output has to be .gif/.png)", "Audio Path (pcm wav, output has to be .wav)",
+                                           "Raw File Path (all bytes, except the headers of bmps and wavs)"], |raw, choice| {
         match choice {
             "Path" => {
-                DifCodeImage::open(raw).map_err(|_| "Failed to load image from path")
+                ContainerImage::open(raw).map_err(|_| "Failed to load image from path")
+            }
+            "JPEG Path (coded in the dct coefficients, output has to be .jpg)" => {
+                ContainerImage::open_as(raw, ContainerFormat::Jpeg).map_err(|_| "Failed to load baseline jpeg from path")
+            }
+            "Animation Path (gif palettes or all apng frames, output has to be .gif/.png)" => {
+                ContainerImage::open_animation(raw).map_err(|_| "Failed to load gif or animated png from path")
+            }
+            "Audio Path (pcm wav, output has to be .wav)" => {
+                ContainerImage::open_as(raw, ContainerFormat::Wav).map_err(|_| "Failed to load 8, 16 or 24 bit pcm wav from path")
+            }
+            "Raw File Path (all bytes, except the headers of bmps and wavs)" => {
+                ContainerImage::open_as(raw, ContainerFormat::Raw).map_err(|_| "Failed to read file from path")
             }
             "URL" => {
-                let img_bytes = reqwest::blocking::get(raw).map_err(|_| "Failed to download image from url")?.bytes().map_err(|_| "Failed to convert downloaded image to bytes")?;
-
-                DifCodeImage::from_memory(&img_bytes).map_err(|_| "Failed to load image from path")
+                download_image(raw)
             }
             _ => Err("Invalid Input for Choice (impossible)")
         }
     })
 }
 
-fn get_selection_algorithm_from_choice(selection_algorithm_chooser: Choice) -> Option<fn(message: &[u8], original: &DifCodeImage) -> DifCodeResult<Vec<u8>>> {
+//shown before encoding, so a message that is too long is explained before the encoding fails
+fn print_capacity(image: &ContainerImage, selection_algorithm: &SelectionAlgorithm, embed_in_alpha: bool, channel_weighting: ChannelWeighting, options: &EncodingOptions, message_length_in_bytes: usize) {
+    match image.capacity_report(selection_algorithm, embed_in_alpha, channel_weighting, options) {
+        Ok(report) => {
+            println!("Capacity: guaranteed {} bytes, expected {} bytes, maximum {} bytes (for the message after encryption, which is {} bytes)",
+                     report.guaranteed_bytes, report.expected_bytes, report.maximum_bytes, message_length_in_bytes);
+            if message_length_in_bytes > report.guaranteed_bytes {
+                println!("The message exceeds the guaranteed capacity, encoding may fail");
+            }
+        }
+        Err(err) => println!("Could not determine capacity({:?})", err)
+    }
+}
+
+//the visible change and the steganalysis scores, only for pixel images (not in the dct coefficients, palettes, samples or bytes)
+fn print_encoded_analysis(original: &ContainerImage, output_path: &str) {
+    if !original.is_pixel_image() {
+        return
+    }
+    let encoded = match ContainerImage::open(output_path) {
+        Ok(encoded) => encoded,
+        Err(err) => return println!("Could not reopen the encoded image({:?})", err)
+    };
+    match ContainerImage::quality_metrics(original, &encoded) {
+        Ok(metrics) => println!("Quality: {}", metrics),
+        Err(err) => println!("Could not measure quality({:?})", err)
+    }
+    match ContainerImage::steganalysis_report(original, &encoded) {
+        Ok(report) => {
+            println!("Steganalysis (original -> encoded): chi-square {:.4} -> {:.4}, rs {:.4} -> {:.4}, sample pairs {:.4} -> {:.4}",
+                     report.original.chi_square, report.encoded.chi_square, report.original.rs, report.encoded.rs, report.original.spa, report.encoded.spa);
+            if report.encoded.is_flagged() {
+                println!("The encoded image would be flagged by a steganalysis detector, consider a shorter message or another selection algorithm");
+            }
+        }
+        Err(err) => println!("Could not analyze({:?})", err)
+    }
+}
+
+/// The ciphertext of the aes (ctr, no padding) encryption is the message plus a constant number of bytes.
+pub fn encryption_overhead_in_bytes() -> usize {
+    aes_crt_np_128_encrypt_into_decipherable(&[], "overhead").len()
+}
+
+fn new_key_chooser() -> ChoiceConstrainedInput<DifCodeKey> {
+    ChoiceConstrainedInput::new("Steganography Key: ", vec!["Password"], |raw, _| Ok(DifCodeKey::from_password(raw)))
+}
+
+pub fn download_image(url: &str) -> Result<ContainerImage, &'static str> {
+    let img_bytes = download(url)?;
+
+    ContainerImage::from_memory(&img_bytes).map_err(|_| "Failed to load image from path")
+}
+pub fn download(url: &str) -> Result<Vec<u8>, &'static str> {
+    let img_bytes = reqwest::blocking::get(url).map_err(|_| "Failed to download image from url")?.bytes().map_err(|_| "Failed to convert downloaded image to bytes")?;
+    Ok(img_bytes.to_vec())
+}
+
+pub enum SelectionAlgorithm {
+    EvenlyRandom,
+    /// radius of the square around each value whose average limits the change (see DEFAULT_AREA_AVERAGE_RADIUS), ignored for audio
+    AreaAverage { radius: u32 },
+    /// radius of the square around each value whose standard deviation limits the change (see DEFAULT_LOCAL_VARIANCE_RADIUS)
+    LocalVariance { radius: u32 },
+    Stratified,
+    /// Bounds the color shift of each pixel (channel_weighting does not apply, the color difference already is perceptual).
+    DeltaE { formula: DeltaEFormula, max_delta_e: f64 }
+}
+impl SelectionAlgorithm {
+    /// Note: the length has to include the encoding overhead (see EncodingOptions::get_embedded_length_in_bits)
+    ///       the rng picks the changes of the last round (the same rng state results in the same map)
+    ///       embed_in_alpha is ignored for images without alpha channel, channel_weighting is only applied to rgb(a) images
+    ///       16 bit images require write_map16
+    pub fn write_map(&self, embedded_length_in_bits: usize, original: &ContainerImage, embed_in_alpha: bool, channel_weighting: ChannelWeighting, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+        match (self, original) {
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Rgb(original)) => write_minimal_evenly_random_image_allowed_changes_map(embedded_length_in_bits, original, 255, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { radius }, ContainerImage::Rgb(original)) => write_minimal_evenly_random_max_area_average_allowed_changes_map(embedded_length_in_bits, original, *radius, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Rgba(original)) => write_minimal_evenly_random_rgba_allowed_changes_map(embedded_length_in_bits, original, 255, embed_in_alpha, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { radius }, ContainerImage::Rgba(original)) => write_minimal_evenly_random_max_area_average_rgba_allowed_changes_map(embedded_length_in_bits, original, *radius, embed_in_alpha, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Luma(original)) => write_minimal_evenly_random_allowed_changes_map_with(embedded_length_in_bits, original, 255, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { radius }, ContainerImage::Luma(original)) => write_minimal_evenly_random_max_area_average_luma_allowed_changes_map(embedded_length_in_bits, original, *radius, rng, output_map),
+            (SelectionAlgorithm::LocalVariance { radius }, ContainerImage::Rgb(original)) => write_minimal_evenly_random_max_local_variance_allowed_changes_map(embedded_length_in_bits, original, *radius, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::LocalVariance { radius }, ContainerImage::Rgba(original)) => write_minimal_evenly_random_max_local_variance_rgba_allowed_changes_map(embedded_length_in_bits, original, *radius, embed_in_alpha, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::LocalVariance { radius }, ContainerImage::Luma(original)) => write_minimal_evenly_random_max_local_variance_luma_allowed_changes_map(embedded_length_in_bits, original, *radius, rng, output_map),
+            (SelectionAlgorithm::Stratified, ContainerImage::Rgb(original)) => write_minimal_stratified_image_allowed_changes_map(embedded_length_in_bits, original, 255, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::Stratified, ContainerImage::Rgba(original)) => write_minimal_stratified_rgba_allowed_changes_map(embedded_length_in_bits, original, 255, embed_in_alpha, channel_weighting, rng, output_map),
+            (SelectionAlgorithm::Stratified, ContainerImage::Luma(original)) => write_minimal_stratified_allowed_changes_map_with(embedded_length_in_bits, original, original.width() as usize, 255, rng, output_map),
+            (SelectionAlgorithm::DeltaE { formula, max_delta_e }, ContainerImage::Rgb(original)) => write_minimal_evenly_random_max_delta_e_allowed_changes_map(embedded_length_in_bits, original, *formula, *max_delta_e, rng, output_map),
+            (SelectionAlgorithm::DeltaE { formula, max_delta_e }, ContainerImage::Rgba(original)) => write_minimal_evenly_random_max_delta_e_rgba_allowed_changes_map(embedded_length_in_bits, original, *formula, *max_delta_e, embed_in_alpha, rng, output_map),
+            (SelectionAlgorithm::DeltaE { formula, max_delta_e }, ContainerImage::Luma(original)) => write_minimal_evenly_random_max_delta_e_luma_allowed_changes_map(embedded_length_in_bits, original, *formula, *max_delta_e, rng, output_map),
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Gif(original)) => write_minimal_evenly_random_allowed_changes_map_with(embedded_length_in_bits, original, 255, rng, output_map),
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Apng(original)) => write_minimal_evenly_random_allowed_changes_map_with(embedded_length_in_bits, original, 255, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { .. }, ContainerImage::Gif(_)) | (SelectionAlgorithm::AreaAverage { .. }, ContainerImage::Apng(_)) =>
+                Err(DifCodeError::Internal("area average selection is not supported for animations")),
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Raw(original)) => write_minimal_evenly_random_raw_file_allowed_changes_map(embedded_length_in_bits, original, 255, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { .. }, ContainerImage::Raw(_)) => Err(DifCodeError::Internal("area average selection is not supported for raw files")),
+            (SelectionAlgorithm::LocalVariance { .. }, ContainerImage::Gif(_)) | (SelectionAlgorithm::LocalVariance { .. }, ContainerImage::Apng(_)) =>
+                Err(DifCodeError::Internal("local variance selection is not supported for animations")),
+            (SelectionAlgorithm::LocalVariance { .. }, ContainerImage::Raw(_)) => Err(DifCodeError::Internal("local variance selection is not supported for raw files")),
+            (SelectionAlgorithm::Stratified, ContainerImage::Gif(_)) | (SelectionAlgorithm::Stratified, ContainerImage::Apng(_)) =>
+                Err(DifCodeError::Internal("stratified selection is not supported for animations")),
+            (SelectionAlgorithm::Stratified, ContainerImage::Raw(_)) => Err(DifCodeError::Internal("stratified selection is not supported for raw files")),
+            (SelectionAlgorithm::DeltaE { .. }, ContainerImage::Gif(_)) | (SelectionAlgorithm::DeltaE { .. }, ContainerImage::Apng(_)) =>
+                Err(DifCodeError::Internal("color difference selection is not supported for animations")),
+            (SelectionAlgorithm::DeltaE { .. }, ContainerImage::Raw(_)) => Err(DifCodeError::Internal("color difference selection is not supported for raw files")),
+            (_, ContainerImage::Rgb16(_)) | (_, ContainerImage::Luma16(_)) => Err(DifCodeError::Internal("16 bit images require a 16 bit allowed changes map")),
+            (_, ContainerImage::Jpeg(_)) => Err(DifCodeError::Internal("jpegs require a jpeg allowed changes map")),
+            (_, ContainerImage::Wav8(_)) | (_, ContainerImage::Wav16(_)) | (_, ContainerImage::Wav24(_)) => Err(DifCodeError::Internal("wavs require a wav allowed changes map"))
+        }
+    }
+    pub fn write_map16(&self, embedded_length_in_bits: usize, original: &ContainerImage, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+        match (self, original) {
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Rgb16(original)) => write_minimal_evenly_random_allowed_changes_map_with(embedded_length_in_bits, original, u16::MAX, rng, output_map),
+            (SelectionAlgorithm::EvenlyRandom, ContainerImage::Luma16(original)) => write_minimal_evenly_random_allowed_changes_map_with(embedded_length_in_bits, original, u16::MAX, rng, output_map),
+            (SelectionAlgorithm::Stratified, ContainerImage::Rgb16(original)) => write_minimal_stratified_allowed_changes_map_with(embedded_length_in_bits, original, original.width() as usize, u16::MAX, rng, output_map),
+            (SelectionAlgorithm::Stratified, ContainerImage::Luma16(original)) => write_minimal_stratified_allowed_changes_map_with(embedded_length_in_bits, original, original.width() as usize, u16::MAX, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { radius }, ContainerImage::Rgb16(original)) => write_minimal_evenly_random_max_area_average_image16_allowed_changes_map(embedded_length_in_bits, original, *radius, rng, output_map),
+            (SelectionAlgorithm::AreaAverage { radius }, ContainerImage::Luma16(original)) => write_minimal_evenly_random_max_area_average_luma16_allowed_changes_map(embedded_length_in_bits, original, *radius, rng, output_map),
+            (SelectionAlgorithm::LocalVariance { radius }, ContainerImage::Rgb16(original)) => write_minimal_evenly_random_max_local_variance_image16_allowed_changes_map(embedded_length_in_bits, original, *radius, rng, output_map),
+            (SelectionAlgorithm::LocalVariance { radius }, ContainerImage::Luma16(original)) => write_minimal_evenly_random_max_local_variance_luma16_allowed_changes_map(embedded_length_in_bits, original, *radius, rng, output_map),
+            (SelectionAlgorithm::DeltaE { formula, max_delta_e }, ContainerImage::Rgb16(original)) => write_minimal_evenly_random_max_delta_e_image16_allowed_changes_map(embedded_length_in_bits, original, *formula, *max_delta_e, rng, output_map),
+            (SelectionAlgorithm::DeltaE { formula, max_delta_e }, ContainerImage::Luma16(original)) => write_minimal_evenly_random_max_delta_e_luma16_allowed_changes_map(embedded_length_in_bits, original, *formula, *max_delta_e, rng, output_map),
+            _ => Err(DifCodeError::Internal("only 16 bit images have a 16 bit image allowed changes map"))
+        }
+    }
+    pub fn write_jpeg_map(&self, embedded_length_in_bits: usize, original: &DifCodeJpeg, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<u16>) -> DifCodeResult<()> {
+        match self {
+            SelectionAlgorithm::EvenlyRandom => write_minimal_evenly_random_jpeg_allowed_changes_map(embedded_length_in_bits, original, JPEG_DEFAULT_MAX_DIFFERENCE, rng, output_map),
+            SelectionAlgorithm::AreaAverage { .. } => Err(DifCodeError::Internal("area average selection is not supported for jpeg coefficients")),
+            SelectionAlgorithm::LocalVariance { .. } => Err(DifCodeError::Internal("local variance selection is not supported for jpeg coefficients")),
+            SelectionAlgorithm::Stratified => Err(DifCodeError::Internal("stratified selection is not supported for jpeg coefficients")),
+            SelectionAlgorithm::DeltaE { .. } => Err(DifCodeError::Internal("color difference selection is not supported for jpeg coefficients"))
+        }
+    }
+    /// For audio the area average selection limits the changes by the local amplitude instead.
+    pub fn write_wav_map<T: Sample>(&self, embedded_length_in_bits: usize, original: &DifCodeWav<T>, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer<T>) -> DifCodeResult<()> {
+        match self {
+            SelectionAlgorithm::EvenlyRandom => write_minimal_evenly_random_allowed_changes_map_with(embedded_length_in_bits, original, T::MAX, rng, output_map),
+            SelectionAlgorithm::AreaAverage { .. } => write_minimal_evenly_random_max_amplitude_allowed_changes_map(embedded_length_in_bits, original, rng, output_map),
+            SelectionAlgorithm::LocalVariance { .. } => Err(DifCodeError::Internal("local variance selection is not supported for audio")),
+            SelectionAlgorithm::Stratified => Err(DifCodeError::Internal("stratified selection is not supported for audio")),
+            SelectionAlgorithm::DeltaE { .. } => Err(DifCodeError::Internal("color difference selection is not supported for audio"))
+        }
+    }
+}
+
+fn get_selection_algorithm_from_choice(selection_algorithm_chooser: Choice, radius_chooser: Choice) -> Option<SelectionAlgorithm> {
+    let radius = match radius_chooser.get_value().as_deref() {
+        Some("3 pixels") => Some(3),
+        Some("5 pixels") => Some(5),
+        Some("10 pixels") => Some(10),
+        Some("20 pixels") => Some(20),
+        Some(_) | None => None
+    };
     match selection_algorithm_chooser.get_value().as_deref() {
         Some("Evenly Random Minimal Difference - No Max") => {
-            Some(create_minimal_evenly_random_allowed_changes_map_for_image)
+            Some(SelectionAlgorithm::EvenlyRandom)
         }
         Some("Evenly Random Minimal Difference - Area Average Max") => {
-            Some(create_minimal_evenly_random_max_area_average_allowed_changes_map)
+            Some(SelectionAlgorithm::AreaAverage { radius: radius.unwrap_or(DEFAULT_AREA_AVERAGE_RADIUS) })
+        }
+        Some("Evenly Random Minimal Difference - Local Variance Max") => {
+            Some(SelectionAlgorithm::LocalVariance { radius: radius.unwrap_or(DEFAULT_LOCAL_VARIANCE_RADIUS) })
+        }
+        Some("Stratified Minimal Difference - No Max") => {
+            Some(SelectionAlgorithm::Stratified)
+        }
+        Some("Evenly Random Minimal Difference - Color Difference Max (CIEDE2000 2.3)") => {
+            Some(SelectionAlgorithm::DeltaE { formula: DeltaEFormula::Ciede2000, max_delta_e: DEFAULT_MAX_DELTA_E })
         }
         Some(_) | None => None
     }
-}
\ No newline at end of file
+}
+
+fn with_error_correction_from_choice(options: EncodingOptions, error_correction_chooser: Choice) -> EncodingOptions {
+    match error_correction_chooser.get_value().as_deref() {
+        Some("Low - 8 parity bytes per 255") => options.with_error_correction(8),
+        Some("Medium - 32 parity bytes per 255") => options.with_error_correction(32),
+        Some("High - 64 parity bytes per 255") => options.with_error_correction(64),
+        Some(_) | None => options
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::parallel;
+use crate::util::{DifCodeError, DifCodeImage, DifCodeResult, EncodingContainer, Sample};
+
+//Full reference quality metrics of an encoded image, i.e. how much (and how visibly) it differs from its original.
+//All metrics are taken over every channel value in the bit depth of the container, so the peak value is T::MAX.
+
+//side length of the (uniformly weighted) square windows the structural similarity is computed in
+const SSIM_WINDOW_LENGTH: usize = 7;
+
+/// How much an encoded image differs from its original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityMetrics {
+    /// mean squared error of all channel values
+    pub mse: f64,
+    /// peak signal to noise ratio in dB, infinite for identical images (above ~40 dB a change is generally not visible)
+    pub psnr: f64,
+    /// mean structural similarity (SSIM) of all windows of all channels, 1 for identical images
+    pub ssim: f64,
+    /// the largest change of any channel value
+    pub max_absolute_change: u32,
+}
+
+impl fmt::Display for QualityMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "psnr {:.2} dB, ssim {:.5}, mse {:.5}, max absolute change {}", self.psnr, self.ssim, self.mse, self.max_absolute_change)
+    }
+}
+
+/// The quality metrics of the encoded image compared to its original (both of the same size).
+pub fn get_quality_metrics(original: &DifCodeImage, encoded: &DifCodeImage) -> DifCodeResult<QualityMetrics> {
+    if original.width() != encoded.width() || original.height() != encoded.height() {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+    get_quality_metrics_of_planes(original, encoded, original.width(), original.height())
+}
+
+/// The quality metrics of any pair of containers that are laid out like the image containers,
+///    i.e. in one plane of width*height values per channel (so rgba, grayscale and 16 bit images as well).
+pub fn get_quality_metrics_of_planes<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, width: u32, height: u32) -> DifCodeResult<QualityMetrics> {
+    let plane_length = width as usize * height as usize;
+    if original.len() != encoded.len() || plane_length == 0 || original.len() % plane_length != 0 {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+    let peak = T::MAX.to_u32() as f64;
+
+    let (squared_error_sum, max_absolute_change) = parallel::map_ranges(original.len(), |range| {
+        range.fold((0u128, 0u32), |(sum, max), i| {
+            let change = original[i].distance(encoded[i]).to_u32();
+            (sum + change as u128 * change as u128, max.max(change))
+        })
+    }).into_iter().fold((0u128, 0u32), |(sum, max), (range_sum, range_max)| (sum + range_sum, max.max(range_max)));
+    let mse = squared_error_sum as f64 / original.len() as f64;
+    let psnr = if squared_error_sum == 0 { f64::INFINITY } else { 10.0 * (peak * peak / mse).log10() };
+
+    let num_channels = original.len() / plane_length;
+    let ssim = (0..num_channels)
+        .map(|z| mean_ssim_of_plane(original, encoded, z * plane_length, width as usize, height as usize, peak))
+        .sum::<f64>() / num_channels as f64;
+
+    Ok(QualityMetrics { mse, psnr, ssim, max_absolute_change })
+}
+
+//the window is clipped to images smaller than it, every position at which it fits entirely is weighted equally
+//the sums of (o, e, o^2, e^2, o*e) per column of the window are updated while it moves down, the window sums while it moves right
+fn mean_ssim_of_plane<T: Sample>(original: &dyn EncodingContainer<T>, encoded: &dyn EncodingContainer<T>, offset: usize, width: usize, height: usize, peak: f64) -> f64 {
+    let (window_width, window_height) = (width.min(SSIM_WINDOW_LENGTH), height.min(SSIM_WINDOW_LENGTH));
+    let num_window_columns = width - window_width + 1;
+    let num_window_rows = height - window_height + 1;
+    let (c1, c2) = ((0.01 * peak) * (0.01 * peak), (0.03 * peak) * (0.03 * peak));
+
+    let statistics_at = |x: usize, y: usize| {
+        let (o, e) = (original[offset + y * width + x].to_u32() as u64, encoded[offset + y * width + x].to_u32() as u64);
+        [o, e, o * o, e * e, o * e]
+    };
+    let add_row = |column_sums: &mut [[u64; 5]], y: usize| {
+        for (x, sums) in column_sums.iter_mut().enumerate() {
+            sums.iter_mut().zip(statistics_at(x, y).iter()).for_each(|(sum, statistic)| *sum += statistic);
+        }
+    };
+    let remove_row = |column_sums: &mut [[u64; 5]], y: usize| {
+        for (x, sums) in column_sums.iter_mut().enumerate() {
+            sums.iter_mut().zip(statistics_at(x, y).iter()).for_each(|(sum, statistic)| *sum -= statistic);
+        }
+    };
+
+    let mut column_sums = vec![[0u64; 5]; width];
+    (0..window_height).for_each(|y| add_row(&mut column_sums, y));
+    let mut ssim_sum = 0.0;
+    for top in 0..num_window_rows {
+        if top > 0 {
+            remove_row(&mut column_sums, top - 1);
+            add_row(&mut column_sums, top + window_height - 1);
+        }
+        let mut window_sums = [0u64; 5];
+        column_sums[..window_width].iter().for_each(|sums| (0..5).for_each(|i| window_sums[i] += sums[i]));
+        for left in 0..num_window_columns {
+            if left > 0 {
+                (0..5).for_each(|i| window_sums[i] = window_sums[i] + column_sums[left + window_width - 1][i] - column_sums[left - 1][i]);
+            }
+            ssim_sum += ssim_of_window(window_sums, (window_width * window_height) as u64, c1, c2);
+        }
+    }
+    ssim_sum / (num_window_rows * num_window_columns) as f64
+}
+
+//as in Wang, Bovik, Sheikh, Simoncelli: Image Quality Assessment: From Error Visibility to Structural Similarity (2004)
+//the (co)variances are computed n^2 times too large, so they are exact integers up to the division
+fn ssim_of_window([o, e, oo, ee, oe]: [u64; 5], n: u64, c1: f64, c2: f64) -> f64 {
+    let (o, e, oo, ee, oe, n) = (o as i128, e as i128, oo as i128, ee as i128, oe as i128, n as i128);
+    let n_squared = (n * n) as f64;
+    let (mean_o, mean_e) = (o as f64 / n as f64, e as f64 / n as f64);
+    let variance_o = (n * oo - o * o) as f64 / n_squared;
+    let variance_e = (n * ee - e * e) as f64 / n_squared;
+    let covariance = (n * oe - o * e) as f64 / n_squared;
+    ((2.0 * mean_o * mean_e + c1) * (2.0 * covariance + c2)) / ((mean_o * mean_o + mean_e * mean_e + c1) * (variance_o + variance_e + c2))
+}
@@ -0,0 +1,39 @@
+use std::ops::Range;
+use std::panic;
+use std::thread;
+
+//Splits work over indices into consecutive ranges that are processed on scoped threads (the work borrows the containers).
+//The results are always combined in index order, so the parallel paths are bit identical to processing the indices one after another.
+
+//below this many indices per thread, spawning the thread costs more than it saves
+const MIN_INDICES_PER_THREAD: usize = 1 << 15;
+
+pub(crate) fn num_threads_for(len: usize) -> usize {
+    let available = thread::available_parallelism().map(|num| num.get()).unwrap_or(1);
+    available.min(len / MIN_INDICES_PER_THREAD).max(1)
+}
+
+/// The consecutive ranges 0..len is split into, at most one per thread.
+pub(crate) fn split_range(len: usize) -> Vec<Range<usize>> {
+    let num_threads = num_threads_for(len);
+    let chunk_length = len.div_ceil(num_threads).max(1);
+    (0..len).step_by(chunk_length).map(|start| start..(start + chunk_length).min(len)).collect()
+}
+
+/// Runs f for each of the ranges of split_range(len) (in parallel) and returns the results in the order of the ranges.
+pub(crate) fn map_ranges<R: Send, F: Fn(Range<usize>) -> R + Sync>(len: usize, f: F) -> Vec<R> {
+    let ranges = split_range(len);
+    if ranges.len() <= 1 {
+        return ranges.into_iter().map(f).collect()
+    }
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = ranges.into_iter().map(|range| scope.spawn(move || f(range))).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap_or_else(|err| panic::resume_unwind(err))).collect()
+    })
+}
+
+/// The sum of f over 0..len.
+pub(crate) fn sum_indices<F: Fn(usize) -> usize + Sync>(len: usize, f: F) -> usize {
+    map_ranges(len, |range| range.map(&f).sum::<usize>()).into_iter().sum()
+}
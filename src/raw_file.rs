@@ -0,0 +1,209 @@
+use std::fs;
+use std::ops::{Index, IndexMut, Range};
+use std::path::Path;
+
+use crate::util::{DifCodeResult, EncodingContainer};
+use crate::difference_encoder::multi_bit::encode_into_container_with_options;
+use crate::difference_encoder::options::EncodingOptions;
+use crate::difference_encoder::max_change_map_creator::{create_allowed_changes_map_for_message, write_minimal_evenly_random_allowed_changes_map};
+use crate::rand::RngCore;
+use crate::audio::{is_wav, get_wav_data_range};
+
+//Any file as a container of its bytes, for formats without a dedicated container (e.g. uncompressed bitmaps, raw pcm or sensor dumps).
+//Bytes that have to stay as they are (headers, checksums, padding, ...) are protected, the raw file map creator never allows a change to them.
+//    For known formats the structural bytes are protected automatically, everything else has to be declared.
+
+const BMP_FILE_HEADER_LENGTH: usize = 14;
+const BMP_INFO_HEADER_LENGTH: usize = 40;
+const BMP_RGB: u32 = 0;
+const BMP_BITFIELDS: u32 = 3;
+
+/// The byte ranges of the structure of a file that are never changed, if the file is of a known format:
+///    - uncompressed 24/32 bit bitmaps: everything but the pixel data (i.e. headers, row padding and anything after the pixel data),
+///      8 bit and smaller bitmaps: everything but the colors of the palette (pixel indices and reserved palette bytes included)
+///    - wavs: everything but the payload of the data chunk
+///
+/// Bitmaps and wavs that cannot be parsed (or other bitmap variants, e.g. compressed) are protected entirely, unknown formats not at all.
+pub fn get_format_protected_ranges(buffer: &[u8]) -> Vec<Range<usize>> {
+    if buffer.starts_with(b"BM") {
+        get_bmp_protected_ranges(buffer).unwrap_or_else(|| vec![Range { start: 0, end: buffer.len() }])
+    } else if is_wav(buffer) {
+        match get_wav_data_range(buffer) {
+            Ok(data) => vec![0..data.start, data.end..buffer.len()],
+            Err(_) => vec![Range { start: 0, end: buffer.len() }]
+        }
+    } else {
+        Vec::new()
+    }
+}
+
+fn get_bmp_protected_ranges(buffer: &[u8]) -> Option<Vec<Range<usize>>> {
+    if buffer.len() < BMP_FILE_HEADER_LENGTH + BMP_INFO_HEADER_LENGTH {
+        return None
+    }
+    let read_u32 = |pos: usize| u32::from_le_bytes([buffer[pos], buffer[pos + 1], buffer[pos + 2], buffer[pos + 3]]);
+    let pixel_data_offset = read_u32(10) as usize;
+    let info_header_length = read_u32(14) as usize;
+    //older (core) headers have a different layout
+    if info_header_length < BMP_INFO_HEADER_LENGTH {
+        return None
+    }
+    let width = read_u32(18) as i32;
+    let height = read_u32(22) as i32;
+    let bits_per_pixel = u16::from_le_bytes([buffer[28], buffer[29]]) as usize;
+    let compression = read_u32(30);
+    if width <= 0 || height == 0 || pixel_data_offset > buffer.len() {
+        return None
+    }
+
+    let row_length = (bits_per_pixel * width as usize).div_ceil(32) * 4;
+    let pixel_data_end = pixel_data_offset.checked_add(row_length.checked_mul(height.unsigned_abs() as usize)?)?;
+    if pixel_data_end > buffer.len() {
+        return None
+    }
+    match (bits_per_pixel, compression) {
+        (24, BMP_RGB) | (32, BMP_RGB) | (32, BMP_BITFIELDS) => {
+            let data_length = bits_per_pixel / 8 * width as usize;
+            let mut protected_ranges = vec![Range { start: 0, end: pixel_data_offset }];
+            for row_start in (pixel_data_offset..pixel_data_end).step_by(row_length) {
+                protected_ranges.push(row_start + data_length..row_start + row_length);
+            }
+            protected_ranges.push(pixel_data_end..buffer.len());
+            Some(protected_ranges)
+        }
+        (1, BMP_RGB) | (4, BMP_RGB) | (8, BMP_RGB) => {
+            //the palette entries (blue, green, red, reserved) follow the info header
+            let palette_start = BMP_FILE_HEADER_LENGTH + info_header_length;
+            let palette_end = palette_start + pixel_data_offset.checked_sub(palette_start)? / 4 * 4;
+            let mut protected_ranges = vec![Range { start: 0, end: palette_start }];
+            for entry_start in (palette_start..palette_end).step_by(4) {
+                protected_ranges.push(entry_start + 3..entry_start + 4);
+            }
+            protected_ranges.push(palette_end..buffer.len());
+            Some(protected_ranges)
+        }
+        _ => None
+    }
+}
+
+/// An arbitrary file as an encoding container of all of its bytes, with byte ranges that are protected from changes.
+/// Only the raw file map creators respect the protection (see create_minimal_evenly_random_raw_file_allowed_changes_map),
+///    the file itself is written back byte for byte with only the encoded changes.
+#[derive(Clone)]
+pub struct DifCodeRawFile {
+    bytes: Vec<u8>,
+    //sorted, disjoint and not adjacent
+    protected_ranges: Vec<Range<usize>>,
+}
+impl DifCodeRawFile {
+    /// The bytes of the file without any protection.
+    pub fn from_bytes(bytes: Vec<u8>) -> DifCodeRawFile {
+        DifCodeRawFile { bytes, protected_ranges: Vec::new() }
+    }
+    /// The bytes of the file, protecting the structure of known formats (see get_format_protected_ranges).
+    pub fn from_memory(buffer: &[u8]) -> DifCodeRawFile {
+        let mut file = DifCodeRawFile::from_bytes(buffer.to_vec());
+        for range in get_format_protected_ranges(buffer) {
+            file.protect(range);
+        }
+        file
+    }
+    pub fn open(path: &str) -> DifCodeResult<DifCodeRawFile> {
+        Ok(DifCodeRawFile::from_memory(&fs::read(path)?))
+    }
+    pub fn save(&self, path: &str) -> DifCodeResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> DifCodeResult<()> {
+        fs::write(path, &self.bytes)?;
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> &[u8] { &self.bytes }
+    pub fn to_bytes(&self) -> Vec<u8> { self.bytes.clone() }
+    pub fn protected_ranges(&self) -> &[Range<usize>] { &self.protected_ranges }
+    pub fn num_protected_bytes(&self) -> usize {
+        self.protected_ranges.iter().map(|range| range.len()).sum()
+    }
+
+    pub fn with_protected_range(mut self, range: Range<usize>) -> DifCodeRawFile {
+        self.protect(range);
+        self
+    }
+    /// Protects the bytes in range (cut off at the end of the file), overlapping or adjacent ranges are merged.
+    pub fn protect(&mut self, range: Range<usize>) {
+        let mut merged = range.start.min(self.bytes.len())..range.end.min(self.bytes.len());
+        if merged.is_empty() {
+            return
+        }
+        self.protected_ranges.retain(|protected| {
+            let touches = protected.start <= merged.end && merged.start <= protected.end;
+            if touches {
+                merged = merged.start.min(protected.start)..merged.end.max(protected.end);
+            }
+            !touches
+        });
+        let position = self.protected_ranges.partition_point(|protected| protected.start < merged.start);
+        self.protected_ranges.insert(position, merged);
+    }
+    pub fn is_protected(&self, index: usize) -> bool {
+        let position = self.protected_ranges.partition_point(|protected| protected.end <= index);
+        position < self.protected_ranges.len() && self.protected_ranges[position].start <= index
+    }
+}
+
+impl std::fmt::Debug for DifCodeRawFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DifCodeRawFile({} bytes, protected: {:?})", self.bytes.len(), self.protected_ranges)
+    }
+}
+impl std::fmt::Display for DifCodeRawFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Raw File({} bytes, {} protected)", self.bytes.len(), self.num_protected_bytes())
+    }
+}
+impl PartialEq<DifCodeRawFile> for DifCodeRawFile {
+    fn eq(&self, other: &DifCodeRawFile) -> bool {
+        self.bytes == other.bytes
+    }
+}
+impl IndexMut<usize> for DifCodeRawFile {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        &mut self.bytes[index]
+    }
+}
+impl Index<usize> for DifCodeRawFile {
+    type Output = u8;
+    fn index(&self, index: usize) -> &u8 {
+        &self.bytes[index]
+    }
+}
+impl EncodingContainer for DifCodeRawFile {
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+/// The encoded file (see encode_into_container) only has changed bytes, the protected ranges are only unchanged if the allowed_changes_map respects them.
+pub fn encode_into_raw_file_into_path(message_bytes: &[u8], original: &DifCodeRawFile, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_raw_file_into_path_with_options(message_bytes, &EncodingOptions::default(), original, allowed_changes_map, path)
+}
+pub fn encode_into_raw_file_into_path_with_options(message_bytes: &[u8], options: &EncodingOptions, original: &DifCodeRawFile, allowed_changes_map: &dyn EncodingContainer, path: &str) -> DifCodeResult<()> {
+    encode_into_container_with_options(message_bytes, options, original, allowed_changes_map)?.save(path)
+}
+
+//protected bytes (see DifCodeRawFile) are never changed, all others like in the evenly random map
+pub fn create_minimal_evenly_random_raw_file_allowed_changes_map(message: &[u8], original: &DifCodeRawFile, max_difference: u8) -> DifCodeResult<Vec<u8>> {
+    create_allowed_changes_map_for_message(message, original, |length, rng, output_map| write_minimal_evenly_random_raw_file_allowed_changes_map(length, original, max_difference, rng, output_map))
+}
+pub fn write_minimal_evenly_random_raw_file_allowed_changes_map(message_length_in_bits: usize, original: &DifCodeRawFile, max_difference: u8, rng: &mut dyn RngCore, output_map: &mut dyn EncodingContainer) -> DifCodeResult<()> {
+    let change_constraint_calculator = move |index, ov| {
+        if original.is_protected(index) {
+            (0, true)
+        } else {
+            (max_difference, ov <= 255 / 2)
+        }
+    };
+
+    write_minimal_evenly_random_allowed_changes_map(message_length_in_bits, original, change_constraint_calculator, rng, output_map)
+}
@@ -0,0 +1,256 @@
+use std::ops::Range;
+
+use crate::parallel;
+use crate::util::{DifCodeError, DifCodeImage, DifCodeResult, EncodingContainer};
+
+//Classic blind detectors of lsb replacement in 8 bit images, to check whether an encoded image would be flagged before it is sent.
+//The difference code changes values by more than their lsb, so the detectors are not tailored to it,
+//    but any embedding that evens out pairs of values (2k, 2k+1) or the structure of neighbouring values raises their scores.
+//All of them look at every channel (plane) of the container, rows are the width*height values of a plane in order.
+
+//the usual thresholds above which a detector flags an image
+const CHI_SQUARE_FLAGGING_PROBABILITY: f64 = 0.5;
+const ESTIMATE_FLAGGING_FRACTION: f64 = 0.05;
+
+/// The scores of the detectors for one image, all between 0 and 1 (higher means more likely to be flagged).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SteganalysisScores {
+    /// the probability that the histogram comes from an image with lsb replacement (Westfeld and Pfitzmann),
+    ///    natural images are usually close to 0
+    pub chi_square: f64,
+    /// the estimated fraction of values that carry a message in their lsb (RS analysis, Fridrich, Goljan and Du),
+    ///    natural images are usually estimated below ~0.05
+    pub rs: f64,
+    /// the estimated fraction of values that carry a message in their lsb (sample pair analysis, Dumitrescu, Wu and Wang)
+    pub spa: f64,
+}
+impl SteganalysisScores {
+    /// Whether any detector would flag the image, i.e. a chi square probability above 0.5 or an estimated message in more than 5% of the values.
+    pub fn is_flagged(&self) -> bool {
+        self.chi_square > CHI_SQUARE_FLAGGING_PROBABILITY || self.rs > ESTIMATE_FLAGGING_FRACTION || self.spa > ESTIMATE_FLAGGING_FRACTION
+    }
+}
+
+/// The scores of an original and of an image encoded from it, to compare the settings of the encoding by how much they raise the scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SteganalysisReport {
+    pub original: SteganalysisScores,
+    pub encoded: SteganalysisScores,
+}
+impl SteganalysisReport {
+    /// How much each score rose by the encoding (negative if it fell).
+    pub fn increase(&self) -> SteganalysisScores {
+        SteganalysisScores {
+            chi_square: self.encoded.chi_square - self.original.chi_square,
+            rs: self.encoded.rs - self.original.rs,
+            spa: self.encoded.spa - self.original.spa,
+        }
+    }
+}
+
+pub fn get_steganalysis_scores(image: &DifCodeImage) -> DifCodeResult<SteganalysisScores> {
+    get_steganalysis_scores_of_planes(image, image.width(), image.height())
+}
+/// The scores of any 8 bit container that is laid out like the image containers,
+///    i.e. in one plane of width*height values per channel (leave out constant planes like the alpha of an opaque image, they skew rs analysis).
+pub fn get_steganalysis_scores_of_planes(container: &dyn EncodingContainer, width: u32, height: u32) -> DifCodeResult<SteganalysisScores> {
+    let plane_length = width as usize * height as usize;
+    if plane_length == 0 || container.len() % plane_length != 0 {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+    Ok(SteganalysisScores {
+        chi_square: chi_square_attack(container),
+        rs: rs_analysis(container, width as usize),
+        spa: sample_pair_analysis(container, width as usize),
+    })
+}
+
+pub fn get_steganalysis_report(original: &DifCodeImage, encoded: &DifCodeImage) -> DifCodeResult<SteganalysisReport> {
+    if original.width() != encoded.width() || original.height() != encoded.height() {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+    get_steganalysis_report_of_planes(original, encoded, original.width(), original.height())
+}
+pub fn get_steganalysis_report_of_planes(original: &dyn EncodingContainer, encoded: &dyn EncodingContainer, width: u32, height: u32) -> DifCodeResult<SteganalysisReport> {
+    if original.len() != encoded.len() {
+        return Err(DifCodeError::InternalMismatchedContainerSizes)
+    }
+    Ok(SteganalysisReport {
+        original: get_steganalysis_scores_of_planes(original, width, height)?,
+        encoded: get_steganalysis_scores_of_planes(encoded, width, height)?,
+    })
+}
+
+/// Lsb replacement evens out the number of values 2k and 2k+1, the chi square test measures how close the histogram is to that.
+///     The probability of embedding over all values of the container (sparse pairs with an expected count below 5 are left out).
+pub fn chi_square_attack(container: &dyn EncodingContainer) -> f64 {
+    let histogram = parallel::map_ranges(container.len(), |range| {
+        let mut histogram = [0usize; 256];
+        range.for_each(|i| histogram[container[i] as usize] += 1);
+        histogram
+    }).into_iter().fold([0usize; 256], |mut histogram, range_histogram| {
+        histogram.iter_mut().zip(range_histogram.iter()).for_each(|(count, range_count)| *count += range_count);
+        histogram
+    });
+
+    let (mut chi_square, mut num_categories) = (0.0, 0usize);
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected >= 5.0 {
+            chi_square += (pair[0] as f64 - expected) * (pair[0] as f64 - expected) / expected;
+            num_categories += 1;
+        }
+    }
+    if num_categories < 2 {
+        return 0.0
+    }
+    regularized_upper_gamma((num_categories - 1) as f64 / 2.0, chi_square / 2.0)
+}
+
+/// RS analysis on groups of 4 horizontally neighbouring values: lsb replacement makes the groups that are smoothed
+///     by flipping the lsbs (F1: 2k <-> 2k+1) as frequent as the groups roughened by it, but not for the shifted flipping (F-1: 2k-1 <-> 2k).
+///     The estimated fraction of values with a message in their lsb (0 if the image is too small).
+pub fn rs_analysis(container: &dyn EncodingContainer, width: usize) -> f64 {
+    const MASK: [bool; 4] = [false, true, true, false];
+    let groups_per_row = width / 4;
+    let num_groups = container.len().checked_div(width).unwrap_or(0) * groups_per_row;
+    if num_groups == 0 {
+        return 0.0
+    }
+
+    //per flip of all lsbs beforehand (no, yes): the (regular, singular) counts of F1 and F-1 applied with the mask
+    let counts = parallel::map_ranges(num_groups, |groups: Range<usize>| {
+        let mut counts = [[0i64; 4]; 2];
+        for group in groups {
+            let start = group / groups_per_row * width + group % groups_per_row * 4;
+            let values: [i32; 4] = std::array::from_fn(|j| container[start + j] as i32);
+            for (flipped, counts) in counts.iter_mut().enumerate() {
+                let group = values.map(|v| if flipped == 1 { v ^ 1 } else { v });
+                let roughness = discrimination(&group);
+                let positive = discrimination(&std::array::from_fn(|j| if MASK[j] { group[j] ^ 1 } else { group[j] }));
+                let negative = discrimination(&std::array::from_fn(|j| if MASK[j] { ((group[j] + 1) ^ 1) - 1 } else { group[j] }));
+                counts[0] += (positive > roughness) as i64;
+                counts[1] += (positive < roughness) as i64;
+                counts[2] += (negative > roughness) as i64;
+                counts[3] += (negative < roughness) as i64;
+            }
+        }
+        counts
+    }).into_iter().fold([[0i64; 4]; 2], |mut counts, range_counts| {
+        for (counts, range_counts) in counts.iter_mut().zip(range_counts.iter()) {
+            counts.iter_mut().zip(range_counts.iter()).for_each(|(count, range_count)| *count += range_count);
+        }
+        counts
+    });
+
+    //R-S of F1 and F-1 at the embedded fraction p/2 (the image) and 1-p/2 (all lsbs flipped), as in Fridrich, Goljan, Du: Reliable Detection of LSB Steganography (2001)
+    let (d0, d1) = ((counts[0][0] - counts[0][1]) as f64, (counts[1][0] - counts[1][1]) as f64);
+    let (negative_d0, negative_d1) = ((counts[0][2] - counts[0][3]) as f64, (counts[1][2] - counts[1][3]) as f64);
+    let x = smaller_root(2.0 * (d1 + d0), negative_d0 - negative_d1 - d1 - 3.0 * d0, d0 - negative_d0);
+    clamp_fraction(x / (x - 0.5))
+}
+
+//how rough a group is, flipping lsbs mostly raises it in natural images
+fn discrimination(group: &[i32; 4]) -> i32 {
+    group.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum()
+}
+
+/// Sample pair analysis on horizontally neighbouring values (u, v): in natural images pairs that differ by an odd amount
+///     are as often of the kind (even, odd) as of (odd, even), lsb replacement moves pairs between the kinds at a known rate.
+///     The estimated fraction of values with a message in their lsb (0 if there are no pairs that only differ in their lsb).
+pub fn sample_pair_analysis(container: &dyn EncodingContainer, width: usize) -> f64 {
+    if width == 0 {
+        return 0.0
+    }
+    //the pairs in the same lsb pair (u/2 == v/2), the equal pairs and the balance of the kinds of the other odd pairs
+    let (same_pair, equal, balance) = parallel::map_ranges(container.len(), |range| {
+        let (mut same_pair, mut equal, mut balance) = (0i64, 0i64, 0i64);
+        for i in range {
+            if i % width == width - 1 {
+                continue
+            }
+            let (u, v) = (container[i] as i64, container[i + 1] as i64);
+            let trace = (v >> 1) - (u >> 1);
+            if trace == 0 {
+                same_pair += 1;
+                equal += (u == v) as i64;
+            } else if (u ^ v) & 1 == 1 {
+                //(even, odd) pairs count towards their trace, (odd, even) pairs against it, mirrored for negative traces
+                balance += trace.signum() * if u & 1 == 0 { 1 } else { -1 };
+            }
+        }
+        (same_pair, equal, balance)
+    }).into_iter().fold((0i64, 0i64, 0i64), |(a, b, c), (range_a, range_b, range_c)| (a + range_a, b + range_b, c + range_c));
+    if same_pair == 0 {
+        return 0.0
+    }
+
+    //with q = p/2 of the lsbs flipped the expected counts satisfy 2*same*q^2 - 2*(same + balance)*q + same - equal + balance = 0
+    let (same_pair, equal, balance) = (same_pair as f64, equal as f64, balance as f64);
+    let q = smaller_root(2.0 * same_pair, -2.0 * (same_pair + balance), same_pair - equal + balance);
+    clamp_fraction(2.0 * q)
+}
+
+//the real root of a*x^2 + b*x + c with the smaller magnitude (the vertex if there is none)
+fn smaller_root(a: f64, b: f64, c: f64) -> f64 {
+    if a == 0.0 {
+        return if b == 0.0 { 0.0 } else { -c / b }
+    }
+    let root_of_discriminant = (b * b - 4.0 * a * c).max(0.0).sqrt();
+    let (first, second) = ((-b + root_of_discriminant) / (2.0 * a), (-b - root_of_discriminant) / (2.0 * a));
+    if first.abs() <= second.abs() { first } else { second }
+}
+
+fn clamp_fraction(fraction: f64) -> f64 {
+    if fraction.is_nan() { 0.0 } else { fraction.clamp(0.0, 1.0) }
+}
+
+//Q(a, x) = Γ(a, x) / Γ(a), i.e. 1 - the cdf of the chi square distribution with 2a degrees of freedom at 2x
+//a series below a + 1, a continued fraction above (as in Numerical Recipes)
+fn regularized_upper_gamma(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-14;
+    if x <= 0.0 {
+        return 1.0
+    }
+    let log_prefactor = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        while term.abs() > sum.abs() * EPSILON {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+        }
+        (1.0 - sum * log_prefactor.exp()).max(0.0)
+    } else {
+        //modified lentz
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let (mut c, mut d) = (1.0 / tiny, 1.0 / b);
+        let mut fraction = d;
+        for i in 1..10000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < tiny { tiny } else { d };
+            c = b + an / c;
+            c = if c.abs() < tiny { tiny } else { c };
+            d = 1.0 / d;
+            let delta = d * c;
+            fraction *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break
+            }
+        }
+        (fraction * log_prefactor.exp()).min(1.0)
+    }
+}
+
+//lanczos approximation (g = 7), precise to ~15 digits for a > 0
+fn ln_gamma(a: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [0.9999999999998099, 676.5203681218851, -1259.1392167224028, 771.3234287776531, -176.6150291621406,
+                                    12.507343278686905, -0.13857109526572012, 9.984369578019572e-6, 1.5056327351493116e-7];
+    let a = a - 1.0;
+    let t = a + 7.5;
+    let sum = COEFFICIENTS[1..].iter().enumerate().fold(COEFFICIENTS[0], |sum, (i, coefficient)| sum + coefficient / (a + i as f64 + 1.0));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (a + 0.5) * t.ln() - t + sum.ln()
+}
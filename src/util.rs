@@ -2,23 +2,61 @@ use std::{fmt, io};
 use std::ops::{Index, IndexMut};
 use std::path::Path;
 
-use image::{DynamicImage, ImageError, ImageResult, RgbImage, Rgb};
-use image::error::{ParameterError, ParameterErrorKind};
-use ndarray::{Array3};
+use image::{DynamicImage, ImageBuffer, ImageError, ImageResult, RgbImage, Rgb, RgbaImage, Rgba, GrayImage, Luma};
+use image::error::{ImageFormatHint, ParameterError, ParameterErrorKind, UnsupportedError, UnsupportedErrorKind};
+
+use crate::integral_image::{IntegralRgbImage, IntegralVarianceImage};
 
 pub fn get_length_in_bits(message: &[u8]) -> usize {
     message.len()*8
 }
 
-pub trait EncodingContainer:Index<usize, Output = u8>+IndexMut<usize> {
+/// The type of a single value in a container (i.e. one channel of one pixel), u8 for regular and u16 for 16 bit images.
+pub trait Sample: Copy + Default + Ord + fmt::Debug + Send + Sync {
+    const MAX: Self;
+    /// The number of bits a difference of MAX can hold
+    const MAX_NUM_BITS: u8;
+    fn to_u32(self) -> u32;
+    /// Note: value has to be <= MAX
+    fn from_u32(value: u32) -> Self;
+    fn distance(self, other: Self) -> Self {
+        if self > other { Self::from_u32(self.to_u32() - other.to_u32()) } else { Self::from_u32(other.to_u32() - self.to_u32()) }
+    }
+}
+impl Sample for u8 {
+    const MAX: u8 = u8::MAX;
+    const MAX_NUM_BITS: u8 = 8;
+    fn to_u32(self) -> u32 { self as u32 }
+    fn from_u32(value: u32) -> u8 { value as u8 }
+}
+impl Sample for u16 {
+    const MAX: u16 = u16::MAX;
+    const MAX_NUM_BITS: u8 = 16;
+    fn to_u32(self) -> u32 { self as u32 }
+    fn from_u32(value: u32) -> u16 { value as u16 }
+}
+/// u32 samples are limited to 24 bits (i.e. 24 bit pcm audio), larger values are not supported by the code tables.
+impl Sample for u32 {
+    const MAX: u32 = (1 << 24) - 1;
+    const MAX_NUM_BITS: u8 = 24;
+    fn to_u32(self) -> u32 { self }
+    fn from_u32(value: u32) -> u32 { value }
+}
+
+/// Containers are Sync, so that the indices can be read from several threads (see decode).
+/// Note: implementations that are not Sync (e.g. that use a Cell or Rc internally) cannot be containers.
+pub trait EncodingContainer<T: Sample = u8>:Index<usize, Output = T>+IndexMut<usize>+Sync {
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
-impl EncodingContainer for Vec<u8> {
+impl<T: Sample> EncodingContainer<T> for Vec<T> {
     fn len(&self) -> usize {
         self.len()
     }
 }
-impl EncodingContainer for [u8] {
+impl<T: Sample> EncodingContainer<T> for [T] {
     fn len(&self) -> usize {
         self.len()
     }
@@ -51,40 +89,48 @@ impl DifCodeImage {
         self.save_to(Path::new(path))
     }
     pub fn save_to(&self, path: &Path) -> ImageResult<()> {
-        let ext = path.extension()
-            .and_then(|s| s.to_str())
-            .map_or("".to_string(), |s| s.to_ascii_lowercase());
-        if &*ext == "jpg" || &*ext == "jpeg" {
-            Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic("Encoding as JPEG currently not supported (require lossless)".to_string()))))
-            // //require quality == 100, i.e. lossless compression
-            // let fout = &mut BufWriter::new(File::create(path)?);
-            // self.0.write_to(fout, image::ImageOutputFormat::Jpeg(255))
-        } else {
-            self.0.save(path)
-        }
+        require_lossless_format(path)?;
+        self.0.save(path)
     }
+    /// Fails if the image has an alpha channel, instead of dropping it (see DifCodeRgbaImage).
     pub fn open(path: &str) -> Result<DifCodeImage, ImageError> {
-        let image = image::open(path)?;
-        Ok(DifCodeImage::from(image.into_rgb8()))
+        DifCodeImage::from_dynamic(image::open(path)?)
     }
     pub fn from_memory(buffer: &[u8]) -> Result<DifCodeImage, ImageError> {
-        Ok(DifCodeImage::from(image::load_from_memory(buffer)?.into_rgb8()))
+        DifCodeImage::from_dynamic(image::load_from_memory(buffer)?)
+    }
+    pub fn from_dynamic(image: DynamicImage) -> Result<DifCodeImage, ImageError> {
+        Ok(DifCodeImage::from(reject_alpha(image)?.into_rgb8()))
     }
 
     pub fn generate_integral_image_for_rgb(&self) -> IntegralRgbImage {
-        let mut integral_image = IntegralRgbImage::zeros(self.width(), self.height());
-        for x in 0..self.width() {
-            for y in 0..self.height() {
-                for z in 0..3 {
-                    let real_v = self.get_rgorb(x, y, z);
-                    let i_ymm = integral_image.get_ymm_or_0(x, y, z);
-                    let i_xmm = integral_image.get_xmm_or_0(x, y, z);
-                    let i_xmm_ymm = integral_image.get_xmm_and_ymm_or_0(x, y, z);
-                    integral_image[[x, y, z]] = real_v as u32 + i_xmm + i_ymm - i_xmm_ymm;
-                }
-            }
-        }
-        integral_image
+        IntegralRgbImage::generate(self.width(), self.height(), |x, y, z| self.get_rgorb(x, y, z))
+    }
+    pub fn generate_integral_variance_image_for_rgb(&self) -> IntegralVarianceImage {
+        IntegralVarianceImage::generate(self.width(), self.height(), |x, y, z| self.get_rgorb(x, y, z))
+    }
+}
+
+//converting an image with an alpha channel to a container without one would silently drop it
+fn reject_alpha(image: DynamicImage) -> ImageResult<DynamicImage> {
+    if image.color().has_alpha() {
+        return Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(ImageFormatHint::Unknown,
+            UnsupportedErrorKind::GenericFeature("alpha channel (open the image as DifCodeRgbaImage to keep it)".to_string()))))
+    }
+    Ok(image)
+}
+
+fn require_lossless_format(path: &Path) -> ImageResult<()> {
+    let ext = path.extension()
+        .and_then(|s| s.to_str())
+        .map_or("".to_string(), |s| s.to_ascii_lowercase());
+    if &*ext == "jpg" || &*ext == "jpeg" {
+        Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic("Encoding as JPEG currently not supported (require lossless)".to_string()))))
+        // //require quality == 100, i.e. lossless compression
+        // let fout = &mut BufWriter::new(File::create(path)?);
+        // self.0.write_to(fout, image::ImageOutputFormat::Jpeg(255))
+    } else {
+        Ok(())
     }
 }
 
@@ -133,143 +179,399 @@ impl EncodingContainer for DifCodeImage {
 }
 
 
+/// Like DifCodeImage, but keeps the alpha channel (which is addressed as the 4th plane, after r, g and b).
+/// Whether alpha is changed at all is decided by the allowed changes map (see the rgba map creators).
+pub struct DifCodeRgbaImage(RgbaImage);
+impl DifCodeRgbaImage {
+    pub const ALPHA_CHANNEL: u32 = 3;
 
-pub type DifCodeResult<T> = Result<T, DifCodeError>;
-#[derive(Debug)]
-pub enum DifCodeError {
-    Internal(&'static str),
-    InternalMismatchedContainerSizes,
-    /// Contains the number of BITS(!) successfully fit, before capacity was reached
-    InternalCapacityReached(usize),
-    IO(io::Error),
-    IMG(ImageError)
+    pub fn raw(&self) -> &RgbaImage { &self.0 }
+    pub fn from(orig: RgbaImage) -> DifCodeRgbaImage { DifCodeRgbaImage(orig) }
+    pub fn width(&self) -> u32  { self.0.width()  }
+    pub fn height(&self) -> u32 { self.0.height() }
+    pub fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> { *self.0.get_pixel(x, y) }
+    pub fn get_channel(&self, x: u32, y: u32, z: u32) -> u8 { self.0.get_pixel(x, y).0[z as usize] }
+    pub fn get_alpha(&self, x: u32, y: u32) -> u8 { self.get_channel(x, y, DifCodeRgbaImage::ALPHA_CHANNEL) }
+    pub fn index_to_xyz(&self, index: usize) -> (u32, u32, u32) {
+        DifCodeImage::index_to_xyz_with_wh(index, self.width(), self.height())
+    }
+    pub fn is_alpha_index(&self, index: usize) -> bool {
+        self.index_to_xyz(index).2 == DifCodeRgbaImage::ALPHA_CHANNEL
+    }
+    /// Whether any pixel is not fully opaque (otherwise an rgb container would lose nothing).
+    pub fn has_transparency(&self) -> bool {
+        self.0.pixels().any(|p| p.0[DifCodeRgbaImage::ALPHA_CHANNEL as usize] != 255)
+    }
+    /// The smallest and largest alpha value in the square around x, y.
+    pub fn alpha_range_in_radius(&self, x: u32, y: u32, radius: u32) -> (u8, u8) {
+        let x_min = x.sub_min(0, radius);
+        let y_min = y.sub_min(0, radius);
+        let x_max = (x + radius).min(self.width() - 1);
+        let y_max = (y + radius).min(self.height() - 1);
+
+        let mut range = (255u8, 0u8);
+        for xi in x_min..=x_max {
+            for yi in y_min..=y_max {
+                let alpha = self.get_alpha(xi, yi);
+                range = (range.0.min(alpha), range.1.max(alpha));
+            }
+        }
+        range
+    }
+
+    pub fn with_capacity(width: u32, height: u32) -> DifCodeRgbaImage {
+        DifCodeRgbaImage(DynamicImage::new_rgba8(width, height).into_rgba8())
+    }
+    pub fn save(&self, path: &str) -> ImageResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> ImageResult<()> {
+        require_lossless_format(path)?;
+        self.0.save(path)
+    }
+    pub fn open(path: &str) -> Result<DifCodeRgbaImage, ImageError> {
+        let image = image::open(path)?;
+        Ok(DifCodeRgbaImage::from(image.into_rgba8()))
+    }
+    pub fn from_memory(buffer: &[u8]) -> Result<DifCodeRgbaImage, ImageError> {
+        Ok(DifCodeRgbaImage::from(image::load_from_memory(buffer)?.into_rgba8()))
+    }
+
+    /// Integral image of the colour channels only (alpha is not averaged).
+    pub fn generate_integral_image_for_rgb(&self) -> IntegralRgbImage {
+        IntegralRgbImage::generate(self.width(), self.height(), |x, y, z| self.get_channel(x, y, z))
+    }
+    /// Of the colour channels only, like generate_integral_image_for_rgb.
+    pub fn generate_integral_variance_image_for_rgb(&self) -> IntegralVarianceImage {
+        IntegralVarianceImage::generate(self.width(), self.height(), |x, y, z| self.get_channel(x, y, z))
+    }
 }
-impl From<&'static str> for DifCodeError {
-    fn from(err: &'static str) -> DifCodeError {
-        DifCodeError::Internal(err)
+
+impl fmt::Debug for DifCodeRgbaImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DifCodeRgbaImage({}, {})", self.width(), self.height())
     }
 }
-impl From<io::Error> for DifCodeError {
-    fn from(err: io::Error) -> DifCodeError {
-        DifCodeError::IO(err)
+impl fmt::Display for DifCodeRgbaImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RGBA Image({}, {})", self.width(), self.height())
     }
 }
-impl From<ImageError> for DifCodeError {
-    fn from(err: ImageError) -> DifCodeError {
-        DifCodeError::IMG(err)
+impl PartialEq<DifCodeRgbaImage> for DifCodeRgbaImage {
+    fn eq(&self, other: &DifCodeRgbaImage) -> bool {
+        self.0.dimensions() == other.0.dimensions() && self.0.as_raw() == other.0.as_raw()
+    }
+}
+impl IndexMut<usize> for DifCodeRgbaImage {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        let (x, y, z) = self.index_to_xyz(index);
+        &mut self.0.get_pixel_mut(x, y).0[z as usize]
+    }
+}
+impl Index<usize> for DifCodeRgbaImage {
+    type Output = u8;
+    fn index(&self, index: usize) -> &u8 {
+        let (x, y, z) = self.index_to_xyz(index);
+        &self.0.get_pixel(x, y).0[z as usize]
+    }
+}
+impl EncodingContainer for DifCodeRgbaImage {
+    fn len(&self) -> usize {
+        (self.0.width() * self.0.height() * 4) as usize
     }
 }
 
 
-trait SubWithMin where Self: Sized {
-    fn sub_min(self, min: Self, other: Self) -> Self;
+
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+/// Like DifCodeImage, but with 16 bits per channel (for example 16 bit png or tiff), which gives far more room for imperceptible differences.
+pub struct DifCodeImage16(Rgb16Image);
+impl DifCodeImage16 {
+    pub fn raw(&self) -> &Rgb16Image { &self.0 }
+    pub fn from(orig: Rgb16Image) -> DifCodeImage16 { DifCodeImage16(orig) }
+    pub fn width(&self) -> u32  { self.0.width()  }
+    pub fn height(&self) -> u32 { self.0.height() }
+    pub fn get_pixel(&self, x: u32, y: u32) -> Rgb<u16> { *self.0.get_pixel(x, y) }
+    pub fn get_rgorb(&self, x: u32, y: u32, z: u32) -> u16 { self.0.get_pixel(x, y).0[z as usize] }
+    pub fn index_to_xyz(&self, index: usize) -> (u32, u32, u32) {
+        DifCodeImage::index_to_xyz_with_wh(index, self.width(), self.height())
+    }
+
+    pub fn with_capacity(width: u32, height: u32) -> DifCodeImage16 {
+        DifCodeImage16(DynamicImage::new_rgb16(width, height).into_rgb16())
+    }
+    pub fn save(&self, path: &str) -> ImageResult<()> {
+        self.save_to(Path::new(path))
+    }
+    /// Note: the format has to support 16 bits per channel (png, tiff), otherwise the changes are lost
+    pub fn save_to(&self, path: &Path) -> ImageResult<()> {
+        require_lossless_format(path)?;
+        self.0.save(path)
+    }
+    /// Fails if the image has an alpha channel, instead of dropping it (see DifCodeRgbaImage).
+    pub fn open(path: &str) -> Result<DifCodeImage16, ImageError> {
+        DifCodeImage16::from_dynamic(image::open(path)?)
+    }
+    pub fn from_memory(buffer: &[u8]) -> Result<DifCodeImage16, ImageError> {
+        DifCodeImage16::from_dynamic(image::load_from_memory(buffer)?)
+    }
+    pub fn from_dynamic(image: DynamicImage) -> Result<DifCodeImage16, ImageError> {
+        Ok(DifCodeImage16::from(reject_alpha(image)?.into_rgb16()))
+    }
+
+    pub fn generate_integral_image_for_rgb(&self) -> IntegralRgbImage<u16> {
+        IntegralRgbImage::generate(self.width(), self.height(), |x, y, z| self.get_rgorb(x, y, z))
+    }
+    pub fn generate_integral_variance_image_for_rgb(&self) -> IntegralVarianceImage<u16> {
+        IntegralVarianceImage::generate(self.width(), self.height(), |x, y, z| self.get_rgorb(x, y, z))
+    }
 }
-impl SubWithMin for u32 {
-    fn sub_min(self, min: Self, other: Self) -> Self {
-        if self >= other {
-            self - other
-        } else {
-            min
-        }
+
+impl fmt::Debug for DifCodeImage16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DifCodeImage16({}, {})", self.width(), self.height())
+    }
+}
+impl fmt::Display for DifCodeImage16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "16 bit Image({}, {})", self.width(), self.height())
+    }
+}
+impl PartialEq<DifCodeImage16> for DifCodeImage16 {
+    fn eq(&self, other: &DifCodeImage16) -> bool {
+        self.0.dimensions() == other.0.dimensions() && self.0.as_raw() == other.0.as_raw()
+    }
+}
+impl IndexMut<usize> for DifCodeImage16 {
+    fn index_mut(&mut self, index: usize) -> &mut u16 {
+        let (x, y, z) = self.index_to_xyz(index);
+        &mut self.0.get_pixel_mut(x, y).0[z as usize]
+    }
+}
+impl Index<usize> for DifCodeImage16 {
+    type Output = u16;
+    fn index(&self, index: usize) -> &u16 {
+        let (x, y, z) = self.index_to_xyz(index);
+        &self.0.get_pixel(x, y).0[z as usize]
+    }
+}
+impl EncodingContainer<u16> for DifCodeImage16 {
+    fn len(&self) -> usize {
+        (self.0.width() * self.0.height() * 3) as usize
     }
 }
 
 
-pub struct IntegralRgbImage(Array3<u32>);
-impl IntegralRgbImage {
-    pub fn zeros(w: u32, h: u32) -> IntegralRgbImage {
-        let x = Array3::<u32>::zeros([w as usize, h as usize, 3]);
-        IntegralRgbImage(x)
+
+/// Single channel container, so grayscale images stay grayscale (promoting them to rgb would let the three channels diverge).
+pub struct DifCodeLumaImage(GrayImage);
+impl DifCodeLumaImage {
+    pub fn raw(&self) -> &GrayImage { &self.0 }
+    pub fn from(orig: GrayImage) -> DifCodeLumaImage { DifCodeLumaImage(orig) }
+    pub fn width(&self) -> u32  { self.0.width()  }
+    pub fn height(&self) -> u32 { self.0.height() }
+    pub fn get_luma(&self, x: u32, y: u32) -> u8 { self.0.get_pixel(x, y).0[0] }
+    pub fn index_to_xy(&self, index: usize) -> (u32, u32) {
+        let (x, y, _) = DifCodeImage::index_to_xyz_with_wh(index, self.width(), self.height());
+        (x, y)
     }
-    pub fn average_in_radius(&self, x: u32, y: u32, z: u32, radius: u32) -> u8 {
-        let x_min = x.sub_min(0, radius);
-        let y_min = y.sub_min(0, radius);
-        let x_max = (x + radius).min(self.width() - 1);
-        let y_max = (y + radius).min(self.height() - 1);
 
-        let total_pixels_considered = (x_max - x_min) * (y_max - y_min);
+    pub fn with_capacity(width: u32, height: u32) -> DifCodeLumaImage {
+        DifCodeLumaImage(GrayImage::new(width, height))
+    }
+    pub fn save(&self, path: &str) -> ImageResult<()> {
+        self.save_to(Path::new(path))
+    }
+    pub fn save_to(&self, path: &Path) -> ImageResult<()> {
+        require_lossless_format(path)?;
+        self.0.save(path)
+    }
+    /// Fails if the image has an alpha channel, instead of dropping it (see DifCodeRgbaImage).
+    pub fn open(path: &str) -> Result<DifCodeLumaImage, ImageError> {
+        DifCodeLumaImage::from_dynamic(image::open(path)?)
+    }
+    pub fn from_memory(buffer: &[u8]) -> Result<DifCodeLumaImage, ImageError> {
+        DifCodeLumaImage::from_dynamic(image::load_from_memory(buffer)?)
+    }
+    pub fn from_dynamic(image: DynamicImage) -> Result<DifCodeLumaImage, ImageError> {
+        Ok(DifCodeLumaImage::from(reject_alpha(image)?.into_luma8()))
+    }
 
-        (self.calculate_area_sum(x_min, y_min, x_max, y_max, z)
-             / total_pixels_considered) as u8
+    /// The luma value is stored in all three channels of the integral image.
+    pub fn generate_integral_image(&self) -> IntegralRgbImage {
+        IntegralRgbImage::generate(self.width(), self.height(), |x, y, _| self.get_luma(x, y))
     }
+    /// The luma value is stored in all three channels, like in generate_integral_image.
+    pub fn generate_integral_variance_image(&self) -> IntegralVarianceImage {
+        IntegralVarianceImage::generate(self.width(), self.height(), |x, y, _| self.get_luma(x, y))
+    }
+}
 
-    pub fn get_at_p(&self, p: (u32, u32, u32)) -> u32 {
-        self.get_at(p.0, p.1, p.2)
+impl fmt::Debug for DifCodeLumaImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DifCodeLumaImage({}, {})", self.width(), self.height())
     }
-    pub fn get_at(&self, x: u32, y: u32, z: u32) -> u32 {
-        self[[x, y, z]]
+}
+impl fmt::Display for DifCodeLumaImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Grayscale Image({}, {})", self.width(), self.height())
     }
-    pub fn width(&self) -> u32 {
-        self.0.shape()[0] as u32
+}
+impl PartialEq<DifCodeLumaImage> for DifCodeLumaImage {
+    fn eq(&self, other: &DifCodeLumaImage) -> bool {
+        self.0.dimensions() == other.0.dimensions() && self.0.as_raw() == other.0.as_raw()
     }
-    pub fn height(&self) -> u32 {
-        self.0.shape()[1] as u32
+}
+impl IndexMut<usize> for DifCodeLumaImage {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        let (x, y) = self.index_to_xy(index);
+        &mut self.0.get_pixel_mut(x, y).0[0]
     }
-
-    pub fn calculate_area_sum_at_ps(&self, p1: (u32, u32, u32), p2: (u32, u32, u32)) -> u32 {
-        if p1.2 != p2.2 {
-            panic!("z is unequal in the points");
-        }
-        self.calculate_area_sum(p1.0, p1.1, p2.0, p2.1, p1.2)
+}
+impl Index<usize> for DifCodeLumaImage {
+    type Output = u8;
+    fn index(&self, index: usize) -> &u8 {
+        let (x, y) = self.index_to_xy(index);
+        &self.0.get_pixel(x, y).0[0]
     }
-    pub fn calculate_area_sum(&self, p1_x: u32, p1_y: u32, p2_x: u32, p2_y: u32, z: u32) -> u32 {
-        if p1_x > p2_x || p1_y > p2_y {
-            panic!("p1 > p2");
-        }
+}
+impl EncodingContainer for DifCodeLumaImage {
+    fn len(&self) -> usize {
+        (self.0.width() * self.0.height()) as usize
+    }
+}
 
-        let (a_x, a_y) = (p1_x, p1_y);
-        let (d_x, d_y) = (p2_x, p2_y);
-        let (b_x, b_y) = (d_x, a_y);
-        let (c_x, c_y) = (a_x, d_y);
 
-        self.get_at(d_x, d_y, z) +
-            self.get_at(a_x, a_y, z) - self.get_at(b_x, b_y, z) - self.get_at(c_x, c_y, z)
+
+pub type Luma16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+/// Grayscale with 16 bits per pixel, see DifCodeLumaImage and DifCodeImage16.
+pub struct DifCodeLumaImage16(Luma16Image);
+impl DifCodeLumaImage16 {
+    pub fn raw(&self) -> &Luma16Image { &self.0 }
+    pub fn from(orig: Luma16Image) -> DifCodeLumaImage16 { DifCodeLumaImage16(orig) }
+    pub fn width(&self) -> u32  { self.0.width()  }
+    pub fn height(&self) -> u32 { self.0.height() }
+    pub fn get_luma(&self, x: u32, y: u32) -> u16 { self.0.get_pixel(x, y).0[0] }
+    pub fn index_to_xy(&self, index: usize) -> (u32, u32) {
+        let (x, y, _) = DifCodeImage::index_to_xyz_with_wh(index, self.width(), self.height());
+        (x, y)
     }
 
+    pub fn with_capacity(width: u32, height: u32) -> DifCodeLumaImage16 {
+        DifCodeLumaImage16(Luma16Image::new(width, height))
+    }
+    pub fn save(&self, path: &str) -> ImageResult<()> {
+        self.save_to(Path::new(path))
+    }
+    /// Note: the format has to support 16 bits per channel (png, tiff), otherwise the changes are lost
+    pub fn save_to(&self, path: &Path) -> ImageResult<()> {
+        require_lossless_format(path)?;
+        self.0.save(path)
+    }
+    /// Fails if the image has an alpha channel, instead of dropping it (see DifCodeRgbaImage).
+    pub fn open(path: &str) -> Result<DifCodeLumaImage16, ImageError> {
+        DifCodeLumaImage16::from_dynamic(image::open(path)?)
+    }
+    pub fn from_memory(buffer: &[u8]) -> Result<DifCodeLumaImage16, ImageError> {
+        DifCodeLumaImage16::from_dynamic(image::load_from_memory(buffer)?)
+    }
+    pub fn from_dynamic(image: DynamicImage) -> Result<DifCodeLumaImage16, ImageError> {
+        Ok(DifCodeLumaImage16::from(reject_alpha(image)?.into_luma16()))
+    }
 
+    /// The luma value is stored in all three channels, like in DifCodeLumaImage::generate_integral_image.
+    pub fn generate_integral_image(&self) -> IntegralRgbImage<u16> {
+        IntegralRgbImage::generate(self.width(), self.height(), |x, y, _| self.get_luma(x, y))
+    }
+    pub fn generate_integral_variance_image(&self) -> IntegralVarianceImage<u16> {
+        IntegralVarianceImage::generate(self.width(), self.height(), |x, y, _| self.get_luma(x, y))
+    }
+}
 
-    pub fn get_ymm_or_0(&self, x: u32, y: u32, z: u32) -> u32 {
-        if y > 0 {
-            self[[x, y - 1, z]]
-        } else {
-            0
-        }
+impl fmt::Debug for DifCodeLumaImage16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DifCodeLumaImage16({}, {})", self.width(), self.height())
     }
-    pub fn get_xmm_or_0(&self, x: u32, y: u32, z: u32) -> u32 {
-        if x > 0 {
-            self[[x - 1, y, z]]
-        } else {
-            0
-        }
+}
+impl fmt::Display for DifCodeLumaImage16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "16 bit Grayscale Image({}, {})", self.width(), self.height())
     }
-    pub fn get_xmm_and_ymm_or_0(&self, x: u32, y: u32, z: u32) -> u32 {
-        if x > 0 && y > 0 {
-            self[[x - 1, y - 1, z]]
-        } else {
-            0
-        }
+}
+impl PartialEq<DifCodeLumaImage16> for DifCodeLumaImage16 {
+    fn eq(&self, other: &DifCodeLumaImage16) -> bool {
+        self.0.dimensions() == other.0.dimensions() && self.0.as_raw() == other.0.as_raw()
+    }
+}
+impl IndexMut<usize> for DifCodeLumaImage16 {
+    fn index_mut(&mut self, index: usize) -> &mut u16 {
+        let (x, y) = self.index_to_xy(index);
+        &mut self.0.get_pixel_mut(x, y).0[0]
+    }
+}
+impl Index<usize> for DifCodeLumaImage16 {
+    type Output = u16;
+    fn index(&self, index: usize) -> &u16 {
+        let (x, y) = self.index_to_xy(index);
+        &self.0.get_pixel(x, y).0[0]
     }
 }
+impl EncodingContainer<u16> for DifCodeLumaImage16 {
+    fn len(&self) -> usize {
+        (self.0.width() * self.0.height()) as usize
+    }
+}
+
 
-impl Index<[usize; 3]> for IntegralRgbImage {
-    type Output = u32;
-    fn index(&self, s: [usize; 3]) -> &u32 {
-        self.0.index(s)
+pub type DifCodeResult<T> = Result<T, DifCodeError>;
+#[derive(Debug)]
+pub enum DifCodeError {
+    Internal(&'static str),
+    InternalMismatchedContainerSizes,
+    /// Contains the number of BITS(!) successfully fit, before capacity was reached
+    InternalCapacityReached(usize),
+    /// The decoded bits do not start with a valid envelope header (i.e. wrong images or no message)
+    InvalidHeader(&'static str),
+    UnsupportedFormatVersion(u8),
+    /// The envelope was found, but its content does not match the checksum (i.e. the encoded container was altered)
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The block (index) of the error corrected stream has more altered bytes than its parity symbols can repair
+    UncorrectableErrors { block: usize },
+    /// The file is not a jpeg supported by DifCodeJpeg (i.e. progressive or corrupted)
+    InvalidJpeg(&'static str),
+    /// The file is not a gif or animated png supported by DifCodeGif/DifCodeApng (i.e. 16 bit, not animated or corrupted)
+    InvalidAnimation(&'static str),
+    /// The file is not a wav supported by DifCodeWav (i.e. not pcm, a different bit depth than the container or corrupted)
+    InvalidAudio(&'static str),
+    IO(io::Error),
+    IMG(ImageError)
+}
+impl From<&'static str> for DifCodeError {
+    fn from(err: &'static str) -> DifCodeError {
+        DifCodeError::Internal(err)
     }
 }
-impl IndexMut<[usize; 3]> for IntegralRgbImage {
-    fn index_mut(&mut self, s: [usize; 3]) -> &mut u32 {
-        self.0.index_mut(s)
+impl From<io::Error> for DifCodeError {
+    fn from(err: io::Error) -> DifCodeError {
+        DifCodeError::IO(err)
     }
 }
-impl Index<[u32; 3]> for IntegralRgbImage {
-    type Output = u32;
-    fn index(&self, s: [u32; 3]) -> &u32 {
-        self.0.index([s[0] as usize, s[1] as usize, s[2] as usize])
+impl From<ImageError> for DifCodeError {
+    fn from(err: ImageError) -> DifCodeError {
+        DifCodeError::IMG(err)
     }
 }
-impl IndexMut<[u32; 3]> for IntegralRgbImage {
-    fn index_mut(&mut self, s: [u32; 3]) -> &mut u32 {
-        self.0.index_mut([s[0] as usize, s[1] as usize, s[2] as usize])
+
+
+trait SubWithMin where Self: Sized {
+    fn sub_min(self, min: Self, other: Self) -> Self;
+}
+impl SubWithMin for u32 {
+    fn sub_min(self, min: Self, other: Self) -> Self {
+        if self >= other {
+            self - other
+        } else {
+            min
+        }
     }
-}
\ No newline at end of file
+}